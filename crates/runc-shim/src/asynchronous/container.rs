@@ -21,7 +21,7 @@ use containerd_shim::{
     error::Result,
     protos::{
         api::{CreateTaskRequest, ExecProcessRequest, ProcessInfo, StateResponse},
-        cgroups::metrics::Metrics,
+        protobuf::well_known_types::any::Any,
     },
     Error,
 };
@@ -51,7 +51,7 @@ pub trait Container {
     async fn pid(&self) -> i32;
     async fn id(&self) -> String;
     async fn update(&mut self, resources: &LinuxResources) -> Result<()>;
-    async fn stats(&self) -> Result<Metrics>;
+    async fn stats(&self) -> Result<Any>;
     async fn all_processes(&self) -> Result<Vec<ProcessInfo>>;
     async fn close_io(&mut self, exec_id: Option<&str>) -> Result<()>;
 }
@@ -175,12 +175,12 @@ where
     }
 
     #[cfg(target_os = "linux")]
-    async fn stats(&self) -> Result<Metrics> {
+    async fn stats(&self) -> Result<Any> {
         self.init.stats().await
     }
 
     #[cfg(not(target_os = "linux"))]
-    async fn stats(&self) -> Result<Metrics> {
+    async fn stats(&self) -> Result<Any> {
         Err(Error::Unimplemented("stats".to_string()))
     }
 
@@ -41,7 +41,6 @@ use crate::{
     common::{create_runc, has_shared_pid_namespace, ShimExecutor, GROUP_LABELS},
 };
 
-mod console;
 mod container;
 mod processes;
 mod runc;
@@ -105,9 +104,15 @@ impl Shim for Service {
             Some(Arc::new(ShimExecutor::default())),
         )?;
 
-        runc.delete(&self.id, Some(&DeleteOpts { force: true }))
-            .await
-            .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e));
+        runc.delete(
+            &self.id,
+            Some(&DeleteOpts {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e));
         let mut resp = DeleteResponse::new();
         // sigkill
         resp.set_exit_status(137);
@@ -24,8 +24,7 @@ use containerd_shim::{
     ioctl_set_winsz,
     protos::{
         api::{ProcessInfo, StateResponse, Status},
-        cgroups::metrics::Metrics,
-        protobuf::well_known_types::timestamp::Timestamp,
+        protobuf::well_known_types::{any::Any, timestamp::Timestamp},
     },
     util::asyncify,
     Console, Result,
@@ -52,7 +51,7 @@ pub trait Process {
     async fn exited_at(&self) -> Option<OffsetDateTime>;
     async fn resize_pty(&mut self, height: u32, width: u32) -> Result<()>;
     async fn update(&mut self, resources: &LinuxResources) -> Result<()>;
-    async fn stats(&self) -> Result<Metrics>;
+    async fn stats(&self) -> Result<Any>;
     async fn ps(&self) -> Result<Vec<ProcessInfo>>;
     async fn close_io(&mut self) -> Result<()>;
 }
@@ -63,7 +62,7 @@ pub trait ProcessLifecycle<P: Process> {
     async fn kill(&self, p: &mut P, signal: u32, all: bool) -> Result<()>;
     async fn delete(&self, p: &mut P) -> Result<()>;
     async fn update(&self, p: &mut P, resources: &LinuxResources) -> Result<()>;
-    async fn stats(&self, p: &P) -> Result<Metrics>;
+    async fn stats(&self, p: &P) -> Result<Any>;
     async fn ps(&self, p: &P) -> Result<Vec<ProcessInfo>>;
 }
 
@@ -183,7 +182,7 @@ where
         self.lifecycle.clone().update(self, resources).await
     }
 
-    async fn stats(&self) -> Result<Metrics> {
+    async fn stats(&self) -> Result<Any> {
         self.lifecycle.stats(self).await
     }
 
@@ -16,48 +16,47 @@
 
 use std::{
     convert::TryFrom,
-    os::unix::{
-        io::{AsRawFd, FromRawFd, RawFd},
-        prelude::ExitStatusExt,
-    },
+    os::unix::{io::AsRawFd, prelude::ExitStatusExt},
     path::{Path, PathBuf},
     process::ExitStatus,
     sync::{Arc, Mutex},
 };
 
 use async_trait::async_trait;
+#[cfg(target_os = "linux")]
+use containerd_shim::cgroup::collect_cgroup_metrics;
 use containerd_shim::{
     api::{CreateTaskRequest, ExecProcessRequest, Options, Status},
-    asynchronous::monitor::{monitor_subscribe, monitor_unsubscribe, Subscription},
+    asynchronous::monitor::{
+        monitor_kill_unreaped, monitor_subscribe, monitor_unsubscribe, Subscription,
+    },
     io_error,
     monitor::{ExitEvent, Subject, Topic},
     other, other_error,
     protos::{
         api::ProcessInfo,
-        cgroups::metrics::Metrics,
-        protobuf::{CodedInputStream, Message},
+        protobuf::{well_known_types::any::Any, CodedInputStream, Message},
     },
-    util::{asyncify, mkdir, mount_rootfs, read_file_to_str, write_options, write_runtime},
+    util::{mkdir, mount_rootfs, read_file_to_str, write_options, write_runtime},
     Console, Error, ExitSignal, Result,
 };
 use log::{debug, error};
 use nix::{sys::signal::kill, unistd::Pid};
 use oci_spec::runtime::{LinuxResources, Process};
-use runc::{Command, Runc, Spawner};
+use runc::{console::ConsoleSocket, Command, Runc, Spawner};
 use tokio::{
     fs::{remove_file, File, OpenOptions},
-    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
 };
 
 use super::{
-    console::ConsoleSocket,
     container::{ContainerFactory, ContainerTemplate, ProcessFactory},
     processes::{ProcessLifecycle, ProcessTemplate},
 };
 use crate::{
     common::{
-        check_kill_error, create_io, create_runc, get_spec_from_request, receive_socket,
-        CreateConfig, Log, ProcessIO, ShimExecutor, INIT_PID_FILE, LOG_JSON_FILE,
+        check_runc_kill_error, create_io, create_runc, get_spec_from_request, CreateConfig,
+        ProcessIO, ShimExecutor, StatsSource, INIT_PID_FILE, LOG_JSON_FILE,
     },
     io::Stdio,
 };
@@ -155,8 +154,9 @@ impl RuncFactory {
             .no_new_keyring(opts.no_new_keyring)
             .detach(false);
         let (socket, pio) = if stdio.terminal {
-            let s = ConsoleSocket::new().await?;
-            create_opts.console_socket = Some(s.path.to_owned());
+            let s =
+                ConsoleSocket::new().map_err(other_error!(e, "failed to create console socket"))?;
+            create_opts.console_socket = Some(s.path().to_owned());
             (Some(s), None)
         } else {
             let pio = create_io(&id, opts.io_uid, opts.io_gid, stdio)?;
@@ -184,28 +184,10 @@ impl RuncFactory {
 
 // runtime_error will read the OCI runtime logfile retrieving OCI runtime error
 pub async fn runtime_error(bundle: &str, e: runc::error::Error, msg: &str) -> Error {
-    let mut rt_msg = String::new();
-    match File::open(Path::new(bundle).join(LOG_JSON_FILE)).await {
-        Err(err) => other!("{}: unable to open OCI runtime log file){}", msg, err),
-        Ok(file) => {
-            let mut lines = BufReader::new(file).lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                // Retrieve the last runtime error
-                match serde_json::from_str::<Log>(&line) {
-                    Err(err) => return other!("{}: unable to parse log msg: {}", msg, err),
-                    Ok(log) => {
-                        if log.level == "error" {
-                            rt_msg = log.msg.trim().to_string();
-                        }
-                    }
-                }
-            }
-            if !rt_msg.is_empty() {
-                other!("{}: {}", msg, rt_msg)
-            } else {
-                other!("{}: (no OCI runtime error in logfile) {}", msg, e)
-            }
-        }
+    match runc::log::last_error(Path::new(bundle).join(LOG_JSON_FILE)).await {
+        Err(err) => other!("{}: unable to read OCI runtime log file: {}", msg, err),
+        Ok(Some(entry)) => other!("{}: {}", msg, entry.msg.trim()),
+        Ok(None) => other!("{}: (no OCI runtime error in logfile) {}", msg, e),
     }
 }
 
@@ -253,6 +235,7 @@ pub struct RuncInitLifecycle {
     opts: Options,
     bundle: String,
     exit_signal: Arc<ExitSignal>,
+    stats_source: StatsSource,
 }
 
 #[async_trait]
@@ -275,10 +258,13 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
             .kill(
                 p.id.as_str(),
                 signal,
-                Some(&runc::options::KillOpts { all }),
+                Some(&runc::options::KillOpts {
+                    all,
+                    ..Default::default()
+                }),
             )
             .await
-            .map_err(|e| check_kill_error(e.to_string()))
+            .map_err(check_runc_kill_error)
     }
 
     async fn delete(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
@@ -286,11 +272,14 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
             .runtime
             .delete(
                 p.id.as_str(),
-                Some(&runc::options::DeleteOpts { force: true }),
+                Some(&runc::options::DeleteOpts {
+                    force: true,
+                    ..Default::default()
+                }),
             )
             .await
         {
-            if !e.to_string().to_lowercase().contains("does not exist") {
+            if !e.is_not_found() {
                 return Err(
                     runtime_error(&p.lifecycle.bundle, e, "OCI runtime delete failed").await,
                 );
@@ -317,18 +306,36 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
     }
 
     #[cfg(target_os = "linux")]
-    async fn stats(&self, p: &InitProcess) -> Result<Metrics> {
+    async fn stats(&self, p: &InitProcess) -> Result<Any> {
         if p.pid <= 0 {
             return Err(other!(
                 "failed to collect metrics because init process is {}",
                 p.pid
             ));
         }
-        containerd_shim::cgroup::collect_metrics(p.pid as u32)
+        let runc_stats = || async {
+            self.runtime
+                .stats(&p.id)
+                .await
+                .map(crate::common::runc_stats_metrics)
+                .map_err(other_error!(e, "failed to get stats from runc"))
+        };
+        let metrics = match self.stats_source {
+            StatsSource::Cgroup => collect_cgroup_metrics(p.pid as u32)?,
+            StatsSource::Runc => runc_stats().await?,
+            StatsSource::Auto => match collect_cgroup_metrics(p.pid as u32) {
+                Ok(metrics) => metrics,
+                Err(e) => {
+                    debug!("failed to read cgroup metrics, falling back to runc: {}", e);
+                    runc_stats().await?
+                }
+            },
+        };
+        metrics.to_any()
     }
 
     #[cfg(not(target_os = "linux"))]
-    async fn stats(&self, _p: &InitProcess) -> Result<Metrics> {
+    async fn stats(&self, _p: &InitProcess) -> Result<Any> {
         Err(Error::Unimplemented("process stats".to_string()))
     }
 
@@ -360,6 +367,7 @@ impl RuncInitLifecycle {
             opts,
             bundle: bundle.to_string(),
             exit_signal: Default::default(),
+            stats_source: StatsSource::from_env(),
         }
     }
 }
@@ -384,10 +392,12 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
             pid_file: Some(pid_path.to_owned()),
             console_socket: None,
             detach: true,
+            ..Default::default()
         };
         let (socket, pio) = if p.stdio.terminal {
-            let s = ConsoleSocket::new().await?;
-            exec_opts.console_socket = Some(s.path.to_owned());
+            let s =
+                ConsoleSocket::new().map_err(other_error!(e, "failed to create console socket"))?;
+            exec_opts.console_socket = Some(s.path().to_owned());
             (Some(s), None)
         } else {
             let pio = create_io(&p.id, self.io_uid, self.io_gid, &p.stdio)?;
@@ -463,7 +473,7 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
         Err(Error::Unimplemented("exec update".to_string()))
     }
 
-    async fn stats(&self, _p: &ExecProcess) -> Result<Metrics> {
+    async fn stats(&self, _p: &ExecProcess) -> Result<Any> {
         Err(Error::Unimplemented("exec stats".to_string()))
     }
 
@@ -478,15 +488,19 @@ async fn copy_console(
     exit_signal: Arc<ExitSignal>,
 ) -> Result<Console> {
     debug!("copy_console: waiting for runtime to send console fd");
-    let stream = console_socket.accept().await?;
-    let fd = asyncify(move || -> Result<RawFd> { receive_socket(stream.as_raw_fd()) }).await?;
-    let f = unsafe { File::from_raw_fd(fd) };
+    let console = console_socket
+        .receive()
+        .await
+        .map_err(other_error!(e, "failed to receive console master"))?;
+    debug!(
+        "copy_console: console socket get fd: {}",
+        console.as_raw_fd()
+    );
+    let (console_stdout, console_stdin) = console
+        .split()
+        .map_err(other_error!(e, "failed to split console"))?;
     if !stdio.stdin.is_empty() {
         debug!("copy_console: pipe stdin to console");
-        let console_stdin = f
-            .try_clone()
-            .await
-            .map_err(io_error!(e, "failed to clone console file"))?;
         let stdin = OpenOptions::new()
             .read(true)
             .open(stdio.stdin.as_str())
@@ -496,10 +510,6 @@ async fn copy_console(
     }
 
     if !stdio.stdout.is_empty() {
-        let console_stdout = f
-            .try_clone()
-            .await
-            .map_err(io_error!(e, "failed to clone console file"))?;
         debug!("copy_console: pipe stdout from console");
         let stdout = OpenOptions::new()
             .write(true)
@@ -522,10 +532,9 @@ async fn copy_console(
             }),
         );
     }
-    let console = Console {
-        file: f.into_std().await,
-    };
-    Ok(console)
+    Ok(Console {
+        file: console.into_file(),
+    })
 }
 
 pub async fn copy_io(pio: &ProcessIO, stdio: &Stdio, exit_signal: Arc<ExitSignal>) -> Result<()> {
@@ -533,74 +542,108 @@ pub async fn copy_io(pio: &ProcessIO, stdio: &Stdio, exit_signal: Arc<ExitSignal
         return Ok(());
     };
     if let Some(io) = &pio.io {
-        if let Some(w) = io.stdin() {
+        if !stdio.stdin.is_empty() {
             debug!("copy_io: pipe stdin from {}", stdio.stdin.as_str());
-            if !stdio.stdin.is_empty() {
-                let stdin = OpenOptions::new()
-                    .read(true)
-                    .open(stdio.stdin.as_str())
-                    .await
-                    .map_err(io_error!(e, "open stdin"))?;
+            if let Some(w) = io.stdin_pipe() {
+                let stdin = open_fifo(stdio.stdin.as_str(), true, "open stdin").await?;
+                spawn_splice(stdin.into_std().await, w, exit_signal.clone(), None::<fn()>);
+            } else if let Some(w) = io.stdin() {
+                let stdin = open_fifo(stdio.stdin.as_str(), true, "open stdin").await?;
                 spawn_copy(stdin, w, exit_signal.clone(), None::<fn()>);
             }
         }
 
-        if let Some(r) = io.stdout() {
-            debug!("copy_io: pipe stdout from to {}", stdio.stdout.as_str());
-            if !stdio.stdout.is_empty() {
-                let stdout = OpenOptions::new()
-                    .write(true)
-                    .open(stdio.stdout.as_str())
-                    .await
-                    .map_err(io_error!(e, "open stdout"))?;
-                // open a read to make sure even if the read end of containerd shutdown,
-                // copy still continue until the restart of containerd succeed
-                let stdout_r = OpenOptions::new()
-                    .read(true)
-                    .open(stdio.stdout.as_str())
-                    .await
-                    .map_err(io_error!(e, "open stdout for read"))?;
-                spawn_copy(
-                    r,
-                    stdout,
-                    exit_signal.clone(),
-                    Some(move || {
-                        drop(stdout_r);
-                    }),
-                );
-            }
-        }
-
-        if let Some(r) = io.stderr() {
-            if !stdio.stderr.is_empty() {
-                debug!("copy_io: pipe stderr from to {}", stdio.stderr.as_str());
-                let stderr = OpenOptions::new()
-                    .write(true)
-                    .open(stdio.stderr.as_str())
-                    .await
-                    .map_err(io_error!(e, "open stderr"))?;
-                // open a read to make sure even if the read end of containerd shutdown,
-                // copy still continue until the restart of containerd succeed
-                let stderr_r = OpenOptions::new()
-                    .read(true)
-                    .open(stdio.stderr.as_str())
-                    .await
-                    .map_err(io_error!(e, "open stderr for read"))?;
-                spawn_copy(
-                    r,
-                    stderr,
-                    exit_signal,
-                    Some(move || {
-                        drop(stderr_r);
-                    }),
-                );
-            }
-        }
+        copy_output(
+            "stdout",
+            stdio.stdout.as_str(),
+            io.stdout_pipe(),
+            || io.stdout(),
+            exit_signal.clone(),
+        )
+        .await?;
+        copy_output(
+            "stderr",
+            stdio.stderr.as_str(),
+            io.stderr_pipe(),
+            || io.stderr(),
+            exit_signal,
+        )
+        .await?;
     }
 
     Ok(())
 }
 
+/// Forward an output of the process to the fifo at `path`, with splice(2) if `pipe` is given.
+async fn copy_output<R>(
+    name: &str,
+    path: &str,
+    pipe: Option<std::fs::File>,
+    reader: R,
+    exit_signal: Arc<ExitSignal>,
+) -> Result<()>
+where
+    R: FnOnce() -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>>,
+{
+    if path.is_empty() {
+        return Ok(());
+    }
+    if let Some(pipe) = pipe {
+        let (w, on_close) = open_output(name, path).await?;
+        spawn_splice(pipe, w.into_std().await, exit_signal, Some(on_close));
+    } else if let Some(reader) = reader() {
+        let (w, on_close) = open_output(name, path).await?;
+        spawn_copy(reader, w, exit_signal, Some(on_close));
+    }
+    Ok(())
+}
+
+/// Open the output fifo at `path` for write, returning it with a callback to run once the copy
+/// is done.
+async fn open_output(name: &str, path: &str) -> Result<(File, impl FnOnce() + Send + 'static)> {
+    debug!("copy_io: pipe {} to {}", name, path);
+    let w = open_fifo(path, false, &format!("open {}", name)).await?;
+    // open a read to make sure even if the read end of containerd shutdown,
+    // copy still continue until the restart of containerd succeed
+    let r = open_fifo(path, true, &format!("open {} for read", name)).await?;
+    Ok((w, move || drop(r)))
+}
+
+async fn open_fifo(path: &str, read: bool, msg: &str) -> Result<File> {
+    OpenOptions::new()
+        .read(read)
+        .write(!read)
+        .open(path)
+        .await
+        .map_err(io_error!(e, "{}", msg))
+}
+
+/// Forward `from` to `to` with splice(2), see [runc::splice::splice_copy].
+fn spawn_splice<F>(
+    from: std::fs::File,
+    to: std::fs::File,
+    exit_signal: Arc<ExitSignal>,
+    on_close: Option<F>,
+) where
+    F: FnOnce() + Send + 'static,
+{
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = exit_signal.wait() => {
+                debug!("container exit, copy task should exit too");
+            },
+            res = runc::splice::splice_copy(&from, &to) => {
+               if let Err(e) = res {
+                    error!("copy io failed {}", e);
+                }
+            }
+        }
+        if let Some(f) = on_close {
+            f();
+        }
+    });
+}
+
 fn spawn_copy<R, W, F>(from: R, to: W, exit_signal: Arc<ExitSignal>, on_close: Option<F>)
 where
     R: AsyncRead + Send + Unpin + 'static,
@@ -635,7 +678,7 @@ async fn copy_io_or_console<P>(
     if p.stdio.terminal {
         if let Some(console_socket) = socket {
             let console_result = copy_console(&console_socket, &p.stdio, exit_signal).await;
-            console_socket.clean().await;
+            drop(console_socket);
             match console_result {
                 Ok(c) => {
                     p.console = Some(c);
@@ -658,26 +701,60 @@ impl Spawner for ShimExecutor {
         let subscription = monitor_subscribe(Topic::Pid)
             .await
             .map_err(|e| runc::error::Error::Other(Box::new(e)))?;
-        let sid = subscription.id;
         let child = match cmd.spawn() {
             Ok(c) => c,
             Err(e) => {
-                monitor_unsubscribe(sid).await.unwrap_or_default();
+                monitor_unsubscribe(subscription.id)
+                    .await
+                    .unwrap_or_default();
                 return Err(runc::error::Error::ProcessSpawnFailed(e));
             }
         };
         let pid = child.id().unwrap();
-        let (stdout, stderr, exit_code) = tokio::join!(
-            read_std(child.stdout),
-            read_std(child.stderr),
-            wait_pid(pid as i32, subscription)
-        );
+        // kill runc if the caller gives up on it, e.g. on timeout
+        let mut runc = RuncChild {
+            pid: pid as i32,
+            subscription: Some(subscription),
+            exited: false,
+        };
+        let (stdout, stderr, exit_code) =
+            tokio::join!(read_std(child.stdout), read_std(child.stderr), runc.wait());
         let status = ExitStatus::from_raw(exit_code);
-        monitor_unsubscribe(sid).await.unwrap_or_default();
         Ok((status, pid, stdout, stderr))
     }
 }
 
+/// A runc process reaped by the shim, killed when dropped before it exits, even if the
+/// execution is abandoned halfway.
+struct RuncChild {
+    pid: i32,
+    subscription: Option<Subscription>,
+    exited: bool,
+}
+
+impl RuncChild {
+    async fn wait(&mut self) -> i32 {
+        let s = self.subscription.as_mut().unwrap();
+        let code = wait_pid(self.pid, s).await;
+        self.exited = true;
+        code
+    }
+}
+
+impl Drop for RuncChild {
+    fn drop(&mut self) {
+        if let Some(mut s) = self.subscription.take() {
+            let (pid, exited) = (self.pid, self.exited);
+            tokio::spawn(async move {
+                if !exited {
+                    monitor_kill_unreaped(pid, &mut s).await;
+                }
+                monitor_unsubscribe(s.id).await.unwrap_or_default();
+            });
+        }
+    }
+}
+
 async fn read_std<T>(std: Option<T>) -> String
 where
     T: AsyncRead + Unpin,
@@ -694,8 +771,7 @@ where
     "".to_string()
 }
 
-async fn wait_pid(pid: i32, s: Subscription) -> i32 {
-    let mut s = s;
+async fn wait_pid(pid: i32, s: &mut Subscription) -> i32 {
     loop {
         if let Some(ExitEvent {
             subject: Subject::Pid(epid),
@@ -703,7 +779,6 @@ async fn wait_pid(pid: i32, s: Subscription) -> i32 {
         }) = s.rx.recv().await
         {
             if pid == epid {
-                monitor_unsubscribe(s.id).await.unwrap_or_default();
                 return code;
             }
         }
@@ -715,18 +790,15 @@ mod tests {
     use std::{os::unix::process::ExitStatusExt, path::Path, process::ExitStatus};
 
     use containerd_shim::util::{mkdir, write_str_to_file};
-    use runc::error::Error::CommandFailed;
+    use runc::error::Error;
     use tokio::fs::remove_dir_all;
 
     use crate::{asynchronous::runc::runtime_error, common::LOG_JSON_FILE};
 
     #[tokio::test]
     async fn test_runtime_error() {
-        let empty_err = CommandFailed {
-            status: ExitStatus::from_raw(1),
-            stdout: "".to_string(),
-            stderr: "".to_string(),
-        };
+        let empty_err =
+            Error::command_failed(ExitStatus::from_raw(1), "".to_string(), "".to_string());
         let log_json = "\
         {\"level\":\"info\",\"msg\":\"hello world\",\"time\":\"2022-11-25\"}\n\
         {\"level\":\"error\",\"msg\":\"failed error\",\"time\":\"2022-11-26\"}\n\
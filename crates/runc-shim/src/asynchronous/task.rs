@@ -36,7 +36,7 @@ use containerd_shim::{
         ttrpc,
         ttrpc::r#async::TtrpcContext,
     },
-    util::{convert_to_any, convert_to_timestamp, AsOption},
+    util::{convert_to_timestamp, AsOption},
     TtrpcResult,
 };
 use log::{debug, info, warn};
@@ -330,7 +330,7 @@ where
         let stats = container.stats().await?;
 
         let mut resp = StatsResponse::new();
-        resp.set_stats(convert_to_any(Box::new(stats))?);
+        resp.set_stats(stats);
         Ok(resp)
     }
 
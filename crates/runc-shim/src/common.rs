@@ -14,8 +14,10 @@
    limitations under the License.
 */
 
-use std::{env, io::IoSliceMut, ops::Deref, os::unix::io::RawFd, path::Path, sync::Arc};
+use std::{env, path::Path, str::FromStr, sync::Arc};
 
+#[cfg(target_os = "linux")]
+use containerd_shim::cgroup::{is_cgroup2_unified_mode, CgroupMetrics};
 use containerd_shim::{
     api::{ExecProcessRequest, Options},
     io_error, other, other_error,
@@ -23,20 +25,13 @@ use containerd_shim::{
     Error,
 };
 use log::{debug, warn};
-use nix::{
-    cmsg_space,
-    sys::{
-        socket::{recvmsg, ControlMessageOwned, MsgFlags, UnixAddr},
-        termios::tcgetattr,
-    },
-};
 use oci_spec::runtime::{LinuxNamespaceType, Spec};
 use runc::{
+    error::FailureKind,
     io::{Io, NullIo, FIFO},
     options::GlobalOpts,
     Runc, Spawner,
 };
-use serde::Deserialize;
 
 use super::io::Stdio;
 
@@ -46,11 +41,52 @@ pub const GROUP_LABELS: [&str; 2] = [
 ];
 pub const INIT_PID_FILE: &str = "init.pid";
 pub const LOG_JSON_FILE: &str = "log.json";
+/// Environment variable selecting the [StatsSource], one of "cgroup", "runc" or "auto".
+///
+/// The runc options of containerd have no field for it, so it is set in the environment of the
+/// shim.
+pub const STATS_SOURCE_ENV: &str = "RUNC_SHIM_STATS_SOURCE";
+
+/// Where the shim collects the metrics of a container from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsSource {
+    /// Read the cgroups of the init process directly.
+    Cgroup,
+    /// Run `runc events --stats`, which also works when the shim can't read the cgroups, such as
+    /// for rootless or systemd-managed containers.
+    Runc,
+    /// Read the cgroups, falling back to runc if the shim can't read them.
+    Auto,
+}
+
+impl StatsSource {
+    /// The source set by [STATS_SOURCE_ENV], warning about an unknown value and using
+    /// [StatsSource::Auto] for it.
+    pub fn from_env() -> Self {
+        match env::var(STATS_SOURCE_ENV) {
+            Ok(value) => value.parse().unwrap_or_else(|e| {
+                warn!("{}, using auto", e);
+                StatsSource::Auto
+            }),
+            Err(_) => StatsSource::Auto,
+        }
+    }
+}
 
-#[derive(Deserialize)]
-pub struct Log {
-    pub level: String,
-    pub msg: String,
+impl FromStr for StatsSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cgroup" => Ok(StatsSource::Cgroup),
+            "runc" => Ok(StatsSource::Runc),
+            "auto" => Ok(StatsSource::Auto),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown {} {:?}, expected cgroup, runc or auto",
+                STATS_SOURCE_ENV, s
+            ))),
+        }
+    }
 }
 
 pub struct ProcessIO {
@@ -128,17 +164,14 @@ pub fn get_spec_from_request(
     }
 }
 
-pub fn check_kill_error(emsg: String) -> Error {
-    let emsg = emsg.to_lowercase();
-    if emsg.contains("process already finished")
-        || emsg.contains("container not running")
-        || emsg.contains("no such process")
-    {
-        Error::NotFoundError("process already finished".to_string())
-    } else if emsg.contains("does not exist") {
-        Error::NotFoundError("no such container".to_string())
-    } else {
-        other!("unknown error after kill {}", emsg)
+/// Map a failed `runc kill` to a shim error, based on how the failure was classified.
+pub fn check_runc_kill_error(e: runc::error::Error) -> Error {
+    match e.failure_kind() {
+        Some(FailureKind::NotRunning) | Some(FailureKind::AlreadyStopped) => {
+            Error::NotFoundError("process already finished".to_string())
+        }
+        Some(FailureKind::NotFound) => Error::NotFoundError("no such container".to_string()),
+        _ => other!("unknown error after kill {}", e),
     }
 }
 
@@ -183,38 +216,14 @@ pub fn create_runc(
 #[derive(Default)]
 pub(crate) struct CreateConfig {}
 
-pub fn receive_socket(stream_fd: RawFd) -> containerd_shim::Result<RawFd> {
-    let mut buf = [0u8; 4096];
-    let mut iovec = [IoSliceMut::new(&mut buf)];
-    let mut space = cmsg_space!([RawFd; 2]);
-    let (path, fds) =
-        match recvmsg::<UnixAddr>(stream_fd, &mut iovec, Some(&mut space), MsgFlags::empty()) {
-            Ok(msg) => {
-                let mut iter = msg.cmsgs();
-                if let Some(ControlMessageOwned::ScmRights(fds)) = iter.next() {
-                    (iovec[0].deref(), fds)
-                } else {
-                    return Err(other!("received message is empty"));
-                }
-            }
-            Err(e) => {
-                return Err(other!("failed to receive message: {}", e));
-            }
-        };
-    if fds.is_empty() {
-        return Err(other!("received message is empty"));
+/// The stats of `runc events --stats` as the metrics message of the cgroup version of the host.
+#[cfg(target_os = "linux")]
+pub(crate) fn runc_stats_metrics(stats: runc::events::Stats) -> CgroupMetrics {
+    if is_cgroup2_unified_mode() {
+        CgroupMetrics::V2(stats.into())
+    } else {
+        CgroupMetrics::V1(stats.into())
     }
-    let path = String::from_utf8(Vec::from(path)).unwrap_or_else(|e| {
-        warn!("failed to get path from array {}", e);
-        "".to_string()
-    });
-    let path = path.trim_matches(char::from(0));
-    debug!(
-        "copy_console: console socket get path: {}, fd: {}",
-        path, &fds[0]
-    );
-    tcgetattr(fds[0])?;
-    Ok(fds[0])
 }
 
 pub fn has_shared_pid_namespace(spec: &Spec) -> bool {
@@ -234,9 +243,70 @@ pub fn has_shared_pid_namespace(spec: &Spec) -> bool {
     }
 }
 
-/// Returns a temp dir. If the environment variable "XDG_RUNTIME_DIR" is set, return its value.
-/// Otherwise if `std::env::temp_dir()` failed, return current dir or return the temp dir depended on OS.
-pub(crate) fn xdg_runtime_dir() -> String {
-    env::var("XDG_RUNTIME_DIR")
-        .unwrap_or_else(|_| env::temp_dir().to_str().unwrap_or(".").to_string())
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use std::fs;
+
+    use containerd_shim::{cgroup::collect_metrics_v2, protos::cgroups::v2::metrics as v2};
+
+    use super::StatsSource;
+
+    #[test]
+    fn test_stats_source_from_str() {
+        assert_eq!(
+            "cgroup".parse::<StatsSource>().unwrap(),
+            StatsSource::Cgroup
+        );
+        assert_eq!("runc".parse::<StatsSource>().unwrap(), StatsSource::Runc);
+        assert_eq!("auto".parse::<StatsSource>().unwrap(), StatsSource::Auto);
+        assert!("cgroups".parse::<StatsSource>().is_err());
+        assert!("".parse::<StatsSource>().is_err());
+    }
+
+    /// The cgroup files and runc stats of the same unlimited cgroup v2 report the same limits.
+    #[test]
+    fn test_unlimited_metrics_v2() {
+        let dir = std::env::temp_dir().join(format!("shim-metrics-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, value) in [
+            ("pids.current", "3\n"),
+            ("pids.max", "max\n"),
+            ("memory.current", "2048\n"),
+            ("memory.max", "max\n"),
+            ("memory.swap.current", "0\n"),
+            ("memory.swap.max", "max\n"),
+            ("hugetlb.2MB.current", "0\n"),
+            ("hugetlb.2MB.max", "max\n"),
+        ] {
+            fs::write(dir.join(name), value).unwrap();
+        }
+        let from_cgroup = collect_metrics_v2(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let from_cgroup = from_cgroup.unwrap();
+
+        // runc reports unlimited values as u64::MAX, and adds memory to swap
+        let stats: runc::events::Stats = serde_json::from_str(
+            r#"{
+                "cpu": {},
+                "memory": {
+                    "usage": {"limit": 18446744073709551615, "usage": 2048, "failcnt": 0},
+                    "swap": {"limit": 18446744073709551615, "usage": 2048, "failcnt": 0}
+                },
+                "pids": {"current": 3, "limit": 18446744073709551615},
+                "blkio": {},
+                "hugetlb": {"2MB": {"usage": 0, "max": 18446744073709551615, "failcnt": 0}}
+            }"#,
+        )
+        .unwrap();
+        let from_runc = v2::Metrics::from(stats);
+
+        assert_eq!(from_cgroup.pids, from_runc.pids);
+        assert_eq!(from_cgroup.memory.usage, from_runc.memory.usage);
+        assert_eq!(from_cgroup.memory.usage_limit, from_runc.memory.usage_limit);
+        assert_eq!(from_cgroup.memory.swap_usage, from_runc.memory.swap_usage);
+        assert_eq!(from_cgroup.memory.swap_limit, from_runc.memory.swap_limit);
+        assert_eq!(from_cgroup.hugetlb, from_runc.hugetlb);
+        assert_eq!(from_runc.memory.usage_limit, u64::MAX);
+    }
 }
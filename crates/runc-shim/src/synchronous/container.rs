@@ -18,7 +18,7 @@ use std::{
     collections::HashMap,
     convert::TryFrom,
     fs::{File, OpenOptions},
-    os::unix::io::{AsRawFd, FromRawFd},
+    os::unix::io::AsRawFd,
     path::Path,
     sync::{
         mpsc::{sync_channel, Receiver, SyncSender},
@@ -29,22 +29,18 @@ use std::{
 use containerd_shim as shim;
 use log::debug;
 use oci_spec::runtime::LinuxResources;
+use runc::console::ConsoleSocket;
 use shim::{
     api::*,
     error::{Error, Result},
     io_error, ioctl_set_winsz, other, other_error,
-    protos::cgroups::metrics::Metrics,
+    protos::protobuf::well_known_types::any::Any,
     util::{convert_to_timestamp, read_pid_from_file},
     Console,
 };
 use time::OffsetDateTime;
 
-use super::console::ConsoleSocket;
-use crate::{
-    common::{receive_socket, ProcessIO},
-    io::Stdio,
-    synchronous::io::spawn_copy_for_tty,
-};
+use crate::{common::ProcessIO, io::Stdio, synchronous::io::spawn_copy_for_tty};
 
 pub trait ContainerFactory<C> {
     fn create(&self, ns: &str, req: &CreateTaskRequest) -> Result<C>;
@@ -81,7 +77,7 @@ pub trait Container {
     fn exec(&mut self, req: ExecProcessRequest) -> Result<()>;
     fn resize_pty(&mut self, exec_id: Option<&str>, height: u32, width: u32) -> Result<()>;
     fn pid(&self) -> i32;
-    fn stats(&self) -> Result<Metrics>;
+    fn stats(&self) -> Result<Any>;
     fn update(&mut self, resources: &LinuxResources) -> Result<()>;
     fn pids(&self) -> Result<PidsResponse>;
     fn id(&self) -> String;
@@ -256,29 +252,28 @@ impl Process for CommonProcess {
 
     fn copy_console(&self, console_socket: &ConsoleSocket) -> Result<Console> {
         debug!("copy_console: waiting for runtime to send console fd");
-        let stream = console_socket
-            .accept()
-            .map_err(io_error!(e, "accept console socket"))?;
-        let fd = receive_socket(stream.as_raw_fd())?;
-        let tty = unsafe { File::from_raw_fd(fd) };
+        let console = console_socket
+            .receive()
+            .map_err(other_error!(e, "failed to receive console master"))?;
+        debug!(
+            "copy_console: console socket get fd: {}",
+            console.as_raw_fd()
+        );
+        let (reader, writer) = console
+            .split()
+            .map_err(other_error!(e, "failed to split console"))?;
 
         if !self.stdio.stdin.is_empty() {
             debug!("copy_console: pipe stdin to console");
-            let f = tty
-                .try_clone()
-                .map_err(io_error!(e, "failed to clone tty"))?;
             let stdin = OpenOptions::new()
                 .read(true)
                 .open(self.stdio.stdin.as_str())
                 .map_err(io_error!(e, "open stdin"))?;
-            spawn_copy_for_tty(stdin, f, None, None);
+            spawn_copy_for_tty(stdin, writer, None, None);
         }
 
         if !self.stdio.stdout.is_empty() {
             debug!("copy_console: pipe stdout from console");
-            let f = tty
-                .try_clone()
-                .map_err(io_error!(e, "failed to clone tty"))?;
             let stdout = OpenOptions::new()
                 .write(true)
                 .open(self.stdio.stdout.as_str())
@@ -290,7 +285,7 @@ impl Process for CommonProcess {
                 .open(self.stdio.stdout.as_str())
                 .map_err(io_error!(e, "open stdout for read"))?;
             spawn_copy_for_tty(
-                f,
+                reader,
                 stdout,
                 None,
                 Some(Box::new(move || {
@@ -298,8 +293,9 @@ impl Process for CommonProcess {
                 })),
             );
         }
-        let console = Console { file: tty };
-        Ok(console)
+        Ok(Console {
+            file: console.into_file(),
+        })
     }
 
     fn copy_io(&self) -> Result<()> {
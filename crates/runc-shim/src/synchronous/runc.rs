@@ -17,8 +17,8 @@
 
 use std::{
     convert::TryFrom,
-    fs::{remove_file, File, OpenOptions},
-    io::{BufRead, BufReader, Read},
+    fs::{remove_file, OpenOptions},
+    io::Read,
     os::unix::prelude::ExitStatusExt,
     path::{Path, PathBuf},
     process::ExitStatus,
@@ -29,13 +29,16 @@ use std::{
 };
 
 use containerd_shim as shim;
+#[cfg(target_os = "linux")]
+use containerd_shim::cgroup::collect_cgroup_metrics;
 use log::{debug, error};
 use nix::{
+    errno::Errno,
     sys::{signal::kill, stat::Mode},
     unistd::{mkdir, Pid},
 };
 use oci_spec::runtime::LinuxResources;
-use runc::{Command, Spawner};
+use runc::{console::ConsoleSocket, Command, Spawner};
 use shim::{
     api::*,
     error::{Error, Result},
@@ -44,8 +47,7 @@ use shim::{
     other, other_error,
     protos::{
         api::ProcessInfo,
-        cgroups::metrics::Metrics,
-        protobuf::{CodedInputStream, Message},
+        protobuf::{well_known_types::any::Any, CodedInputStream, Message},
         shim::oci::ProcessDetails,
     },
     util::{convert_to_any, read_spec_from_file, write_options, write_runtime, IntoOption},
@@ -53,12 +55,11 @@ use shim::{
 };
 use time::OffsetDateTime;
 
-use super::console::ConsoleSocket;
 use crate::{
     common,
     common::{
-        create_io, has_shared_pid_namespace, CreateConfig, Log, ShimExecutor, INIT_PID_FILE,
-        LOG_JSON_FILE,
+        create_io, has_shared_pid_namespace, CreateConfig, ShimExecutor, StatsSource,
+        INIT_PID_FILE, LOG_JSON_FILE,
     },
     io::Stdio,
     synchronous::container::{
@@ -174,11 +175,13 @@ impl Container for RuncContainer {
                     pid_file: Some(pid_path.to_owned()),
                     console_socket: None,
                     detach: true,
+                    ..Default::default()
                 };
                 let terminal = process.common.stdio.terminal;
                 let socket = if terminal {
-                    let s = ConsoleSocket::new()?;
-                    exec_opts.console_socket = Some(s.path.to_owned());
+                    let s = ConsoleSocket::new()
+                        .map_err(other_error!(e, "failed to create console socket"))?;
+                    exec_opts.console_socket = Some(s.path().to_owned());
                     Some(s)
                 } else {
                     let io = create_io(
@@ -253,7 +256,6 @@ impl Container for RuncContainer {
             Some(_) => {
                 let p = self.common.get_mut_process(exec_id)?;
                 kill_process(p.pid() as u32, p.exited_at(), signal)
-                    .map_err(|e| common::check_kill_error(format!("{}", e)))
             }
             None => self
                 .common
@@ -264,7 +266,7 @@ impl Container for RuncContainer {
                     signal,
                     Some(&runc::options::KillOpts { all }),
                 )
-                .map_err(|e| common::check_kill_error(format!("{}", e))),
+                .map_err(common::check_runc_kill_error),
         }
     }
 
@@ -296,7 +298,7 @@ impl Container for RuncContainer {
                         Some(&runc::options::DeleteOpts { force: true }),
                     )
                     .or_else(|e| {
-                        if !e.to_string().to_lowercase().contains("does not exist") {
+                        if !e.is_not_found() {
                             Err(runtime_error(
                                 &self.common.bundle,
                                 e,
@@ -328,13 +330,29 @@ impl Container for RuncContainer {
     }
 
     #[cfg(target_os = "linux")]
-    fn stats(&self) -> Result<Metrics> {
+    fn stats(&self) -> Result<Any> {
         let pid = self.common.init.pid() as u32;
-        containerd_shim::cgroup::collect_metrics(pid)
+        let runc_stats = || {
+            self.common
+                .init
+                .runtime
+                .stats(&self.common.id)
+                .map(common::runc_stats_metrics)
+                .map_err(other_error!(e, "failed to get stats from runc"))
+        };
+        let metrics = match self.common.init.stats_source {
+            StatsSource::Cgroup => collect_cgroup_metrics(pid)?,
+            StatsSource::Runc => runc_stats()?,
+            StatsSource::Auto => collect_cgroup_metrics(pid).or_else(|e| {
+                debug!("failed to read cgroup metrics, falling back to runc: {}", e);
+                runc_stats()
+            })?,
+        };
+        metrics.to_any()
     }
 
     #[cfg(not(target_os = "linux"))]
-    fn stats(&self) -> Result<Metrics> {
+    fn stats(&self) -> Result<Any> {
         Err(Error::Unimplemented("stats".to_string()))
     }
 
@@ -415,7 +433,11 @@ fn kill_process(pid: u32, exit_at: Option<OffsetDateTime>, sig: u32) -> Result<(
             Pid::from_raw(pid as i32),
             nix::sys::signal::Signal::try_from(sig as i32).unwrap(),
         )
-        .map_err(Into::into)
+        .map_err(|e| match e {
+            // reaped, but the exit is not recorded yet
+            Errno::ESRCH => Error::NotFoundError("process already finished".to_string()),
+            e => e.into(),
+        })
     }
 }
 
@@ -430,6 +452,7 @@ pub(crate) struct InitProcess {
     pub(crate) no_pivot_root: bool,
     pub(crate) no_new_key_ring: bool,
     pub(crate) criu_work_path: String,
+    pub(crate) stats_source: StatsSource,
 }
 
 impl InitProcess {
@@ -456,6 +479,7 @@ impl InitProcess {
             no_pivot_root: false,
             no_new_key_ring: false,
             criu_work_path: "".to_string(),
+            stats_source: StatsSource::from_env(),
         }
     }
 
@@ -471,8 +495,9 @@ impl InitProcess {
             .no_new_keyring(self.no_new_key_ring)
             .detach(false);
         let socket = if terminal {
-            let s = ConsoleSocket::new()?;
-            create_opts.console_socket = Some(s.path.to_owned());
+            let s =
+                ConsoleSocket::new().map_err(other_error!(e, "failed to create console socket"))?;
+            create_opts.console_socket = Some(s.path().to_owned());
             Some(s)
         } else {
             let io = create_io(&id, self.io_uid, self.io_gid, &self.common.stdio)?;
@@ -719,28 +744,10 @@ where
 
 // runtime_error will read the OCI runtime logfile retrieving OCI runtime error
 pub fn runtime_error(bundle: &str, e: runc::error::Error, msg: &str) -> Error {
-    let mut rt_msg = String::new();
-    match File::open(Path::new(bundle).join(LOG_JSON_FILE)) {
-        Err(err) => other!("{}: unable to open OCI runtime log file){}", msg, err),
-        Ok(file) => {
-            let mut lines = BufReader::new(file).lines();
-            while let Some(Ok(line)) = lines.next() {
-                // Retrieve the last runtime error
-                match serde_json::from_str::<Log>(&line) {
-                    Err(err) => return other!("{}: unable to parse log msg: {}", msg, err),
-                    Ok(log) => {
-                        if log.level == "error" {
-                            rt_msg = log.msg.trim().to_string();
-                        }
-                    }
-                }
-            }
-            if !rt_msg.is_empty() {
-                other!("{}: {}", msg, rt_msg)
-            } else {
-                other!("{}: (no OCI runtime error in logfile) {}", msg, e)
-            }
-        }
+    match runc::log::last_error(Path::new(bundle).join(LOG_JSON_FILE)) {
+        Err(err) => other!("{}: unable to read OCI runtime log file: {}", msg, err),
+        Ok(Some(entry)) => other!("{}: {}", msg, entry.msg.trim()),
+        Ok(None) => other!("{}: (no OCI runtime error in logfile) {}", msg, e),
     }
 }
 
@@ -752,17 +759,14 @@ mod tests {
 
     use containerd_shim::util::{mkdir, write_str_to_path};
     use nix::sys::stat::Mode;
-    use runc::error::Error::CommandFailed;
+    use runc::error::Error;
 
     use crate::{common::LOG_JSON_FILE, synchronous::runc::runtime_error};
 
     #[test]
     fn test_runtime_error() {
-        let empty_err = CommandFailed {
-            status: ExitStatus::from_raw(1),
-            stdout: "".to_string(),
-            stderr: "".to_string(),
-        };
+        let empty_err =
+            Error::command_failed(ExitStatus::from_raw(1), "".to_string(), "".to_string());
         let log_json = "\
         {\"level\":\"info\",\"msg\":\"hello world\",\"time\":\"2022-11-25\"}\n\
         {\"level\":\"error\",\"msg\":\"failed error\",\"time\":\"2022-11-26\"}\n\
@@ -31,7 +31,7 @@ use shim::{
         events::task::{TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskStart},
         protobuf::MessageDyn,
     },
-    util::{convert_to_any, convert_to_timestamp, IntoOption},
+    util::{convert_to_timestamp, IntoOption},
     Error, ExitSignal, Task, TtrpcContext, TtrpcResult,
 };
 
@@ -338,7 +338,7 @@ where
         let stats = container.stats()?;
 
         let mut resp = StatsResponse::new();
-        resp.set_stats(convert_to_any(Box::new(stats))?);
+        resp.set_stats(stats);
         Ok(resp)
     }
 
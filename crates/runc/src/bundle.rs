@@ -0,0 +1,618 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Builder for OCI bundles that can be passed to [Runc::create](crate::Runc::create) or
+//! [Runc::run](crate::Runc::run).
+//!
+//! ```no_run
+//! use runc::bundle::{BundleBuilder, Rootfs};
+//!
+//! let bundle = BundleBuilder::new(Default::default())
+//!     .rootfs(Rootfs::Tar("/tmp/busybox.tar".into()))
+//!     .args(["sleep", "10"])
+//!     .env("FOO", "bar")
+//!     .build()
+//!     .unwrap();
+//! println!("bundle at {}", bundle.path().display());
+//! // removed on drop, unless kept
+//! let path = bundle.keep();
+//! ```
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::warn;
+use oci_spec::runtime::{
+    LinuxIdMapping, LinuxIdMappingBuilder, LinuxNamespace, LinuxNamespaceType, Mount, Spec,
+};
+
+use crate::error::Error;
+
+/// Name of the spec file in a bundle.
+pub const CONFIG_FILE: &str = "config.json";
+
+/// Name of the rootfs directory created in a bundle.
+const ROOTFS_DIR: &str = "rootfs";
+
+/// Where the root filesystem of a bundle comes from.
+#[derive(Debug, Clone)]
+pub enum Rootfs {
+    /// Copy the directory into the bundle.
+    Dir(PathBuf),
+    /// Extract the tarball into the bundle, with any compression `tar` detects.
+    Tar(PathBuf),
+    /// Use an existing directory in place, as the absolute root path of the spec.
+    Bind(PathBuf),
+}
+
+/// Run `cmd`, turning a failure into an error carrying its stderr.
+fn run(cmd: &mut Command) -> io::Result<()> {
+    let output = cmd.output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!(
+            "{:?} failed with {}: {}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    ))
+}
+
+impl Rootfs {
+    /// Populate `bundle` and return the root path to put in the spec.
+    fn install(&self, bundle: &Path) -> Result<PathBuf, Error> {
+        let target = bundle.join(ROOTFS_DIR);
+        match self {
+            Rootfs::Dir(src) => {
+                fs::create_dir_all(&target).map_err(Error::BundleExtractFailed)?;
+                let mut src = src.clone().into_os_string();
+                src.push("/.");
+                run(Command::new("cp").arg("-a").arg(src).arg(&target))
+                    .map_err(Error::BundleExtractFailed)?;
+            }
+            Rootfs::Tar(tarball) => {
+                fs::create_dir_all(&target).map_err(Error::BundleExtractFailed)?;
+                run(Command::new("tar")
+                    .arg("-xf")
+                    .arg(tarball)
+                    .arg("-C")
+                    .arg(&target))
+                .map_err(Error::BundleExtractFailed)?;
+            }
+            Rootfs::Bind(path) => return path.canonicalize().map_err(Error::InvalidPath),
+        }
+        Ok(PathBuf::from(ROOTFS_DIR))
+    }
+}
+
+/// Builder for a [Bundle], starting from a spec and applying overrides to it.
+#[derive(Debug, Clone)]
+pub struct BundleBuilder {
+    spec: Spec,
+    rootfs: Option<Rootfs>,
+    args: Option<Vec<String>>,
+    env: Vec<(String, String)>,
+    mounts: Vec<Mount>,
+    namespaces: Vec<LinuxNamespace>,
+    remove_namespaces: Vec<LinuxNamespaceType>,
+    uid_mappings: Vec<LinuxIdMapping>,
+    gid_mappings: Vec<LinuxIdMapping>,
+    remove_on_drop: bool,
+}
+
+impl BundleBuilder {
+    pub fn new(spec: Spec) -> Self {
+        Self {
+            spec,
+            rootfs: None,
+            args: None,
+            env: Vec::new(),
+            mounts: Vec::new(),
+            namespaces: Vec::new(),
+            remove_namespaces: Vec::new(),
+            uid_mappings: Vec::new(),
+            gid_mappings: Vec::new(),
+            remove_on_drop: true,
+        }
+    }
+
+    /// Start from the rootless spec of oci-spec, mapping root in the container to `uid`/`gid`.
+    pub fn rootless(uid: u32, gid: u32) -> Self {
+        Self::new(Spec::rootless(uid, gid))
+    }
+
+    /// Set the root filesystem, an empty `rootfs` directory is created if unset.
+    pub fn rootfs(mut self, rootfs: Rootfs) -> Self {
+        self.rootfs = Some(rootfs);
+        self
+    }
+
+    /// Replace the args of the container process, added with the defaults of oci-spec if the
+    /// spec has none.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = Some(args.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set an environment variable of the container process, replacing any previous value.
+    ///
+    /// As for [BundleBuilder::args], a spec without process gets the default one of oci-spec.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Add a mount, replacing any mount of the spec at the same destination.
+    pub fn mount(mut self, mount: Mount) -> Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    /// Add a namespace, replacing any namespace of the spec of the same type.
+    pub fn namespace(mut self, namespace: LinuxNamespace) -> Self {
+        self.namespaces.push(namespace);
+        self
+    }
+
+    /// Remove the namespace of type `typ` from the spec, sharing the host's one.
+    pub fn remove_namespace(mut self, typ: LinuxNamespaceType) -> Self {
+        self.remove_namespaces.push(typ);
+        self
+    }
+
+    /// Map `size` uids from `host_id` on the host to `container_id` in the container.
+    ///
+    /// Mappings replace the ones of the spec, and imply a user namespace.
+    pub fn uid_mapping(mut self, container_id: u32, host_id: u32, size: u32) -> Self {
+        self.uid_mappings
+            .push(id_mapping(container_id, host_id, size));
+        self
+    }
+
+    /// Map `size` gids from `host_id` on the host to `container_id` in the container.
+    ///
+    /// Mappings replace the ones of the spec, and imply a user namespace.
+    pub fn gid_mapping(mut self, container_id: u32, host_id: u32, size: u32) -> Self {
+        self.gid_mappings
+            .push(id_mapping(container_id, host_id, size));
+        self
+    }
+
+    /// Remove the bundle when the [Bundle] is dropped, on by default.
+    ///
+    /// Only what the builder created is removed: the contents of a dir passed to
+    /// [BundleBuilder::build_in] that already existed, and never the rootfs of [Rootfs::Bind].
+    pub fn remove_on_drop(mut self, remove: bool) -> Self {
+        self.remove_on_drop = remove;
+        self
+    }
+
+    /// Return the spec with all the overrides applied.
+    pub fn spec(&self) -> Spec {
+        let mut spec = self.spec.clone();
+
+        if self.args.is_some() || !self.env.is_empty() {
+            let process = spec.process_mut().get_or_insert_with(Default::default);
+            if let Some(args) = &self.args {
+                process.set_args(Some(args.clone()));
+            }
+            if !self.env.is_empty() {
+                let mut env = process.env().clone().unwrap_or_default();
+                for (key, value) in &self.env {
+                    let prefix = format!("{}=", key);
+                    env.retain(|e| !e.starts_with(&prefix));
+                    env.push(format!("{}{}", prefix, value));
+                }
+                process.set_env(Some(env));
+            }
+        }
+
+        if !self.mounts.is_empty() {
+            let mut mounts = spec.mounts().clone().unwrap_or_default();
+            for mount in &self.mounts {
+                mounts.retain(|m| m.destination() != mount.destination());
+                mounts.push(mount.clone());
+            }
+            spec.set_mounts(Some(mounts));
+        }
+
+        let mut namespaces = self.namespaces.clone();
+        if !self.uid_mappings.is_empty() || !self.gid_mappings.is_empty() {
+            let user_ns = spec
+                .linux()
+                .as_ref()
+                .and_then(|l| l.namespaces().as_ref())
+                .map_or(false, |n| {
+                    n.iter().any(|n| n.typ() == LinuxNamespaceType::User)
+                });
+            if !user_ns
+                && !namespaces
+                    .iter()
+                    .any(|n| n.typ() == LinuxNamespaceType::User)
+            {
+                let mut ns = LinuxNamespace::default();
+                ns.set_typ(LinuxNamespaceType::User);
+                namespaces.push(ns);
+            }
+        }
+
+        let linux = spec.linux_mut().get_or_insert_with(Default::default);
+        if !namespaces.is_empty() || !self.remove_namespaces.is_empty() {
+            let mut all = linux.namespaces().clone().unwrap_or_default();
+            all.retain(|n| {
+                !self.remove_namespaces.contains(&n.typ())
+                    && !namespaces.iter().any(|o| o.typ() == n.typ())
+            });
+            all.extend(namespaces);
+            linux.set_namespaces(Some(all));
+        }
+        if !self.uid_mappings.is_empty() {
+            linux.set_uid_mappings(Some(self.uid_mappings.clone()));
+        }
+        if !self.gid_mappings.is_empty() {
+            linux.set_gid_mappings(Some(self.gid_mappings.clone()));
+        }
+
+        spec
+    }
+
+    /// Create the bundle in a new temp dir.
+    pub fn build(self) -> Result<Bundle, Error> {
+        let dir = std::env::temp_dir().join(format!("bundle{}", uuid::Uuid::new_v4()));
+        self.build_in(dir)
+    }
+
+    /// Create the bundle in `dir`, which must be empty or not exist yet.
+    ///
+    /// If the bundle can't be created, the files created so far are removed, along with `dir`
+    /// if it did not exist.
+    pub fn build_in(self, dir: impl AsRef<Path>) -> Result<Bundle, Error> {
+        let dir = dir.as_ref();
+        let created = match fs::read_dir(dir) {
+            Ok(mut entries) => {
+                if entries.next().is_some() {
+                    return Err(Error::SpecFileCreationFailed(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("bundle dir {} is not empty", dir.display()),
+                    )));
+                }
+                false
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(dir).map_err(Error::SpecFileCreationFailed)?;
+                true
+            }
+            Err(e) => return Err(Error::SpecFileCreationFailed(e)),
+        };
+        let path = dir.canonicalize().map_err(Error::InvalidPath)?;
+        let mut bundle = self.populate(&path).map_err(|e| {
+            let cleanup = if created {
+                fs::remove_dir_all(&path)
+            } else {
+                remove_contents(&path)
+            };
+            if let Err(ce) = cleanup {
+                warn!("failed to clean up bundle {}: {}", path.display(), ce);
+            }
+            e
+        })?;
+        bundle.created = created;
+        Ok(bundle)
+    }
+
+    /// Write the rootfs and the config of the bundle in the empty dir `path`.
+    fn populate(&self, path: &Path) -> Result<Bundle, Error> {
+        let mut bundle = Bundle {
+            path: path.to_path_buf(),
+            spec: self.spec(),
+            remove: false,
+            created: false,
+        };
+
+        let root = match &self.rootfs {
+            Some(rootfs) => rootfs.install(&bundle.path)?,
+            None => {
+                fs::create_dir_all(bundle.path.join(ROOTFS_DIR))
+                    .map_err(Error::SpecFileCreationFailed)?;
+                PathBuf::from(ROOTFS_DIR)
+            }
+        };
+        bundle
+            .spec
+            .root_mut()
+            .get_or_insert_with(Default::default)
+            .set_path(root);
+
+        let config = serde_json::to_vec_pretty(&bundle.spec)?;
+        fs::write(bundle.config(), config).map_err(Error::SpecFileCreationFailed)?;
+        bundle.remove = self.remove_on_drop;
+        Ok(bundle)
+    }
+}
+
+/// Remove everything in the directory `dir`, but not `dir` itself.
+fn remove_contents(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn id_mapping(container_id: u32, host_id: u32, size: u32) -> LinuxIdMapping {
+    LinuxIdMappingBuilder::default()
+        .container_id(container_id)
+        .host_id(host_id)
+        .size(size)
+        .build()
+        .expect("all fields of the id mapping are set")
+}
+
+/// An OCI bundle on disk, removed on drop unless [Bundle::keep] was called or
+/// [BundleBuilder::remove_on_drop] was unset.
+#[derive(Debug)]
+pub struct Bundle {
+    path: PathBuf,
+    spec: Spec,
+    remove: bool,
+    /// Whether the builder created the bundle dir, which is otherwise only emptied.
+    created: bool,
+}
+
+impl Bundle {
+    /// Absolute path of the bundle directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of the `config.json` of the bundle.
+    pub fn config(&self) -> PathBuf {
+        self.path.join(CONFIG_FILE)
+    }
+
+    /// The spec written to `config.json`.
+    pub fn spec(&self) -> &Spec {
+        &self.spec
+    }
+
+    /// Keep the bundle on disk after drop, returning its path.
+    pub fn keep(mut self) -> PathBuf {
+        self.remove = false;
+        self.path.clone()
+    }
+}
+
+impl Drop for Bundle {
+    fn drop(&mut self) {
+        if !self.remove {
+            return;
+        }
+        let res = if self.created {
+            fs::remove_dir_all(&self.path)
+        } else {
+            remove_contents(&self.path)
+        };
+        if let Err(e) = res {
+            warn!("failed to remove bundle {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::MountBuilder;
+
+    use super::*;
+
+    #[test]
+    fn overrides_test() {
+        let mount = MountBuilder::default()
+            .destination("/data")
+            .typ("bind")
+            .source("/tmp")
+            .options(vec!["rbind".to_string()])
+            .build()
+            .unwrap();
+        let tmp = MountBuilder::default()
+            .destination("/dev")
+            .typ("tmpfs")
+            .build()
+            .unwrap();
+        let builder = BundleBuilder::new(Spec::default())
+            .args(["sleep", "10"])
+            .env("PATH", "/bin")
+            .env("FOO", "bar")
+            .mount(mount.clone())
+            .mount(tmp.clone())
+            .remove_namespace(LinuxNamespaceType::Network)
+            .uid_mapping(0, 1000, 1)
+            .gid_mapping(0, 1000, 1);
+        let spec = builder.spec();
+
+        let process = spec.process().as_ref().unwrap();
+        assert_eq!(process.args().as_ref().unwrap(), &["sleep", "10"]);
+        let env = process.env().as_ref().unwrap();
+        assert!(env.contains(&"FOO=bar".to_string()));
+        assert_eq!(env.iter().filter(|e| e.starts_with("PATH=")).count(), 1);
+        assert!(env.contains(&"PATH=/bin".to_string()));
+
+        let mounts = spec.mounts().as_ref().unwrap();
+        assert!(mounts.contains(&mount));
+        assert_eq!(
+            mounts
+                .iter()
+                .filter(|m| m.destination() == Path::new("/dev"))
+                .collect::<Vec<_>>(),
+            vec![&tmp]
+        );
+
+        let linux = spec.linux().as_ref().unwrap();
+        let types: Vec<_> = linux
+            .namespaces()
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|n| n.typ())
+            .collect();
+        assert!(!types.contains(&LinuxNamespaceType::Network));
+        assert!(types.contains(&LinuxNamespaceType::User));
+        assert_eq!(linux.uid_mappings().as_ref().unwrap()[0].host_id(), 1000);
+        assert_eq!(linux.gid_mappings().as_ref().unwrap()[0].size(), 1);
+    }
+
+    #[test]
+    fn overrides_without_process_test() {
+        let mut spec = Spec::default();
+        spec.set_process(None);
+        let spec = BundleBuilder::new(spec)
+            .args(["sleep", "10"])
+            .env("FOO", "bar")
+            .spec();
+        let process = spec.process().as_ref().unwrap();
+        assert_eq!(process.args().as_ref().unwrap(), &["sleep", "10"]);
+        assert!(process
+            .env()
+            .as_ref()
+            .unwrap()
+            .contains(&"FOO=bar".to_string()));
+
+        let mut spec = Spec::default();
+        spec.set_process(None);
+        assert!(BundleBuilder::new(spec).spec().process().is_none());
+    }
+
+    #[test]
+    fn build_test() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("bin")).unwrap();
+        fs::write(src.path().join("bin/sh"), "sh").unwrap();
+
+        let bundle = BundleBuilder::rootless(1000, 1000)
+            .rootfs(Rootfs::Dir(src.path().to_path_buf()))
+            .build()
+            .unwrap();
+        let path = bundle.path().to_path_buf();
+        assert!(path.join("rootfs/bin/sh").exists());
+        let written: Spec = serde_json::from_slice(&fs::read(bundle.config()).unwrap()).unwrap();
+        assert_eq!(written.root().as_ref().unwrap().path(), Path::new("rootfs"));
+        assert_eq!(&written, bundle.spec());
+        drop(bundle);
+        assert!(!path.exists());
+
+        // tarball
+        let tarball = src.path().join("rootfs.tar");
+        run(Command::new("tar")
+            .arg("-cf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(src.path())
+            .arg("bin"))
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = BundleBuilder::new(Spec::default())
+            .rootfs(Rootfs::Tar(tarball))
+            .build_in(dir.path().join("b"))
+            .unwrap();
+        assert!(bundle.path().join("rootfs/bin/sh").exists());
+        let kept = bundle.keep();
+        assert!(kept.join(CONFIG_FILE).exists());
+
+        // existing path
+        let bundle = BundleBuilder::new(Spec::default())
+            .rootfs(Rootfs::Bind(src.path().to_path_buf()))
+            .build()
+            .unwrap();
+        assert_eq!(
+            bundle.spec().root().as_ref().unwrap().path(),
+            &src.path().canonicalize().unwrap()
+        );
+        assert!(!bundle.path().join("rootfs").exists());
+
+        // the bound rootfs is left alone
+        let path = bundle.path().to_path_buf();
+        drop(bundle);
+        assert!(!path.exists());
+        assert!(src.path().join("bin/sh").exists());
+
+        let bundle = BundleBuilder::new(Spec::default())
+            .remove_on_drop(false)
+            .build()
+            .unwrap();
+        let kept = bundle.path().to_path_buf();
+        drop(bundle);
+        assert!(kept.exists());
+        fs::remove_dir_all(kept).unwrap();
+
+        assert!(BundleBuilder::new(Spec::default())
+            .rootfs(Rootfs::Tar(src.path().join("missing.tar")))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn build_in_existing_dir_test() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data"), "data").unwrap();
+
+        // a non-empty dir is refused and left alone
+        assert!(BundleBuilder::new(Spec::default())
+            .build_in(dir.path())
+            .is_err());
+        assert!(dir.path().join("data").exists());
+
+        // a failed build in an empty dir removes what it created, but not the dir
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        assert!(BundleBuilder::new(Spec::default())
+            .rootfs(Rootfs::Tar(dir.path().join("missing.tar")))
+            .build_in(&empty)
+            .is_err());
+        assert!(empty.exists());
+        assert_eq!(fs::read_dir(&empty).unwrap().count(), 0);
+
+        // a bundle built in an empty dir only removes its contents on drop
+        let bundle = BundleBuilder::new(Spec::default())
+            .build_in(&empty)
+            .unwrap();
+        assert!(empty.join(CONFIG_FILE).exists());
+        drop(bundle);
+        assert!(empty.exists());
+        assert_eq!(fs::read_dir(&empty).unwrap().count(), 0);
+
+        // and of a missing dir, the dir too
+        let missing = dir.path().join("missing");
+        assert!(BundleBuilder::new(Spec::default())
+            .rootfs(Rootfs::Tar(dir.path().join("missing.tar")))
+            .build_in(&missing)
+            .is_err());
+        assert!(!missing.exists());
+    }
+}
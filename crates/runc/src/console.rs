@@ -0,0 +1,409 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Console socket handling for containers created with a terminal.
+//!
+//! Pass [ConsoleSocket::path] as `console_socket` in [CreateOpts](crate::options::CreateOpts)
+//! or [ExecOpts](crate::options::ExecOpts), then call [ConsoleSocket::receive] to get the pty
+//! master that runc sends over the socket.
+
+use std::{
+    env,
+    fs::File,
+    io::IoSliceMut,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use nix::{
+    cmsg_space,
+    sys::{
+        socket::{recvmsg, ControlMessageOwned, MsgFlags, UnixAddr},
+        stat::Mode,
+        termios::tcgetattr,
+    },
+};
+
+use crate::{error::Error, Result};
+
+/// Directory for the console socket, under `$XDG_RUNTIME_DIR` if set.
+fn socket_dir() -> PathBuf {
+    let base = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    base.join(format!("pty{}", uuid::Uuid::new_v4()))
+}
+
+/// Receive the pty master sent by runc over the connected console socket `fd`.
+pub fn receive_master(fd: RawFd) -> Result<File> {
+    let mut buf = [0u8; 4096];
+    let mut iovec = [IoSliceMut::new(&mut buf)];
+    let mut space = cmsg_space!([RawFd; 2]);
+    let msg = recvmsg::<UnixAddr>(fd, &mut iovec, Some(&mut space), MsgFlags::MSG_CMSG_CLOEXEC)
+        .map_err(|_| Error::UnixSocketReceiveMessageFailed)?;
+    if msg.bytes == 0 {
+        return Err(Error::UnixSocketClosed);
+    }
+    let fds = msg
+        .cmsgs()
+        .find_map(|c| match c {
+            ControlMessageOwned::ScmRights(fds) => Some(fds),
+            _ => None,
+        })
+        .unwrap_or_default();
+    // Take ownership of everything received, so that extra fds are closed.
+    let mut files = fds.into_iter().map(|fd| unsafe { File::from_raw_fd(fd) });
+    let master = files.next().ok_or(Error::UnixSocketReceiveMessageFailed)?;
+    tcgetattr(master.as_raw_fd()).map_err(|_| Error::UnixSocketReceiveMessageFailed)?;
+    Ok(master)
+}
+
+/// Set the window size of the terminal `fd`.
+fn set_size(fd: RawFd, width: u16, height: u16) -> Result<()> {
+    let ws = libc::winsize {
+        ws_row: height,
+        ws_col: width,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let ret = unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &ws) };
+    nix::errno::Errno::result(ret).map_err(Error::ConsoleResizeFailed)?;
+    Ok(())
+}
+
+/// The pty master of a container's terminal.
+#[derive(Debug)]
+pub struct Console {
+    master: File,
+}
+
+impl Console {
+    pub fn new(master: File) -> Self {
+        Self { master }
+    }
+
+    /// Set the window size of the terminal.
+    pub fn resize(&self, width: u16, height: u16) -> Result<()> {
+        set_size(self.master.as_raw_fd(), width, height)
+    }
+
+    pub fn into_file(self) -> File {
+        self.master
+    }
+
+    /// Split the master into a read and a write half, each a duplicate of the master fd.
+    #[cfg(not(feature = "async"))]
+    pub fn split(&self) -> Result<(File, File)> {
+        let reader = self.master.try_clone().map_err(Error::InvalidCommand)?;
+        let writer = self.master.try_clone().map_err(Error::InvalidCommand)?;
+        Ok((reader, writer))
+    }
+
+    /// Split the master into a read and a write half, each a duplicate of the master fd
+    /// registered with the tokio reactor.
+    ///
+    /// The master is switched to non-blocking mode, which is shared by all its duplicates.
+    #[cfg(feature = "async")]
+    pub fn split(&self) -> Result<(PtyHalf, PtyHalf)> {
+        let reader = self.master.try_clone().map_err(Error::InvalidCommand)?;
+        let writer = self.master.try_clone().map_err(Error::InvalidCommand)?;
+        set_nonblocking(reader.as_raw_fd())?;
+        Ok((PtyHalf::new(reader)?, PtyHalf::new(writer)?))
+    }
+}
+
+#[cfg(feature = "async")]
+fn set_nonblocking(fd: RawFd) -> Result<()> {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+    let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(|e| Error::InvalidCommand(e.into()))?;
+    let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+    fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(|e| Error::InvalidCommand(e.into()))?;
+    Ok(())
+}
+
+/// One half of a pty master, read and written when the reactor reports it ready.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct PtyHalf {
+    inner: tokio::io::unix::AsyncFd<File>,
+}
+
+#[cfg(feature = "async")]
+impl PtyHalf {
+    fn new(file: File) -> Result<Self> {
+        let inner = tokio::io::unix::AsyncFd::new(file).map_err(Error::InvalidCommand)?;
+        Ok(Self { inner })
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncRead for PtyHalf {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use std::{io::Read, task::Poll};
+
+        loop {
+            let mut guard = futures::ready!(self.inner.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|inner| inner.get_ref().read(unfilled)) {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncWrite for PtyHalf {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        use std::io::Write;
+
+        loop {
+            let mut guard = futures::ready!(self.inner.poll_write_ready(cx))?;
+            match guard.try_io(|inner| inner.get_ref().write(buf)) {
+                Ok(res) => return std::task::Poll::Ready(res),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+impl AsRawFd for Console {
+    fn as_raw_fd(&self) -> RawFd {
+        self.master.as_raw_fd()
+    }
+}
+
+#[cfg(not(feature = "async"))]
+type UnixListener = std::os::unix::net::UnixListener;
+
+#[cfg(feature = "async")]
+type UnixListener = tokio::net::UnixListener;
+
+/// A unix socket listening for the pty master sent by runc.
+///
+/// The socket, and the temp dir created by [ConsoleSocket::new], are removed on drop.
+#[derive(Debug)]
+pub struct ConsoleSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    rmdir: bool,
+}
+
+impl ConsoleSocket {
+    /// Bind a console socket in a new temp dir.
+    pub fn new() -> Result<Self> {
+        let dir = socket_dir();
+        nix::unistd::mkdir(&dir, Mode::from_bits_truncate(0o711)).map_err(Error::CreateDir)?;
+        let mut socket = Self::bind(dir.join("pty.sock")).map_err(|e| {
+            let _ = std::fs::remove_dir_all(&dir);
+            e
+        })?;
+        socket.rmdir = true;
+        Ok(socket)
+    }
+
+    /// Bind a console socket at `path`.
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path).map_err(Error::UnixSocketBindFailed)?;
+        Ok(Self {
+            listener,
+            path,
+            rmdir: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait for runc to connect and receive the pty master.
+    #[cfg(not(feature = "async"))]
+    pub fn receive(&self) -> Result<Console> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        let master = receive_master(stream.as_raw_fd())?;
+        Ok(Console::new(master))
+    }
+
+    /// Wait for runc to connect and receive the pty master.
+    #[cfg(feature = "async")]
+    pub async fn receive(&self) -> Result<Console> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .await
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        let stream = stream
+            .into_std()
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        stream
+            .set_nonblocking(false)
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        let master = tokio::task::spawn_blocking(move || receive_master(stream.as_raw_fd()))
+            .await
+            .map_err(|e| Error::Other(Box::new(e)))??;
+        Ok(Console::new(master))
+    }
+}
+
+impl Drop for ConsoleSocket {
+    fn drop(&mut self) {
+        let res = if self.rmdir {
+            match self.path.parent() {
+                Some(dir) => std::fs::remove_dir_all(dir),
+                None => Ok(()),
+            }
+        } else {
+            std::fs::remove_file(&self.path)
+        };
+        if let Err(e) = res {
+            warn!(
+                "failed to remove console socket {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::IoSlice,
+        os::unix::{io::AsRawFd, net::UnixStream},
+    };
+
+    use nix::{
+        pty::openpty,
+        sys::socket::{sendmsg, ControlMessage},
+    };
+
+    use super::*;
+
+    /// Send a new pty master to the socket at `path` like runc does, returning the slave.
+    fn send_pty(path: &Path) -> File {
+        let pty = openpty(None, None).unwrap();
+        let (master, slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        let stream = UnixStream::connect(path).unwrap();
+        let name = b"/dev/pts/fake";
+        let fds = [master.as_raw_fd()];
+        sendmsg::<UnixAddr>(
+            stream.as_raw_fd(),
+            &[IoSlice::new(name)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+        slave
+    }
+
+    fn window_size(fd: RawFd) -> (u16, u16) {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws) }, 0);
+        (ws.ws_col, ws.ws_row)
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn receive_test() {
+        use std::io::{Read, Write};
+
+        let socket = ConsoleSocket::new().unwrap();
+        let path = socket.path().to_path_buf();
+        let sender = std::thread::spawn(move || send_pty(&path));
+        let console = socket.receive().unwrap();
+        let mut slave = sender.join().unwrap();
+
+        console.resize(120, 40).unwrap();
+        assert_eq!(window_size(slave.as_raw_fd()), (120, 40));
+
+        let (mut reader, _writer) = console.split().unwrap();
+        slave.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let dir = socket.path().parent().unwrap().to_path_buf();
+        drop(socket);
+        assert!(!dir.exists());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn receive_test() {
+        use std::io::{Read, Write};
+
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let socket = ConsoleSocket::new().unwrap();
+        let path = socket.path().to_path_buf();
+        let sender = std::thread::spawn(move || send_pty(&path));
+        let console = socket.receive().await.unwrap();
+        let mut slave = sender.join().unwrap();
+
+        console.resize(120, 40).unwrap();
+        assert_eq!(window_size(slave.as_raw_fd()), (120, 40));
+
+        let (mut reader, mut writer) = console.split().unwrap();
+        slave.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // The slave is in canonical mode, so a read only returns a full line.
+        writer.write_all(b"world\n").await.unwrap();
+        let mut line = [0u8; 6];
+        slave.read_exact(&mut line).unwrap();
+        assert_eq!(&line, b"world\n");
+
+        let dir = socket.path().parent().unwrap().to_path_buf();
+        drop(socket);
+        assert!(!dir.exists());
+    }
+}
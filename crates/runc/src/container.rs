@@ -38,6 +38,8 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use time::{serde::timestamp, OffsetDateTime};
 
+use crate::{error::Error, Result};
+
 /// Information for runc container
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Container {
@@ -51,6 +53,66 @@ pub struct Container {
     pub annotations: HashMap<String, String>,
 }
 
+/// Processes inside a container, as reported by `runc ps --format table`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopResults {
+    /// Column headers of the ps output, such as `UID` and `PID`
+    pub headers: Vec<String>,
+    /// One row per process, with as many fields as there are headers
+    pub processes: Vec<Vec<String>>,
+}
+
+impl TopResults {
+    /// Return the values of the `PID` column.
+    pub fn pids(&self) -> Vec<usize> {
+        let index = match self.headers.iter().position(|h| h == "PID") {
+            Some(i) => i,
+            None => return Vec::new(),
+        };
+        self.processes
+            .iter()
+            .filter_map(|p| p.get(index).and_then(|pid| pid.parse().ok()))
+            .collect()
+    }
+}
+
+/// Parse the table output of `ps`, the same way as containerd's `ParsePSOutput`.
+///
+/// The last column, usually `CMD`, may contain spaces, so any remaining fields are
+/// joined into it.
+pub fn parse_ps_output(output: &str) -> Result<TopResults> {
+    let mut lines = output.lines();
+    let headers: Vec<String> = match lines.next() {
+        Some(line) if !line.trim().is_empty() => {
+            line.split_whitespace().map(|s| s.to_string()).collect()
+        }
+        _ => return Err(Error::TopShortResponseError),
+    };
+    let pid_index = headers
+        .iter()
+        .position(|h| h == "PID")
+        .ok_or(Error::TopMissingPidHeader)?;
+
+    let mut processes = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() < headers.len() {
+            return Err(Error::TopShortResponseError);
+        }
+        if fields[pid_index] == "-" {
+            continue;
+        }
+        let last = headers.len() - 1;
+        let mut process: Vec<String> = fields[..last].iter().map(|s| s.to_string()).collect();
+        process.push(fields[last..].join(" "));
+        processes.push(process);
+    }
+    Ok(TopResults { headers, processes })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,4 +145,37 @@ mod tests {
         assert_eq!(c.annotations.get("foo"), Some(&"bar".to_string()));
         assert_eq!(c.annotations.get("bar"), None);
     }
+
+    #[test]
+    fn parse_ps_output_test() {
+        let output = "UID PID PPID C STIME TTY TIME CMD
+root 1234 1 0 10:00 ? 00:00:00 sleep infinity
+root - 1 0 10:00 ? 00:00:00 thread
+root 5678 1234 0 10:01 ? 00:00:00 sh -c echo hello world
+";
+        let top = parse_ps_output(output).unwrap();
+        assert_eq!(
+            top.headers,
+            vec!["UID", "PID", "PPID", "C", "STIME", "TTY", "TIME", "CMD"]
+        );
+        assert_eq!(top.processes.len(), 2);
+        assert_eq!(top.processes[0][1], "1234");
+        assert_eq!(top.processes[0][7], "sleep infinity");
+        assert_eq!(top.processes[1][1], "5678");
+        assert_eq!(top.processes[1][7], "sh -c echo hello world");
+        assert_eq!(top.pids(), vec![1234, 5678]);
+
+        match parse_ps_output("UID PPID CMD\nroot 1 sleep\n") {
+            Err(Error::TopMissingPidHeader) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match parse_ps_output("") {
+            Err(Error::TopShortResponseError) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match parse_ps_output("UID PID CMD\nroot\n") {
+            Err(Error::TopShortResponseError) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
 }
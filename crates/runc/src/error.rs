@@ -37,6 +37,8 @@ use std::{env, io, process::ExitStatus};
 
 use thiserror::Error;
 
+use crate::log::Entry;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Unable to extract test files: {0}")]
@@ -62,6 +64,8 @@ pub enum Error {
         status: ExitStatus,
         stdout: String,
         stderr: String,
+        /// Classification of the failure, see [Error::command_failed].
+        kind: FailureKind,
     },
 
     #[error("Runc IO unavailable: {0}")]
@@ -71,6 +75,10 @@ pub enum Error {
     #[error("Runc command timed out: {0}")]
     CommandTimeout(tokio::time::error::Elapsed),
 
+    #[cfg(feature = "async")]
+    #[error("Runc command cancelled")]
+    CommandCancelled,
+
     #[error("Unable to parse runc version")]
     InvalidVersion,
 
@@ -107,6 +115,9 @@ pub enum Error {
     #[error("Unix socket unexpectedly closed")]
     UnixSocketClosed,
 
+    #[error("Failed to set console size: {0}")]
+    ConsoleResizeFailed(nix::Error),
+
     #[error("Failed to handle environment variable: {0}")]
     EnvError(env::VarError),
 
@@ -121,4 +132,231 @@ pub enum Error {
 
     #[error("Failed to create dir: {0}")]
     CreateDir(nix::Error),
+
+    #[error("No recorded runc command matches: {0}")]
+    TranscriptNotFound(String),
+
+    #[error("Cgroup is not delegated: {0}")]
+    CgroupNotDelegated(String),
+}
+
+/// Classification of a failed runc command, parsed from the messages runc printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The container does not exist.
+    NotFound,
+    /// A container with the same id already exists.
+    AlreadyExists,
+    /// The container is not running.
+    NotRunning,
+    /// The container or process has already stopped.
+    AlreadyStopped,
+    /// The container is paused.
+    Paused,
+    /// runc lacks the permission to perform the operation.
+    PermissionDenied,
+    /// runc failed to manage the cgroups of the container.
+    CgroupError,
+    /// The message did not match any known failure.
+    Unknown,
+}
+
+impl Default for FailureKind {
+    fn default() -> Self {
+        FailureKind::Unknown
+    }
+}
+
+impl FailureKind {
+    /// Classify an error message printed by runc, either on stderr or in its log.
+    pub fn from_message(msg: &str) -> FailureKind {
+        let msg = msg.to_lowercase();
+        let matches = |patterns: &[&str]| patterns.iter().any(|p| msg.contains(p));
+        if matches(&["does not exist", "container not found"]) {
+            FailureKind::NotFound
+        } else if matches(&["already exists", "id already in use"]) {
+            FailureKind::AlreadyExists
+        } else if matches(&[
+            "process already finished",
+            "no such process",
+            "container that has stopped",
+            "container is stopped",
+        ]) {
+            FailureKind::AlreadyStopped
+        } else if matches(&["container paused", "container is paused"]) {
+            FailureKind::Paused
+        } else if matches(&["not running"]) {
+            FailureKind::NotRunning
+        } else if matches(&["cgroup"]) {
+            FailureKind::CgroupError
+        } else if matches(&["permission denied", "operation not permitted"]) {
+            FailureKind::PermissionDenied
+        } else {
+            FailureKind::Unknown
+        }
+    }
+}
+
+impl Error {
+    /// Build an [Error::CommandFailed], classifying it from the stderr of the command, latest
+    /// line first.
+    ///
+    /// runc prints the error it exits with to stderr even when it logs to a file, so its log is
+    /// only a fallback, see [Error::with_log_errors].
+    pub fn command_failed(status: ExitStatus, stdout: String, stderr: String) -> Self {
+        let kind = stderr
+            .lines()
+            .rev()
+            .map(FailureKind::from_message)
+            .find(|k| *k != FailureKind::Unknown)
+            .unwrap_or_default();
+        Error::CommandFailed {
+            status,
+            stdout,
+            stderr,
+            kind,
+        }
+    }
+
+    /// Classify an [Error::CommandFailed] that stderr left [FailureKind::Unknown] from the
+    /// errors runc logged during the command, latest first.
+    pub fn with_log_errors(mut self, entries: &[Entry]) -> Self {
+        if let Error::CommandFailed { kind, .. } = &mut self {
+            if *kind == FailureKind::Unknown {
+                *kind = entries
+                    .iter()
+                    .rev()
+                    .map(|e| FailureKind::from_message(&e.msg))
+                    .find(|k| *k != FailureKind::Unknown)
+                    .unwrap_or_default();
+            }
+        }
+        self
+    }
+
+    /// Return the classification if this is an [Error::CommandFailed].
+    pub fn failure_kind(&self) -> Option<FailureKind> {
+        match self {
+            Error::CommandFailed { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.failure_kind() == Some(FailureKind::NotFound)
+    }
+
+    pub fn is_already_exists(&self) -> bool {
+        self.failure_kind() == Some(FailureKind::AlreadyExists)
+    }
+
+    pub fn is_not_running(&self) -> bool {
+        self.failure_kind() == Some(FailureKind::NotRunning)
+    }
+
+    pub fn is_already_stopped(&self) -> bool {
+        self.failure_kind() == Some(FailureKind::AlreadyStopped)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.failure_kind() == Some(FailureKind::Paused)
+    }
+
+    pub fn is_permission_denied(&self) -> bool {
+        self.failure_kind() == Some(FailureKind::PermissionDenied)
+    }
+
+    pub fn is_cgroup_error(&self) -> bool {
+        self.failure_kind() == Some(FailureKind::CgroupError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+    use crate::log::Level;
+
+    #[test]
+    fn failure_kind_test() {
+        let cases = [
+            ("container \"abc\" does not exist", FailureKind::NotFound),
+            (
+                "container with given ID already exists",
+                FailureKind::AlreadyExists,
+            ),
+            ("container not running", FailureKind::NotRunning),
+            (
+                "kill: os: process already finished",
+                FailureKind::AlreadyStopped,
+            ),
+            (
+                "cannot start a container that has stopped",
+                FailureKind::AlreadyStopped,
+            ),
+            ("container paused", FailureKind::Paused),
+            (
+                "mkdir /sys/fs/cgroup/foo: permission denied",
+                FailureKind::CgroupError,
+            ),
+            (
+                "open /run/runc/abc: permission denied",
+                FailureKind::PermissionDenied,
+            ),
+            ("something went wrong", FailureKind::Unknown),
+        ];
+        for (msg, kind) in cases.iter() {
+            assert_eq!(FailureKind::from_message(msg), *kind, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn command_failed_test() {
+        let status = ExitStatus::from_raw(1 << 8);
+        let err = Error::command_failed(
+            status,
+            String::new(),
+            "ERRO[0000] container not running".to_string(),
+        );
+        assert!(err.is_not_running());
+        assert!(!err.is_not_found());
+
+        // the latest line takes precedence
+        let err = Error::command_failed(
+            status,
+            String::new(),
+            "WARN[0000] cgroup v1 is deprecated\ncontainer \"abc\" does not exist\n".to_string(),
+        );
+        assert!(err.is_not_found());
+
+        let err = Error::command_failed(status, String::new(), String::new());
+        assert_eq!(err.failure_kind(), Some(FailureKind::Unknown));
+        assert_eq!(Error::NotFound.failure_kind(), None);
+    }
+
+    #[test]
+    fn with_log_errors_test() {
+        let status = ExitStatus::from_raw(1 << 8);
+        let entry = |msg: &str| Entry {
+            level: Level::Error,
+            msg: msg.to_string(),
+            time: String::new(),
+        };
+        let entries = [
+            entry("container \"abc\" does not exist"),
+            entry("container is paused"),
+            entry("exit status 1"),
+        ];
+
+        // the log is only used when stderr tells nothing
+        let err = Error::command_failed(status, String::new(), "exit status 1".to_string())
+            .with_log_errors(&entries);
+        assert!(err.is_paused());
+        let err = Error::command_failed(status, String::new(), "container not running".to_string())
+            .with_log_errors(&entries);
+        assert!(err.is_not_running());
+        let err = Error::command_failed(status, String::new(), String::new()).with_log_errors(&[]);
+        assert_eq!(err.failure_kind(), Some(FailureKind::Unknown));
+    }
 }
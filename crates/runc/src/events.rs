@@ -34,8 +34,18 @@
  */
 
 use std::collections::HashMap;
+#[cfg(not(feature = "async"))]
+use std::io::{BufRead, BufReader, Lines, Read};
+#[cfg(feature = "async")]
+use std::pin::Pin;
 
+#[cfg(feature = "async")]
+use futures::Stream;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+use crate::{error::Error, Result};
 
 /// Event type generated by runc
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,8 +73,9 @@ pub struct Stats {
     pub pids: Pids,
     #[serde(rename = "blkio")]
     pub block_io: BlkIO,
+    /// Hugetlb statistics, keyed by page size
     #[serde(rename = "hugetlb")]
-    pub huge_tlb: HugeTLB,
+    pub huge_tlb: Option<HashMap<String, HugeTLB>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -137,6 +148,7 @@ pub struct CpuUsage {
     /// Total CPU time consumed
     pub total: Option<u64>,
     /// Total CPU time consumed per core
+    #[serde(rename = "percpu")]
     pub per_cpu: Option<Vec<u64>>,
     /// Total CPU time consumed in kernel mode
     pub kernel: u64,
@@ -146,7 +158,7 @@ pub struct CpuUsage {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cpu {
-    pub usage: Option<u64>,
+    pub usage: Option<CpuUsage>,
     pub throttling: Option<Throttling>,
 }
 
@@ -179,3 +191,205 @@ pub struct Memory {
     /// Raw stats of memory
     pub raw: Option<HashMap<String, u64>>,
 }
+
+/// Blocking iterator over the events emitted by a `runc events` process.
+///
+/// The iterator ends when the runc process exits, and the child is killed when the iterator
+/// is dropped before that.
+#[cfg(not(feature = "async"))]
+pub struct EventIterator {
+    child: std::process::Child,
+    lines: Lines<BufReader<std::process::ChildStdout>>,
+    stderr: Option<std::thread::JoinHandle<String>>,
+    done: bool,
+}
+
+#[cfg(not(feature = "async"))]
+impl EventIterator {
+    pub(crate) fn new(mut child: std::process::Child) -> Result<Self> {
+        let stdout = child.stdout.take().ok_or_else(|| {
+            Error::UnavailableIO(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "stdout of runc events is not piped",
+            ))
+        })?;
+        // Drained while the events are read, so that runc never blocks on a full stderr pipe.
+        let stderr = child.stderr.take().map(|mut e| {
+            std::thread::spawn(move || {
+                let mut stderr = String::new();
+                let _ = e.read_to_string(&mut stderr);
+                stderr
+            })
+        });
+        Ok(Self {
+            child,
+            lines: BufReader::new(stdout).lines(),
+            stderr,
+            done: false,
+        })
+    }
+
+    fn finish(&mut self) -> Option<Result<Event>> {
+        self.done = true;
+        let status = self.child.wait();
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|h| h.join().ok())
+            .unwrap_or_default();
+        match status {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(Err(Error::command_failed(status, String::new(), stderr))),
+            Err(e) => Some(Err(Error::InvalidCommand(e))),
+        }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Iterator for EventIterator {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.lines.next() {
+                Some(Ok(line)) if line.trim().is_empty() => continue,
+                Some(Ok(line)) => {
+                    return Some(
+                        serde_json::from_str(&line).map_err(Error::JsonDeserializationFailed),
+                    )
+                }
+                Some(Err(e)) => {
+                    self.done = true;
+                    let _ = self.child.kill();
+                    let _ = self.child.wait();
+                    return Some(Err(Error::InvalidCommand(e)));
+                }
+                None => return self.finish(),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Drop for EventIterator {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Stream of the events emitted by a `runc events` process.
+///
+/// The stream ends when the runc process exits, and the child is killed when the stream
+/// is dropped before that.
+#[cfg(feature = "async")]
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<Event>> + Send>>;
+
+#[cfg(feature = "async")]
+pub(crate) fn event_stream(mut child: tokio::process::Child) -> Result<EventStream> {
+    let stdout = child.stdout.take().ok_or_else(|| {
+        Error::UnavailableIO(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "stdout of runc events is not piped",
+        ))
+    })?;
+    let lines = BufReader::new(stdout).lines();
+    // Drained while the events are read, so that runc never blocks on a full stderr pipe.
+    let stderr = child.stderr.take().map(|mut e| {
+        tokio::spawn(async move {
+            let mut stderr = String::new();
+            let _ = e.read_to_string(&mut stderr).await;
+            stderr
+        })
+    });
+    let stream = futures::stream::unfold(Some((child, lines, stderr)), |state| async move {
+        let (mut child, mut lines, stderr) = state?;
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => {
+                    let event =
+                        serde_json::from_str(&line).map_err(Error::JsonDeserializationFailed);
+                    return Some((event, Some((child, lines, stderr))));
+                }
+                Ok(None) => {
+                    let status = child.wait().await;
+                    let stderr = match stderr {
+                        Some(h) => h.await.unwrap_or_default(),
+                        None => String::new(),
+                    };
+                    return match status {
+                        Ok(status) if status.success() => None,
+                        Ok(status) => Some((
+                            Err(Error::command_failed(status, String::new(), stderr)),
+                            None,
+                        )),
+                        Err(e) => Some((Err(Error::InvalidCommand(e)), None)),
+                    };
+                }
+                Err(e) => {
+                    let _ = child.kill().await;
+                    return Some((Err(Error::InvalidCommand(e)), None));
+                }
+            }
+        }
+    });
+    Ok(Box::pin(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_event_test() {
+        let j = r#"
+            {
+                "type": "stats",
+                "id": "fake",
+                "data": {
+                    "cpu": {
+                        "usage": {
+                            "total": 4000,
+                            "percpu": [1000, 3000],
+                            "kernel": 1500,
+                            "user": 2500
+                        },
+                        "throttling": {}
+                    },
+                    "memory": {
+                        "usage": { "limit": 1048576, "usage": 4096, "max": 8192, "failcnt": 0 },
+                        "raw": { "total_inactive_file": 1024 }
+                    },
+                    "pids": { "current": 3 },
+                    "blkio": {},
+                    "hugetlb": {
+                        "2MB": { "failcnt": 0 }
+                    }
+                }
+            }"#;
+
+        let event: Event = serde_json::from_str(j).unwrap();
+        assert!(matches!(event.event_type, EventType::Stats));
+        assert_eq!(event.id, "fake");
+        let stats = event.stats.unwrap();
+        let usage = stats.cpu.usage.unwrap();
+        assert_eq!(usage.total, Some(4000));
+        assert_eq!(usage.per_cpu, Some(vec![1000, 3000]));
+        assert_eq!(usage.kernel, 1500);
+        assert_eq!(usage.user, 2500);
+        assert_eq!(stats.memory.usage.unwrap().usage, Some(4096));
+        assert_eq!(stats.pids.current, Some(3));
+        assert_eq!(stats.huge_tlb.unwrap()["2MB"].fail_count, 0);
+    }
+
+    #[test]
+    fn oom_event_test() {
+        let event: Event = serde_json::from_str(r#"{"type":"oom","id":"fake"}"#).unwrap();
+        assert!(matches!(event.event_type, EventType::Oom));
+        assert!(event.stats.is_none());
+    }
+}
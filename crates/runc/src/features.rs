@@ -0,0 +1,144 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Types for the output of `runc features`.
+//!
+//! See <https://github.com/opencontainers/runtime-spec/blob/main/features.md>
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Features supported by the runtime, as reported by `runc features`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Features {
+    /// The minimum OCI runtime spec version recognized by the runtime
+    pub oci_version_min: Option<String>,
+    /// The maximum OCI runtime spec version recognized by the runtime
+    pub oci_version_max: Option<String>,
+    /// Known hook names, [`None`] means unknown
+    pub hooks: Option<Vec<String>>,
+    /// Known mount options, [`None`] means unknown
+    pub mount_options: Option<Vec<String>>,
+    /// Linux specific features
+    pub linux: Option<Linux>,
+    /// Implementation specific information, such as the runc version and commit
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Linux {
+    /// Known namespaces
+    pub namespaces: Option<Vec<String>>,
+    /// Known capabilities
+    pub capabilities: Option<Vec<String>>,
+    pub cgroup: Option<Cgroup>,
+    pub seccomp: Option<Seccomp>,
+    pub apparmor: Option<Availability>,
+    pub selinux: Option<Availability>,
+    pub intel_rdt: Option<Availability>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cgroup {
+    /// Whether cgroup v1 is supported
+    pub v1: Option<bool>,
+    /// Whether cgroup v2 is supported
+    pub v2: Option<bool>,
+    /// Whether the systemd cgroup driver is supported
+    pub systemd: Option<bool>,
+    /// Whether the systemd cgroup driver is supported in user mode
+    pub systemd_user: Option<bool>,
+    /// Whether the rdma cgroup is supported
+    pub rdma: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Seccomp {
+    /// Whether seccomp support is compiled in
+    pub enabled: Option<bool>,
+    /// Supported actions, e.g. `SCMP_ACT_ALLOW`
+    pub actions: Option<Vec<String>>,
+    /// Supported operators, e.g. `SCMP_CMP_EQ`
+    pub operators: Option<Vec<String>>,
+    /// Supported architectures, e.g. `SCMP_ARCH_X86_64`
+    pub archs: Option<Vec<String>>,
+    /// Flags known to the runtime
+    pub known_flags: Option<Vec<String>>,
+    /// Flags supported by the runtime on this host
+    pub supported_flags: Option<Vec<String>>,
+}
+
+/// Availability of a security module, such as apparmor or selinux
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Availability {
+    pub enabled: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serde_test() {
+        let j = r#"
+            {
+                "ociVersionMin": "1.0.0",
+                "ociVersionMax": "1.1.0",
+                "hooks": ["prestart", "poststop"],
+                "mountOptions": ["ro", "rbind"],
+                "linux": {
+                    "namespaces": ["cgroup", "pid"],
+                    "capabilities": ["CAP_CHOWN"],
+                    "cgroup": { "v1": true, "v2": true, "systemd": true, "systemdUser": false },
+                    "seccomp": {
+                        "enabled": true,
+                        "actions": ["SCMP_ACT_ALLOW", "SCMP_ACT_ERRNO"],
+                        "operators": ["SCMP_CMP_EQ"],
+                        "archs": ["SCMP_ARCH_X86_64"]
+                    },
+                    "apparmor": { "enabled": true },
+                    "selinux": { "enabled": false }
+                },
+                "annotations": {
+                    "org.opencontainers.runc.version": "1.1.4"
+                }
+            }"#;
+
+        let f: Features = serde_json::from_str(j).unwrap();
+        assert_eq!(f.oci_version_max.as_deref(), Some("1.1.0"));
+        assert_eq!(f.hooks.unwrap().len(), 2);
+        let linux = f.linux.unwrap();
+        let cgroup = linux.cgroup.unwrap();
+        assert_eq!(cgroup.v2, Some(true));
+        assert_eq!(cgroup.systemd_user, Some(false));
+        assert_eq!(cgroup.rdma, None);
+        let seccomp = linux.seccomp.unwrap();
+        assert_eq!(seccomp.actions.unwrap()[1], "SCMP_ACT_ERRNO");
+        assert_eq!(linux.apparmor.unwrap().enabled, Some(true));
+        assert_eq!(linux.selinux.unwrap().enabled, Some(false));
+        assert!(linux.intel_rdt.is_none());
+        assert_eq!(
+            f.annotations.get("org.opencontainers.runc.version"),
+            Some(&"1.1.4".to_string())
+        );
+    }
+}
@@ -15,11 +15,18 @@
 */
 #[cfg(not(feature = "async"))]
 use std::io::{Read, Write};
+#[cfg(feature = "async")]
+use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::{
     fmt::Debug,
     fs::{File, OpenOptions},
-    io::Result,
-    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    io::{Error, ErrorKind, Result},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, RawFd},
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
     process::Stdio,
     sync::Mutex,
 };
@@ -69,6 +76,24 @@ pub trait Io: Debug + Send + Sync {
         None
     }
 
+    /// Return a duplicate of the write side of the stdin pipe, for [crate::splice::splice_copy]
+    #[cfg(feature = "async")]
+    fn stdin_pipe(&self) -> Option<File> {
+        None
+    }
+
+    /// Return a duplicate of the read side of the stdout pipe, for [crate::splice::splice_copy]
+    #[cfg(feature = "async")]
+    fn stdout_pipe(&self) -> Option<File> {
+        None
+    }
+
+    /// Return a duplicate of the read side of the stderr pipe, for [crate::splice::splice_copy]
+    #[cfg(feature = "async")]
+    fn stderr_pipe(&self) -> Option<File> {
+        None
+    }
+
     /// Set IO for passed command.
     /// Read side of stdin, write side of stdout and write side of stderr should be provided to command.
     fn set(&self, cmd: &mut Command) -> Result<()>;
@@ -212,6 +237,30 @@ impl Io for PipedIo {
         })
     }
 
+    #[cfg(feature = "async")]
+    fn stdin_pipe(&self) -> Option<File> {
+        self.stdin
+            .as_ref()
+            .and_then(|pipe| pipe.wr.try_clone().ok())
+            .map(|w| unsafe { File::from_raw_fd(w.into_raw_fd()) })
+    }
+
+    #[cfg(feature = "async")]
+    fn stdout_pipe(&self) -> Option<File> {
+        self.stdout
+            .as_ref()
+            .and_then(|pipe| pipe.rd.try_clone().ok())
+            .map(|r| unsafe { File::from_raw_fd(r.into_raw_fd()) })
+    }
+
+    #[cfg(feature = "async")]
+    fn stderr_pipe(&self) -> Option<File> {
+        self.stderr
+            .as_ref()
+            .and_then(|pipe| pipe.rd.try_clone().ok())
+            .map(|r| unsafe { File::from_raw_fd(r.into_raw_fd()) })
+    }
+
     // Note that this internally use [`std::fs::File`]'s `try_clone()`.
     // Thus, the files passed to commands will be not closed after command exit.
     fn set(&self, cmd: &mut Command) -> std::io::Result<()> {
@@ -355,6 +404,222 @@ impl Io for FIFO {
     fn close_after_start(&self) {}
 }
 
+/// Split a `scheme://path?query` URI into its path and query pairs, percent-decoded.
+fn parse_uri(uri: &str, scheme: &str) -> Result<(PathBuf, Vec<(String, String)>)> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid {} uri: {}", scheme, uri),
+        )
+    };
+    let rest = uri
+        .strip_prefix(scheme)
+        .and_then(|r| r.strip_prefix("://"))
+        .ok_or_else(invalid)?;
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let path = percent_decode(path, false).ok_or_else(invalid)?;
+    if !path.starts_with('/') {
+        return Err(invalid());
+    }
+    let mut pairs = Vec::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        let k = percent_decode(k, true).ok_or_else(invalid)?;
+        let v = percent_decode(v, true).ok_or_else(invalid)?;
+        pairs.push((k, v));
+    }
+    Ok((PathBuf::from(path), pairs))
+}
+
+fn percent_decode(s: &str, query: bool) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'+' if query => out.push(b' '),
+            b => out.push(b),
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Io driver passing `fds` to runc as fds 3, 4, ... for `--preserve-fds`, on top of the stdio
+/// set up by `io`.
+///
+/// [Command] can only pass fds at given numbers from a `pre_exec` hook, so that is what
+/// [Io::set] installs.
+#[derive(Debug)]
+pub(crate) struct PreservedFdsIo<'a> {
+    io: Option<&'a dyn Io>,
+    fds: &'a [RawFd],
+}
+
+impl<'a> PreservedFdsIo<'a> {
+    pub(crate) fn new(io: Option<&'a dyn Io>, fds: &'a [RawFd]) -> Self {
+        Self { io, fds }
+    }
+}
+
+impl Io for PreservedFdsIo<'_> {
+    fn set(&self, cmd: &mut Command) -> Result<()> {
+        if let Some(io) = self.io {
+            io.set(cmd)?;
+        }
+        if !self.fds.is_empty() {
+            crate::utils::pre_exec(cmd, crate::utils::preserve_fds_hook(self.fds));
+        }
+        Ok(())
+    }
+
+    fn close_after_start(&self) {
+        if let Some(io) = self.io {
+            io.close_after_start();
+        }
+    }
+}
+
+/// Io driver appending both stdout and stderr to a file, for containerd's `file://` stdio URIs.
+#[derive(Debug)]
+pub struct FileIo {
+    file: Mutex<Option<File>>,
+}
+
+impl FileIo {
+    /// Open `path` for appending, creating it and its parent dirs if needed.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o644)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Create from a `file:///path/to/log` URI.
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let (path, _) = parse_uri(uri, "file")?;
+        Self::new(path)
+    }
+}
+
+impl Io for FileIo {
+    fn set(&self, cmd: &mut Command) -> Result<()> {
+        if let Some(f) = self.file.lock().unwrap().as_ref() {
+            cmd.stdin(Stdio::null());
+            cmd.stdout(f.try_clone()?);
+            cmd.stderr(f.try_clone()?);
+        }
+        Ok(())
+    }
+
+    fn close_after_start(&self) {
+        let _ = self.file.lock().unwrap().take();
+    }
+}
+
+/// Io driver forwarding stdout and stderr to a logging binary, for containerd's `binary://`
+/// stdio URIs.
+///
+/// As with `containerd-shim-logging`, the binary gets the read sides of the stdout and
+/// stderr pipes as fds 3 and 4, and closes fd 5 once it is ready to read them. The query
+/// of the URI is passed as arguments, `?key=value` becoming `key value`, and the
+/// container is identified by the `CONTAINER_ID` and `CONTAINER_NAMESPACE` env vars.
+#[derive(Debug)]
+pub struct BinaryIo {
+    stdout: Mutex<Option<PipeWriter>>,
+    stderr: Mutex<Option<PipeWriter>>,
+    child: Mutex<Option<std::process::Child>>,
+}
+
+impl BinaryIo {
+    /// Start the logging binary of the `binary:///path?args` URI and wait until it is ready.
+    pub fn new(uri: &str, id: &str, namespace: &str) -> Result<Self> {
+        let (path, query) = parse_uri(uri, "binary")?;
+        let args = query
+            .into_iter()
+            .flat_map(|(k, v)| if v.is_empty() { vec![k] } else { vec![k, v] });
+
+        let (out_r, out_w) = os_pipe::pipe()?;
+        let (err_r, err_w) = os_pipe::pipe()?;
+        let (mut ready_r, ready_w) = os_pipe::pipe()?;
+
+        let mut cmd = std::process::Command::new(&path);
+        cmd.args(args)
+            .env("CONTAINER_ID", id)
+            .env("CONTAINER_NAMESPACE", namespace)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let fds = [out_r.as_raw_fd(), err_r.as_raw_fd(), ready_w.as_raw_fd()];
+        unsafe {
+            cmd.pre_exec(crate::utils::preserve_fds_hook(&fds));
+        }
+        let mut child = cmd.spawn()?;
+        drop((out_r, err_r, ready_w));
+
+        // The binary closes fd 5 when ready, or exits.
+        let mut buf = [0u8; 1];
+        if let Err(e) = std::io::Read::read(&mut ready_r, &mut buf) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        // best effort, the binary may not have been reaped yet
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("logging binary {} exited: {}", path.display(), status),
+                ));
+            }
+        }
+        debug!("logging binary {} started for {}", path.display(), id);
+
+        Ok(Self {
+            stdout: Mutex::new(Some(out_w)),
+            stderr: Mutex::new(Some(err_w)),
+            child: Mutex::new(Some(child)),
+        })
+    }
+
+    /// Wait for the logging binary to exit, which happens once the process it logs for exits.
+    pub fn wait(&self) -> Result<Option<std::process::ExitStatus>> {
+        match self.child.lock().unwrap().take() {
+            Some(mut child) => child.wait().map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Io for BinaryIo {
+    fn set(&self, cmd: &mut Command) -> Result<()> {
+        cmd.stdin(Stdio::null());
+        if let Some(w) = self.stdout.lock().unwrap().as_ref() {
+            cmd.stdout(w.try_clone()?);
+        }
+        if let Some(w) = self.stderr.lock().unwrap().as_ref() {
+            cmd.stderr(w.try_clone()?);
+        }
+        Ok(())
+    }
+
+    fn close_after_start(&self) {
+        let _ = self.stdout.lock().unwrap().take();
+        let _ = self.stderr.lock().unwrap().take();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -423,4 +688,93 @@ mod tests {
         assert!(io.stdout().is_none());
         assert!(io.stderr().is_none());
     }
+
+    #[test]
+    fn test_parse_uri() {
+        let (path, query) = parse_uri(
+            "binary:///usr/bin/log%20ger?id=abc&debug&msg=a+b%2Bc",
+            "binary",
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("/usr/bin/log ger"));
+        assert_eq!(
+            query,
+            vec![
+                ("id".to_string(), "abc".to_string()),
+                ("debug".to_string(), "".to_string()),
+                ("msg".to_string(), "a b+c".to_string()),
+            ]
+        );
+        let (path, query) = parse_uri("file:///var/log/c.log", "file").unwrap();
+        assert_eq!(path, PathBuf::from("/var/log/c.log"));
+        assert!(query.is_empty());
+
+        assert!(parse_uri("fifo:///var/log/c.log", "file").is_err());
+        assert!(parse_uri("file://relative/c.log", "file").is_err());
+        assert!(parse_uri("file:///c%zz", "file").is_err());
+    }
+
+    /// Run a shell command with `io` set, and close the write sides once it has exited.
+    fn run_with_io(io: &dyn Io, script: &str) {
+        #[cfg(feature = "async")]
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        #[cfg(feature = "async")]
+        let _guard = rt.enter();
+        {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(script);
+            io.set(&mut cmd).unwrap();
+            #[cfg(not(feature = "async"))]
+            let status = cmd.status().unwrap();
+            #[cfg(feature = "async")]
+            let status = rt.block_on(cmd.status()).unwrap();
+            assert!(status.success());
+        }
+        io.close_after_start();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_file_io() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs/container.log");
+        let uri = format!("file://{}", path.display());
+        let io = FileIo::from_uri(&uri).unwrap();
+        run_with_io(&io, "echo out; echo err >&2");
+
+        // appended to an existing file
+        let io = FileIo::new(&path).unwrap();
+        run_with_io(&io, "echo again");
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "out\nerr\nagain\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_binary_io() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+[ "$1" = "--log" ] || exit 1
+exec 5>&-
+cat <&3 > "$2.out" &
+cat <&4 > "$2.err"
+wait
+echo "$CONTAINER_NAMESPACE/$CONTAINER_ID" > "$2.meta"
+"#;
+        let logger = crate::fake_runc(&dir, script);
+        let log = dir.path().join("container");
+        let uri = format!("binary://{}?--log={}", logger.display(), log.display());
+        let io = BinaryIo::new(&uri, "fake-id", "default").unwrap();
+        run_with_io(&io, "echo out; echo err >&2");
+        assert!(io.wait().unwrap().unwrap().success());
+
+        let read = |ext: &str| std::fs::read_to_string(log.with_extension(ext)).unwrap();
+        assert_eq!(read("out"), "out\n");
+        assert_eq!(read("err"), "err\n");
+        assert_eq!(read("meta"), "default/fake-id\n");
+
+        // a binary that fails to start is reported
+        let uri = format!("binary://{}", dir.path().join("missing").display());
+        assert!(BinaryIo::new(&uri, "fake-id", "default").is_err());
+    }
 }
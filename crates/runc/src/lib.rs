@@ -35,6 +35,8 @@
 
 //! A crate for consuming the runc binary in your Rust applications, similar to
 //! [go-runc](https://github.com/containerd/go-runc) for Go.
+#[cfg(feature = "async")]
+use std::time::Duration;
 use std::{
     fmt::{self, Debug, Display},
     path::{Path, PathBuf},
@@ -42,21 +44,46 @@ use std::{
     sync::Arc,
 };
 
+#[cfg(feature = "async")]
+use ::log::debug;
 #[cfg(feature = "async")]
 use async_trait::async_trait;
+use oci_spec::runtime::{LinuxResources, Process, Spec};
 #[cfg(feature = "async")]
-use log::debug;
-use oci_spec::runtime::{LinuxResources, Process};
-
-use crate::{container::Container, error::Error, options::*, utils::write_value_to_temp_file};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::Child,
+};
+#[cfg(feature = "async")]
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    container::{Container, TopResults},
+    error::{Error, FailureKind},
+    io::{Io, PreservedFdsIo},
+    log::{Level, LogReader},
+    options::*,
+    utils::write_value_to_temp_file,
+};
 
+pub mod bundle;
+pub mod console;
 pub mod container;
 pub mod error;
 pub mod events;
+pub mod features;
 pub mod io;
+pub mod log;
+#[cfg(feature = "metrics")]
+mod metrics;
 #[cfg(feature = "async")]
 pub mod monitor;
 pub mod options;
+pub mod profile;
+pub mod rootless;
+pub mod spawner;
+#[cfg(feature = "async")]
+pub mod splice;
 pub mod utils;
 
 pub type Result<T> = std::result::Result<T, crate::error::Error>;
@@ -76,6 +103,38 @@ pub struct Version {
     pub commit: Option<String>,
 }
 
+impl Version {
+    /// Parse the output of `runc --version`, which looks like:
+    ///
+    /// ```text
+    /// runc version 1.1.4
+    /// commit: v1.1.4-0-g5fd4c4d1
+    /// spec: 1.0.2-dev
+    /// ```
+    pub fn parse(output: &str) -> Result<Version> {
+        let mut version = Version {
+            runc_version: None,
+            spec_version: None,
+            commit: None,
+        };
+        for line in output.lines().map(str::trim) {
+            if let Some(v) = line.strip_prefix("commit:") {
+                version.commit = Some(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix("spec:") {
+                version.spec_version = Some(v.trim().to_string());
+            } else if let Some((_, v)) = line.split_once(" version ") {
+                if version.runc_version.is_none() {
+                    version.runc_version = Some(v.trim().to_string());
+                }
+            }
+        }
+        if version.runc_version.is_none() {
+            return Err(Error::InvalidVersion);
+        }
+        Ok(version)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LogFormat {
     Json,
@@ -107,10 +166,21 @@ pub type Command = tokio::process::Command;
 pub struct Runc {
     command: PathBuf,
     args: Vec<String>,
+    profile: profile::RuntimeProfile,
+    set_pgid: bool,
+    /// The json log of runc, read to classify failures that stderr does not explain.
+    log: Option<PathBuf>,
+    #[cfg(feature = "async")]
+    timeout: Duration,
     spawner: Arc<dyn Spawner + Send + Sync>,
 }
 
 impl Runc {
+    /// The profile of the runtime this client drives.
+    pub fn profile(&self) -> &profile::RuntimeProfile {
+        &self.profile
+    }
+
     fn command(&self, args: &[String]) -> Result<Command> {
         let args = [&self.args, args].concat();
         let mut cmd = Command::new(&self.command);
@@ -123,13 +193,43 @@ impl Runc {
         // NOTIFY_SOCKET introduces a special behavior in runc but should only be set if invoked from systemd
         cmd.args(&args).env_remove("NOTIFY_SOCKET");
 
+        if self.set_pgid {
+            utils::set_pgid(&mut cmd);
+        }
+
         Ok(cmd)
     }
+
+    /// A reader of the errors runc logs from now on, to classify the failure of a command.
+    fn log_errors(&self) -> Option<LogReader> {
+        self.log
+            .as_ref()
+            .map(|path| LogReader::tail(path).min_level(Level::Error))
+    }
 }
 
 #[cfg(not(feature = "async"))]
 impl Runc {
+    /// Build the error of a failed command, falling back to the errors runc logged since
+    /// `log_errors` was created when stderr does not classify it.
+    fn command_failed(
+        &self,
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+        log_errors: Option<LogReader>,
+    ) -> Error {
+        let err = Error::command_failed(status, stdout, stderr);
+        match log_errors {
+            Some(mut reader) if err.failure_kind() == Some(FailureKind::Unknown) => {
+                err.with_log_errors(&reader.read().unwrap_or_default())
+            }
+            _ => err,
+        }
+    }
+
     fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
+        let log_errors = self.log_errors();
         let (status, pid, stdout, stderr) = self.spawner.execute(cmd)?;
         if status.success() {
             let output = if combined_output {
@@ -143,11 +243,7 @@ impl Runc {
                 output,
             })
         } else {
-            Err(Error::CommandFailed {
-                status,
-                stdout,
-                stderr,
-            })
+            Err(self.command_failed(status, stdout, stderr, log_errors))
         }
     }
 
@@ -188,6 +284,24 @@ impl Runc {
         Ok(())
     }
 
+    /// Return an iterator of container notifications
+    ///
+    /// The iterator yields OOM notifications and stats collected every `interval`, and ends
+    /// when the underlying `runc events` process exits.
+    pub fn events(
+        &self,
+        id: &str,
+        interval: &std::time::Duration,
+    ) -> Result<events::EventIterator> {
+        let args = [
+            "events".to_string(),
+            format!("--interval={}ms", interval.as_millis()),
+            id.to_string(),
+        ];
+        let child = self.spawner.spawn(self.command(&args)?)?;
+        events::EventIterator::new(child)
+    }
+
     /// Execute an additional process inside the container
     pub fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
         let (_temp_file, filename) = write_value_to_temp_file(spec)?;
@@ -196,9 +310,26 @@ impl Runc {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
+        self.launch_exec(&args, opts)
+    }
+
+    /// Execute an additional process inside the container, described by `cmd` and [ExecOpts]
+    /// instead of a full process spec
+    pub fn exec_args(&self, id: &str, cmd: &[String], opts: Option<&ExecOpts>) -> Result<()> {
+        let mut args = vec!["exec".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        args.extend_from_slice(cmd);
+        self.launch_exec(&args, opts)
+    }
+
+    fn launch_exec(&self, args: &[String], opts: Option<&ExecOpts>) -> Result<()> {
+        let mut cmd = self.command(args)?;
         match opts {
-            Some(ExecOpts { io: Some(io), .. }) => {
+            Some(opts) if opts.io.is_some() || !opts.preserve_fds.is_empty() => {
+                let io = PreservedFdsIo::new(opts.io.as_deref(), &opts.preserve_fds);
                 io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
                 self.launch(cmd, true)?;
                 io.close_after_start();
@@ -250,16 +381,51 @@ impl Runc {
         Ok(())
     }
 
-    pub fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    /// Checkpoint a running container
+    pub fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<()> {
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        self.launch(self.command(&args)?, true)?;
+        Ok(())
     }
 
-    pub fn restore(&self) -> Result<()> {
-        Err(Error::Unimplemented("restore".to_string()))
+    /// Restore a container from a previous checkpoint
+    pub fn restore<P>(&self, id: &str, bundle: P, opts: Option<&RestoreOpts>) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let mut args = vec![
+            "restore".to_string(),
+            "--bundle".to_string(),
+            utils::abs_string(bundle)?,
+        ];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        match opts {
+            Some(RestoreOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                let res = self.launch(cmd, true)?;
+                io.close_after_start();
+                Ok(res)
+            }
+            _ => self.launch(cmd, true),
+        }
     }
 
     /// List all the processes inside the container, returning their pids
     pub fn ps(&self, id: &str) -> Result<Vec<usize>> {
+        if !self.profile.ps_json {
+            let args = ["ps".to_string(), id.to_string()];
+            let res = self.launch(self.command(&args)?, false)?;
+            let top = container::parse_ps_output(&res.output)?;
+            return Ok(top.pids());
+        }
         let args = [
             "ps".to_string(),
             "--format=json".to_string(),
@@ -276,6 +442,18 @@ impl Runc {
         })
     }
 
+    /// List the processes inside the container as a table, passing `ps_args` to `ps`
+    pub fn top(&self, id: &str, ps_args: &[String]) -> Result<TopResults> {
+        let mut args = vec![
+            "ps".to_string(),
+            "--format=table".to_string(),
+            id.to_string(),
+        ];
+        args.extend_from_slice(ps_args);
+        let res = self.launch(self.command(&args)?, false)?;
+        container::parse_ps_output(&res.output)
+    }
+
     /// Run the create, start, delete lifecycle of the container and return its exit status
     pub fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<Response>
     where
@@ -323,6 +501,42 @@ impl Runc {
         }
     }
 
+    /// Return the features supported by the runtime
+    pub fn features(&self) -> Result<features::Features> {
+        let args = ["features".to_string()];
+        let res = self.launch(self.command(&args)?, false)?;
+        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Generate a default spec in the `bundle` directory with `runc spec` and return it.
+    ///
+    /// With `rootless`, the spec is suitable for running as an unprivileged user.
+    pub fn spec<P>(&self, bundle: P, rootless: bool) -> Result<Spec>
+    where
+        P: AsRef<Path>,
+    {
+        let bundle = utils::abs_path_buf(bundle)?;
+        let mut args = vec![
+            "spec".to_string(),
+            "--bundle".to_string(),
+            bundle.to_string_lossy().to_string(),
+        ];
+        if rootless {
+            args.push("--rootless".to_string());
+        }
+        self.launch(self.command(&args)?, true)?;
+        let config =
+            std::fs::read(bundle.join(bundle::CONFIG_FILE)).map_err(|_| Error::SpecFileNotFound)?;
+        serde_json::from_slice(&config).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Return the version of the runtime
+    pub fn version(&self) -> Result<Version> {
+        let args = ["--version".to_string()];
+        let res = self.launch(self.command(&args)?, false)?;
+        Version::parse(&res.output)
+    }
+
     /// Update a container with the provided resource spec
     pub fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
         let (_temp_file, filename) = write_value_to_temp_file(resources)?;
@@ -335,6 +549,27 @@ impl Runc {
         self.launch(self.command(&args)?, true)?;
         Ok(())
     }
+
+    /// Update a container with the limits set in `opts`, leaving the others untouched
+    pub fn update_with(&self, id: &str, opts: &UpdateOpts) -> Result<()> {
+        let mut args = vec!["update".to_string()];
+        let _temp_file = match opts.merged_resources()? {
+            Some(resources) => {
+                let (temp_file, filename) = write_value_to_temp_file(&resources)?;
+                args.push("--resources".to_string());
+                args.push(filename);
+                args.append(&mut opts.rdt_args());
+                Some(temp_file)
+            }
+            None => {
+                args.append(&mut opts.args());
+                None
+            }
+        };
+        args.push(id.to_string());
+        self.launch(self.command(&args)?, true)?;
+        Ok(())
+    }
 }
 
 // a macro tool to cleanup the file with name $filename,
@@ -357,12 +592,22 @@ macro_rules! tc {
 #[cfg(not(feature = "async"))]
 pub trait Spawner: Debug {
     fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)>;
+
+    /// Start a long running command, like `runc events`, without waiting for it.
+    fn spawn(&self, mut cmd: Command) -> Result<std::process::Child> {
+        cmd.spawn().map_err(Error::ProcessSpawnFailed)
+    }
 }
 
 #[cfg(feature = "async")]
 #[async_trait]
 pub trait Spawner: Debug {
     async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)>;
+
+    /// Start a long running command, like `runc events`, without waiting for it.
+    fn spawn(&self, mut cmd: Command) -> Result<tokio::process::Child> {
+        cmd.spawn().map_err(Error::ProcessSpawnFailed)
+    }
 }
 
 /// Async implementation for [Runc].
@@ -371,9 +616,59 @@ pub trait Spawner: Debug {
 /// and some other utilities.
 #[cfg(feature = "async")]
 impl Runc {
+    /// Build the error of a failed command, falling back to the errors runc logged since
+    /// `log_errors` was created when stderr does not classify it.
+    async fn command_failed(
+        &self,
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+        log_errors: Option<LogReader>,
+    ) -> Error {
+        let err = Error::command_failed(status, stdout, stderr);
+        match log_errors {
+            Some(mut reader) if err.failure_kind() == Some(FailureKind::Unknown) => {
+                err.with_log_errors(&reader.read().await.unwrap_or_default())
+            }
+            _ => err,
+        }
+    }
+
     async fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
+        self.launch_with(cmd, combined_output, None, None).await
+    }
+
+    /// Launch the command, giving up once `timeout` (or the global timeout if [`None`]) elapses
+    /// or `cancel` is cancelled. A zero timeout waits forever.
+    ///
+    /// Giving up drops the pending [`Spawner::execute`] future, and [DefaultExecutor] kills the
+    /// runc process (and its process group if `set_pgid` is enabled) when that happens.
+    async fn launch_with(
+        &self,
+        cmd: Command,
+        combined_output: bool,
+        timeout: Option<Duration>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Response> {
         debug!("Execute command {:?}", cmd);
-        let (status, pid, stdout, stderr) = self.spawner.execute(cmd).await?;
+        let log_errors = self.log_errors();
+        let execute = async {
+            let timeout = timeout.unwrap_or(self.timeout);
+            if timeout.is_zero() {
+                self.spawner.execute(cmd).await
+            } else {
+                tokio::time::timeout(timeout, self.spawner.execute(cmd))
+                    .await
+                    .map_err(Error::CommandTimeout)?
+            }
+        };
+        let (status, pid, stdout, stderr) = match cancel {
+            Some(token) => tokio::select! {
+                res = execute => res?,
+                _ = token.cancelled() => return Err(Error::CommandCancelled),
+            },
+            None => execute.await?,
+        };
         if status.success() {
             let output = if combined_output {
                 stdout + stderr.as_str()
@@ -386,11 +681,9 @@ impl Runc {
                 output,
             })
         } else {
-            Err(Error::CommandFailed {
-                status,
-                stdout,
-                stderr,
-            })
+            Err(self
+                .command_failed(status, stdout, stderr, log_errors)
+                .await)
         }
     }
 
@@ -414,14 +707,15 @@ impl Runc {
         }
         args.push(id.to_string());
         let mut cmd = self.command(&args)?;
+        let (timeout, cancel) = opts.map_or((None, None), |o| (o.timeout, o.cancel.as_ref()));
         match opts {
             Some(CreateOpts { io: Some(io), .. }) => {
                 io.set(&mut cmd).map_err(Error::UnavailableIO)?;
-                let res = self.launch(cmd, true).await?;
+                let res = self.launch_with(cmd, true, timeout, cancel).await?;
                 io.close_after_start();
                 Ok(res)
             }
-            _ => self.launch(cmd, true).await,
+            _ => self.launch_with(cmd, true, timeout, cancel).await,
         }
     }
 
@@ -432,13 +726,32 @@ impl Runc {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
-        let _ = self.launch(self.command(&args)?, true).await?;
+        let (timeout, cancel) = opts.map_or((None, None), |o| (o.timeout, o.cancel.as_ref()));
+        let _ = self
+            .launch_with(self.command(&args)?, true, timeout, cancel)
+            .await?;
         Ok(())
     }
 
     /// Return an event stream of container notifications
-    pub async fn events(&self, _id: &str, _interval: &std::time::Duration) -> Result<()> {
-        Err(Error::Unimplemented("events".to_string()))
+    ///
+    /// The stream yields OOM notifications and stats collected every `interval`, and ends
+    /// when the underlying `runc events` process exits.
+    pub async fn events(
+        &self,
+        id: &str,
+        interval: &std::time::Duration,
+    ) -> Result<events::EventStream> {
+        let args = [
+            "events".to_string(),
+            format!("--interval={}ms", interval.as_millis()),
+            id.to_string(),
+        ];
+        let mut cmd = self.command(&args)?;
+        cmd.kill_on_drop(true);
+        debug!("Execute command {:?}", cmd);
+        let child = self.spawner.spawn(cmd)?;
+        events::event_stream(child)
     }
 
     /// Execute an additional process inside the container
@@ -449,21 +762,43 @@ impl Runc {
             args.append(&mut tc!(opts.args(), &f));
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
+        tc!(self.launch_exec(&args, opts).await, &f);
+        let _ = tokio::fs::remove_file(&f).await;
+        Ok(())
+    }
+
+    /// Execute an additional process inside the container, described by `cmd` and [ExecOpts]
+    /// instead of a full process spec
+    pub async fn exec_args(&self, id: &str, cmd: &[String], opts: Option<&ExecOpts>) -> Result<()> {
+        let mut args = vec!["exec".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        args.extend_from_slice(cmd);
+        self.launch_exec(&args, opts).await
+    }
+
+    async fn launch_exec(&self, args: &[String], opts: Option<&ExecOpts>) -> Result<()> {
+        let mut cmd = self.command(args)?;
+        // Without detach, runc waits for the process to exit, so the global timeout is not applied.
+        let timeout = match opts {
+            Some(opts) if opts.timeout.is_some() => opts.timeout,
+            Some(ExecOpts { detach: true, .. }) => None,
+            _ => Some(Duration::ZERO),
+        };
+        let cancel = opts.and_then(|o| o.cancel.as_ref());
         match opts {
-            Some(ExecOpts { io: Some(io), .. }) => {
-                tc!(
-                    io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string())),
-                    &f
-                );
-                tc!(self.launch(cmd, true).await, &f);
+            Some(opts) if opts.io.is_some() || !opts.preserve_fds.is_empty() => {
+                let io = PreservedFdsIo::new(opts.io.as_deref(), &opts.preserve_fds);
+                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                self.launch_with(cmd, true, timeout, cancel).await?;
                 io.close_after_start();
             }
             _ => {
-                tc!(self.launch(cmd, true).await, &f);
+                self.launch_with(cmd, true, timeout, cancel).await?;
             }
         }
-        let _ = tokio::fs::remove_file(&f).await;
         Ok(())
     }
 
@@ -475,7 +810,10 @@ impl Runc {
         }
         args.push(id.to_string());
         args.push(sig.to_string());
-        let _ = self.launch(self.command(&args)?, true).await?;
+        let (timeout, cancel) = opts.map_or((None, None), |o| (o.timeout, o.cancel.as_ref()));
+        let _ = self
+            .launch_with(self.command(&args)?, true, timeout, cancel)
+            .await?;
         Ok(())
     }
 
@@ -507,16 +845,66 @@ impl Runc {
         Ok(())
     }
 
-    pub async fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    /// Checkpoint a running container
+    pub async fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<()> {
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let (timeout, cancel) = opts.map_or((None, None), |o| (o.timeout, o.cancel.as_ref()));
+        let _ = self
+            .launch_with(self.command(&args)?, true, timeout, cancel)
+            .await?;
+        Ok(())
     }
 
-    pub async fn restore(&self) -> Result<()> {
-        Err(Error::Unimplemented("restore".to_string()))
+    /// Restore a container from a previous checkpoint
+    pub async fn restore<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&RestoreOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let mut args = vec![
+            "restore".to_string(),
+            "--bundle".to_string(),
+            utils::abs_string(bundle)?,
+        ];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        // Without detach, runc waits for the container to exit, so the global timeout is not applied.
+        let timeout = match opts {
+            Some(opts) if opts.timeout.is_some() => opts.timeout,
+            Some(RestoreOpts { detach: true, .. }) => None,
+            _ => Some(Duration::ZERO),
+        };
+        let cancel = opts.and_then(|o| o.cancel.as_ref());
+        match opts {
+            Some(RestoreOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(Error::UnavailableIO)?;
+                let res = self.launch_with(cmd, true, timeout, cancel).await?;
+                io.close_after_start();
+                Ok(res)
+            }
+            _ => self.launch_with(cmd, true, timeout, cancel).await,
+        }
     }
 
     /// List all the processes inside the container, returning their pids
     pub async fn ps(&self, id: &str) -> Result<Vec<usize>> {
+        if !self.profile.ps_json {
+            let args = ["ps".to_string(), id.to_string()];
+            let res = self.launch(self.command(&args)?, false).await?;
+            let top = container::parse_ps_output(&res.output)?;
+            return Ok(top.pids());
+        }
         let args = [
             "ps".to_string(),
             "--format=json".to_string(),
@@ -533,8 +921,23 @@ impl Runc {
         })
     }
 
+    /// List the processes inside the container as a table, passing `ps_args` to `ps`
+    pub async fn top(&self, id: &str, ps_args: &[String]) -> Result<TopResults> {
+        let mut args = vec![
+            "ps".to_string(),
+            "--format=table".to_string(),
+            id.to_string(),
+        ];
+        args.extend_from_slice(ps_args);
+        let res = self.launch(self.command(&args)?, false).await?;
+        container::parse_ps_output(&res.output)
+    }
+
     /// Run the create, start, delete lifecycle of the container and return its exit status
-    pub async fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<()>
+    ///
+    /// The global timeout is not applied as this waits for the container to exit, set one in
+    /// [CreateOpts] if needed.
+    pub async fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<Response>
     where
         P: AsRef<Path>,
     {
@@ -551,19 +954,19 @@ impl Runc {
         if let Some(CreateOpts { io: Some(io), .. }) = opts {
             io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
         };
-        let _ = self.launch(cmd, true).await?;
-        Ok(())
+        let timeout = opts.and_then(|o| o.timeout).unwrap_or(Duration::ZERO);
+        let cancel = opts.and_then(|o| o.cancel.as_ref());
+        self.launch_with(cmd, true, Some(timeout), cancel).await
     }
 
     /// Start an already created container
-    pub async fn start(&self, id: &str) -> Result<()> {
+    pub async fn start(&self, id: &str) -> Result<Response> {
         let args = vec!["start".to_string(), id.to_string()];
-        let _ = self.launch(self.command(&args)?, true).await?;
-        Ok(())
+        self.launch(self.command(&args)?, true).await
     }
 
     /// Return the state of a container
-    pub async fn state(&self, id: &str) -> Result<Vec<usize>> {
+    pub async fn state(&self, id: &str) -> Result<Container> {
         let args = vec!["state".to_string(), id.to_string()];
         let res = self.launch(self.command(&args)?, true).await?;
         serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
@@ -582,6 +985,43 @@ impl Runc {
         }
     }
 
+    /// Return the features supported by the runtime
+    pub async fn features(&self) -> Result<features::Features> {
+        let args = ["features".to_string()];
+        let res = self.launch(self.command(&args)?, false).await?;
+        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Generate a default spec in the `bundle` directory with `runc spec` and return it.
+    ///
+    /// With `rootless`, the spec is suitable for running as an unprivileged user.
+    pub async fn spec<P>(&self, bundle: P, rootless: bool) -> Result<Spec>
+    where
+        P: AsRef<Path>,
+    {
+        let bundle = utils::abs_path_buf(bundle)?;
+        let mut args = vec![
+            "spec".to_string(),
+            "--bundle".to_string(),
+            bundle.to_string_lossy().to_string(),
+        ];
+        if rootless {
+            args.push("--rootless".to_string());
+        }
+        self.launch(self.command(&args)?, true).await?;
+        let config = tokio::fs::read(bundle.join(bundle::CONFIG_FILE))
+            .await
+            .map_err(|_| Error::SpecFileNotFound)?;
+        serde_json::from_slice(&config).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Return the version of the runtime
+    pub async fn version(&self) -> Result<Version> {
+        let args = ["--version".to_string()];
+        let res = self.launch(self.command(&args)?, false).await?;
+        Version::parse(&res.output)
+    }
+
     /// Update a container with the provided resource spec
     pub async fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
         let f = write_value_to_temp_file(resources).await?;
@@ -595,15 +1035,123 @@ impl Runc {
         let _ = tokio::fs::remove_file(&f).await;
         Ok(())
     }
+
+    /// Update a container with the limits set in `opts`, leaving the others untouched
+    pub async fn update_with(&self, id: &str, opts: &UpdateOpts) -> Result<()> {
+        let mut args = vec!["update".to_string()];
+        let temp_file = match opts.merged_resources()? {
+            Some(resources) => {
+                let f = write_value_to_temp_file(&resources).await?;
+                args.push("--resources".to_string());
+                args.push(f.clone());
+                args.append(&mut opts.rdt_args());
+                Some(f)
+            }
+            None => {
+                args.append(&mut opts.args());
+                None
+            }
+        };
+        args.push(id.to_string());
+        let res = self
+            .launch_with(
+                self.command(&args)?,
+                true,
+                opts.timeout,
+                opts.cancel.as_ref(),
+            )
+            .await;
+        if let Some(f) = temp_file {
+            let _ = tokio::fs::remove_file(&f).await;
+        }
+        res?;
+        Ok(())
+    }
 }
 
+/// Write an executable shell script to be used as a fake runc binary.
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+fn fake_runc(dir: &tempfile::TempDir, script: &str) -> PathBuf {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    let path = dir.path().join("runc");
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .mode(0o755)
+        .open(&path)
+        .unwrap();
+    f.write_all(format!("#!/bin/sh\n{}", script).as_bytes())
+        .unwrap();
+    path
+}
+
+/// Fake `runc events` output with an OOM and a stats event.
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+const EVENTS_SCRIPT: &str = r#"
+echo '{"type":"oom","id":"fake-id"}'
+echo '{"type":"stats","id":"fake-id","data":{"cpu":{},"memory":{},"pids":{"current":3},"blkio":{},"hugetlb":{}}}'
+"#;
+
+/// Logs that the container does not exist to the `--log` file, and fails with a generic stderr.
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+const LOG_ERROR_SCRIPT: &str = r#"
+while [ $# -gt 0 ]; do
+    case "$1" in
+    --log) log="$2"; shift ;;
+    esac
+    shift
+done
+echo '{"level":"error","msg":"container \"fake-id\" does not exist","time":"2022-11-26T08:00:00Z"}' >> "$log"
+echo "exit status 1" >&2
+exit 1
+"#;
+
+/// An error runc logged before the command of a test ran.
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+const PREVIOUS_LOG_ERROR: &str =
+    "{\"level\":\"error\",\"msg\":\"container is paused\",\"time\":\"2022-11-25T08:00:00Z\"}\n";
+
+/// Fake youki, rejecting `--rootless` and printing `ps` as json or as a table.
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+const FAKE_YOUKI_SCRIPT: &str = r#"
+format=table
+for a in "$@"; do
+    case "$a" in
+    --version) echo "youki version 0.1.0"; echo "commit: 0.1.0-0-abcdef1"; exit 0 ;;
+    --rootless*) echo "unexpected argument '$a'" >&2; exit 1 ;;
+    --format=json) format=json ;;
+    esac
+done
+if [ "$format" = json ]; then
+    echo "[1234]"
+else
+    echo "UID PID PPID C STIME TTY TIME CMD"
+    echo "root 1234 1 0 10:00 ? 00:00:00 sleep infinity"
+fi
+"#;
+
+/// Fills the stderr pipe before emitting an event, then fails.
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+const NOISY_EVENTS_SCRIPT: &str = r#"
+head -c 1048576 /dev/zero >&2
+echo '{"type":"oom","id":"fake-id"}'
+exit 1
+"#;
+
 #[cfg(test)]
 #[cfg(all(target_os = "linux", not(feature = "async")))]
 mod tests {
     use std::sync::Arc;
 
     use super::{
-        io::{InheritedStdIo, PipedStdIo},
+        io::{FileIo, InheritedStdIo, PipedStdIo},
         *,
     };
 
@@ -628,6 +1176,13 @@ mod tests {
             .expect("unable to create runc instance")
     }
 
+    fn events_client(dir: &tempfile::TempDir) -> Runc {
+        GlobalOpts::new()
+            .command(fake_runc(dir, EVENTS_SCRIPT))
+            .build()
+            .expect("unable to create runc instance")
+    }
+
     fn dummy_process() -> Process {
         serde_json::from_str(
             "
@@ -660,6 +1215,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -689,6 +1245,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -717,6 +1274,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -728,6 +1286,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_exec_args() {
+        use std::os::unix::io::AsRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Report what the fake runc sees as fd 3 on stdout.
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, "readlink /proc/self/fd/3\n"))
+            .build()
+            .expect("unable to create runc instance");
+        let preserved = tempfile::NamedTempFile::new().unwrap();
+        let output = dir.path().join("output");
+        let io = Arc::new(FileIo::new(&output).unwrap());
+        let opts = ExecOpts::new()
+            .io(io)
+            .preserve_fd(preserved.as_file().as_raw_fd());
+        runc.exec_args("fake-id", &["true".to_string()], Some(&opts))
+            .expect("exec failed.");
+
+        let output = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(output.trim(), preserved.path().to_str().unwrap());
+    }
+
     #[test]
     fn test_delete() {
         let opts = DeleteOpts::new();
@@ -744,6 +1325,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -755,6 +1337,342 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_checkpoint() {
+        let opts = CheckpointOpts::new().leave_running(true);
+        let ok_runc = ok_client();
+        ok_runc
+            .checkpoint("fake-id", Some(&opts))
+            .expect("true failed.");
+        eprintln!("ok_runc succeeded.");
+
+        let fail_runc = fail_client();
+        match fail_runc.checkpoint("fake-id", Some(&opts)) {
+            Ok(_) => panic!("fail_runc returned exit status 0."),
+            Err(Error::CommandFailed {
+                status,
+                stdout,
+                stderr,
+                ..
+            }) => {
+                if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                    eprintln!("fail_runc succeeded.");
+                } else {
+                    panic!("unexpected outputs from fail_runc.")
+                }
+            }
+            Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_restore() {
+        let opts = RestoreOpts::new().detach(true);
+        let ok_runc = ok_client();
+        let response = ok_runc
+            .restore("fake-id", "fake-bundle", Some(&opts))
+            .expect("true failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(response.output.is_empty());
+
+        let fail_runc = fail_client();
+        match fail_runc.restore("fake-id", "fake-bundle", Some(&opts)) {
+            Ok(_) => panic!("fail_runc returned exit status 0."),
+            Err(Error::CommandFailed {
+                status,
+                stdout,
+                stderr,
+                ..
+            }) => {
+                if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                    eprintln!("fail_runc succeeded.");
+                } else {
+                    panic!("unexpected outputs from fail_runc.")
+                }
+            }
+            Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+        }
+
+        // restore with pipe Io, expect the output to be captured
+        let opts = RestoreOpts::new().io(Arc::new(PipedStdIo::new().unwrap()));
+        let echo_runc = echo_client();
+        let response = echo_runc
+            .restore("fake-id", "fake-bundle", Some(&opts))
+            .expect("echo failed.");
+        assert!(response.output.contains("restore"));
+    }
+
+    #[test]
+    fn test_log_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = fake_runc(&dir, LOG_ERROR_SCRIPT);
+        let log = dir.path().join("log.json");
+        std::fs::write(&log, PREVIOUS_LOG_ERROR).unwrap();
+
+        let runc = GlobalOpts::new()
+            .command(&command)
+            .log(&log)
+            .log_json()
+            .build()
+            .expect("unable to create runc instance");
+        assert!(runc.pause("fake-id").unwrap_err().is_not_found());
+
+        // a text log is not read
+        let runc = GlobalOpts::new()
+            .command(&command)
+            .log(&log)
+            .build()
+            .expect("unable to create runc instance");
+        assert_eq!(
+            runc.pause("fake-id").unwrap_err().failure_kind(),
+            Some(FailureKind::Unknown)
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = events_client(&dir);
+        let events = runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .expect("events failed.")
+            .collect::<Result<Vec<_>>>()
+            .expect("events stream failed.");
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0].event_type, events::EventType::Oom));
+        assert_eq!(events[0].id, "fake-id");
+        let stats = events[1].stats.as_ref().unwrap();
+        assert_eq!(stats.pids.current, Some(3));
+
+        let fail_runc = fail_client();
+        let mut events = fail_runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .expect("events failed.");
+        match events.next() {
+            Some(Err(Error::CommandFailed { status, .. })) => {
+                assert_eq!(status.code().unwrap(), 1)
+            }
+            _ => panic!("unexpected result from fail_runc"),
+        }
+        assert!(events.next().is_none());
+
+        let noisy_dir = tempfile::tempdir().unwrap();
+        let noisy_runc = GlobalOpts::new()
+            .command(fake_runc(&noisy_dir, NOISY_EVENTS_SCRIPT))
+            .build()
+            .expect("unable to create runc instance");
+        let events: Vec<_> = noisy_runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .expect("events failed.")
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].is_ok());
+        match &events[1] {
+            Err(Error::CommandFailed { stderr, .. }) => assert_eq!(stderr.len(), 1048576),
+            _ => panic!("unexpected result from noisy_runc"),
+        }
+    }
+
+    #[test]
+    fn test_top() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+echo "UID PID PPID CMD"
+echo "root 1234 1 sleep infinity"
+"#;
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, script))
+            .build()
+            .expect("unable to create runc instance");
+        let top = runc
+            .top("fake-id", &["-ef".to_string()])
+            .expect("top failed.");
+        assert_eq!(top.headers, vec!["UID", "PID", "PPID", "CMD"]);
+        assert_eq!(
+            top.processes,
+            vec![vec!["root", "1234", "1", "sleep infinity"]]
+        );
+
+        match ok_client().top("fake-id", &[]) {
+            Err(Error::TopShortResponseError) => {}
+            r => panic!("unexpected result from ok_runc: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_failure_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log.json");
+        // errors of other commands sharing the log must not be picked up
+        let script = r#"while [ $# -gt 0 ]; do [ "$1" = "--log" ] && log="$2"; shift; done
+echo '{"level":"error","msg":"container not running"}' >> "$log"
+echo '{"level":"error","msg":"container \"fake-id\" does not exist"}' >> "$log"
+echo 'container "fake-id" does not exist' >&2
+exit 1
+"#;
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, script))
+            .log(log)
+            .log_json()
+            .build()
+            .expect("unable to create runc instance");
+        let err = runc.delete("fake-id", None).unwrap_err();
+        assert!(err.is_not_found(), "unexpected error: {:?}", err);
+
+        let err = fail_client().delete("fake-id", None).unwrap_err();
+        assert_eq!(err.failure_kind(), Some(FailureKind::Unknown));
+    }
+
+    #[test]
+    fn test_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+echo "runc version 1.1.4"
+echo "commit: v1.1.4-0-g5fd4c4d1"
+echo "spec: 1.0.2-dev"
+echo "go: go1.19.3"
+"#;
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, script))
+            .build()
+            .expect("unable to create runc instance");
+        let version = runc.version().expect("version failed.");
+        assert_eq!(version.runc_version.as_deref(), Some("1.1.4"));
+        assert_eq!(version.commit.as_deref(), Some("v1.1.4-0-g5fd4c4d1"));
+        assert_eq!(version.spec_version.as_deref(), Some("1.0.2-dev"));
+
+        match ok_client().version() {
+            Err(Error::InvalidVersion) => {}
+            r => panic!("unexpected result from ok_runc: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let youki = fake_runc(&dir, FAKE_YOUKI_SCRIPT);
+        let runc = GlobalOpts::new()
+            .command(&youki)
+            .rootless(true)
+            .detect_profile()
+            .expect("unable to detect profile")
+            .build()
+            .expect("unable to create runc instance");
+        assert_eq!(runc.profile(), &profile::RuntimeProfile::youki());
+        assert_eq!(runc.ps("fake-id").expect("ps failed."), vec![1234]);
+
+        // the runc profile passes flags the fake youki does not accept
+        let runc = GlobalOpts::new()
+            .command(&youki)
+            .rootless(true)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(runc.ps("fake-id").is_err());
+
+        // without `ps --format json`, the pids are parsed from the table
+        let runc = GlobalOpts::new()
+            .command(&youki)
+            .profile(profile::RuntimeProfile {
+                ps_json: false,
+                ..profile::RuntimeProfile::youki()
+            })
+            .build()
+            .expect("unable to create runc instance");
+        assert_eq!(runc.ps("fake-id").expect("ps failed."), vec![1234]);
+
+        let err = GlobalOpts::new()
+            .command(ok_client().command)
+            .detect_profile()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidVersion));
+    }
+
+    #[test]
+    fn test_spec() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+rootless=false
+while [ $# -gt 0 ]; do
+    case "$1" in
+    --bundle) bundle="$2"; shift ;;
+    --rootless) rootless=true ;;
+    esac
+    shift
+done
+echo "{\"ociVersion\":\"1.0.2-dev\",\"hostname\":\"$rootless\"}" > "$bundle/config.json"
+"#;
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, script))
+            .build()
+            .expect("unable to create runc instance");
+        let bundle = tempfile::tempdir().unwrap();
+        let spec = runc.spec(bundle.path(), true).expect("spec failed.");
+        assert_eq!(spec.hostname().as_deref(), Some("true"));
+
+        match ok_client().spec(tempfile::tempdir().unwrap().path(), false) {
+            Err(Error::SpecFileNotFound) => {}
+            r => panic!("unexpected result from ok_runc: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_update_with() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let script = format!(
+            r#"
+echo "$@" > {out}
+while [ $# -gt 0 ]; do
+    [ "$1" = "--resources" ] && cat "$2" >> {out}
+    shift
+done
+"#,
+            out = out.display()
+        );
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, &script))
+            .build()
+            .expect("unable to create runc instance");
+
+        let opts = UpdateOpts::new().memory(1024).pids_limit(10);
+        runc.update_with("fake-id", &opts).expect("update failed.");
+        let output = std::fs::read_to_string(&out).unwrap();
+        assert!(output
+            .trim()
+            .ends_with(" update --memory 1024 --pids-limit 10 fake-id"));
+
+        let opts = opts
+            .resources(LinuxResources::default())
+            .l3_cache_schema("L3:0=ff");
+        runc.update_with("fake-id", &opts).expect("update failed.");
+        let output = std::fs::read_to_string(&out).unwrap();
+        let (args, resources) = output.split_once('\n').unwrap();
+        assert!(args.contains(" update --resources "));
+        assert!(args.ends_with(" --l3-cache-schema L3:0=ff fake-id"));
+        let resources: LinuxResources = serde_json::from_str(resources).unwrap();
+        assert_eq!(resources.memory().unwrap().limit(), Some(1024));
+        assert_eq!(resources.pids().unwrap().limit(), 10);
+    }
+
+    #[test]
+    fn test_features() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+echo '{"ociVersionMin":"1.0.0","ociVersionMax":"1.1.0","hooks":["prestart"],"linux":{"cgroup":{"v2":true}}}'
+"#;
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, script))
+            .build()
+            .expect("unable to create runc instance");
+        let features = runc.features().expect("features failed.");
+        assert_eq!(features.oci_version_min.as_deref(), Some("1.0.0"));
+        assert_eq!(features.hooks, Some(vec!["prestart".to_string()]));
+        let cgroup = features.linux.unwrap().cgroup.unwrap();
+        assert_eq!(cgroup.v2, Some(true));
+        assert_eq!(cgroup.v1, None);
+    }
+
     #[test]
     fn test_output() {
         // test create cmd with inherit Io, expect empty cmd output
@@ -839,6 +1757,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -870,6 +1789,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -909,6 +1829,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -944,6 +1865,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -958,6 +1880,194 @@ mod tests {
         .expect("tokio spawn falied.");
     }
 
+    #[tokio::test]
+    async fn test_async_restore() {
+        let opts = RestoreOpts::new().detach(true);
+        let ok_runc = ok_client();
+        let response = ok_runc
+            .restore("fake-id", "fake-bundle", Some(&opts))
+            .await
+            .expect("true failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+
+        let fail_runc = fail_client();
+        match fail_runc
+            .checkpoint("fake-id", Some(&CheckpointOpts::new()))
+            .await
+        {
+            Ok(_) => panic!("fail_runc returned exit status 0."),
+            Err(Error::CommandFailed { status, .. }) => {
+                assert_eq!(status.code().unwrap(), 1);
+            }
+            Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"
+echo '{"id":"fake-id","pid":1000,"status":"running","bundle":"/b","rootfs":"/b/rootfs","created":1431684000,"annotations":{}}'
+"#;
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, script))
+            .build()
+            .expect("unable to create runc instance");
+        let container = runc.state("fake-id").await.expect("state failed.");
+        assert_eq!(container.id, "fake-id");
+        assert_eq!(container.pid, 1000);
+        assert_eq!(container.status, "running");
+    }
+
+    #[tokio::test]
+    async fn test_async_events() {
+        use futures::StreamExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, EVENTS_SCRIPT))
+            .build()
+            .expect("unable to create runc instance");
+        let events = runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .await
+            .expect("events failed.")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(events.len(), 2);
+        let oom = events[0].as_ref().unwrap();
+        assert!(matches!(oom.event_type, events::EventType::Oom));
+        let stats = events[1].as_ref().unwrap().stats.as_ref().unwrap();
+        assert_eq!(stats.pids.current, Some(3));
+
+        let fail_runc = fail_client();
+        let mut events = fail_runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .await
+            .expect("events failed.");
+        match events.next().await {
+            Some(Err(Error::CommandFailed { status, .. })) => {
+                assert_eq!(status.code().unwrap(), 1)
+            }
+            _ => panic!("unexpected result from fail_runc"),
+        }
+        assert!(events.next().await.is_none());
+
+        let noisy_dir = tempfile::tempdir().unwrap();
+        let noisy_runc = GlobalOpts::new()
+            .command(fake_runc(&noisy_dir, NOISY_EVENTS_SCRIPT))
+            .build()
+            .expect("unable to create runc instance");
+        let events = noisy_runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .await
+            .expect("events failed.")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(events.len(), 2);
+        assert!(events[0].is_ok());
+        match &events[1] {
+            Err(Error::CommandFailed { stderr, .. }) => assert_eq!(stderr.len(), 1048576),
+            _ => panic!("unexpected result from noisy_runc"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_log_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log.json");
+        std::fs::write(&log, PREVIOUS_LOG_ERROR).unwrap();
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, LOG_ERROR_SCRIPT))
+            .log(&log)
+            .log_json()
+            .build()
+            .expect("unable to create runc instance");
+        assert!(runc.pause("fake-id").await.unwrap_err().is_not_found());
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"sleep 30 &
+echo $! > "$(dirname "$0")/child"
+wait
+"#;
+        let mut opts = GlobalOpts::new()
+            .command(fake_runc(&dir, script))
+            .set_pgid(true);
+        opts.timeout(200);
+        let runc = opts.build().expect("unable to create runc instance");
+
+        match runc.delete("fake-id", None).await {
+            Err(Error::CommandTimeout(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // the whole process group is killed, including the background sleep
+        let child = std::fs::read_to_string(dir.path().join("child")).unwrap();
+        let stat = format!("/proc/{}/stat", child.trim());
+        let mut killed = false;
+        for _ in 0..50 {
+            match std::fs::read_to_string(&stat) {
+                Ok(s) if !s.contains(") Z ") => {
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await
+                }
+                _ => {
+                    killed = true;
+                    break;
+                }
+            }
+        }
+        assert!(killed, "child of runc is still running");
+
+        // per-call timeout overrides the global one
+        let mut opts = GlobalOpts::new().command(fake_runc(&dir, "sleep 30\n"));
+        opts.timeout(0);
+        let runc = opts.build().expect("unable to create runc instance");
+        let kill_opts = KillOpts::new().timeout(std::time::Duration::from_millis(100));
+        match runc.kill("fake-id", 9, Some(&kill_opts)).await {
+            Err(Error::CommandTimeout(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, FAKE_YOUKI_SCRIPT))
+            .rootless(true)
+            .detect_profile()
+            .await
+            .expect("unable to detect profile")
+            .build()
+            .expect("unable to create runc instance");
+        assert_eq!(runc.profile(), &profile::RuntimeProfile::youki());
+        assert_eq!(runc.ps("fake-id").await.expect("ps failed."), vec![1234]);
+    }
+
+    #[tokio::test]
+    async fn test_async_cancel() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = GlobalOpts::new()
+            .command(fake_runc(&dir, "sleep 30\n"))
+            .build()
+            .expect("unable to create runc instance");
+        let token = CancellationToken::new();
+        let opts = DeleteOpts::new().cancel(token.clone());
+        let cancel = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            token.cancel();
+        });
+        match runc.delete("fake-id", Some(&opts)).await {
+            Err(Error::CommandCancelled) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        cancel.await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_async_output() {
         // test create cmd with inherit Io, expect empty cmd output
@@ -985,23 +2095,129 @@ mod tests {
     }
 }
 
+/// Compile-time check that every public [Runc] method has the same signature in the sync and
+/// async builds, apart from `async` itself.
+///
+/// `events()` is left out on purpose: it returns an iterator in the sync build and a stream in
+/// the async build.
+#[cfg(test)]
+#[allow(dead_code)]
+mod parity_tests {
+    use super::*;
+
+    #[cfg(not(feature = "async"))]
+    macro_rules! call {
+        ($e:expr) => {
+            $e
+        };
+    }
+
+    #[cfg(feature = "async")]
+    macro_rules! call {
+        ($e:expr) => {
+            $e.await
+        };
+    }
+
+    macro_rules! signatures {
+        ($runc:ident, $process:ident, $resources:ident) => {{
+            let _: Result<Response> = call!($runc.create("id", "bundle", None::<&CreateOpts>));
+            let _: Result<()> = call!($runc.delete("id", None::<&DeleteOpts>));
+            let _: Result<()> = call!($runc.exec("id", $process, None::<&ExecOpts>));
+            let _: Result<()> = call!($runc.exec_args("id", &[], None::<&ExecOpts>));
+            let _: Result<()> = call!($runc.kill("id", 9, None::<&KillOpts>));
+            let _: Result<Vec<Container>> = call!($runc.list());
+            let _: Result<()> = call!($runc.pause("id"));
+            let _: Result<()> = call!($runc.resume("id"));
+            let _: Result<()> = call!($runc.checkpoint("id", None::<&CheckpointOpts>));
+            let _: Result<Response> = call!($runc.restore("id", "bundle", None::<&RestoreOpts>));
+            let _: Result<Vec<usize>> = call!($runc.ps("id"));
+            let _: Result<TopResults> = call!($runc.top("id", &[]));
+            let _: Result<Response> = call!($runc.run("id", "bundle", None::<&CreateOpts>));
+            let _: Result<Response> = call!($runc.start("id"));
+            let _: Result<Container> = call!($runc.state("id"));
+            let _: Result<events::Stats> = call!($runc.stats("id"));
+            let _: Result<()> = call!($runc.update("id", $resources));
+            let _: Result<()> = call!($runc.update_with("id", &UpdateOpts::new()));
+            let _: Result<Spec> = call!($runc.spec("bundle", false));
+            let _: Result<features::Features> = call!($runc.features());
+            let _: Result<Version> = call!($runc.version());
+        }};
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn check_signatures(runc: &Runc, process: &Process, resources: &LinuxResources) {
+        signatures!(runc, process, resources)
+    }
+
+    #[cfg(feature = "async")]
+    async fn check_signatures(runc: &Runc, process: &Process, resources: &LinuxResources) {
+        signatures!(runc, process, resources)
+    }
+}
+
 #[derive(Debug)]
 pub struct DefaultExecutor {}
 
+/// Kills a spawned runc process, and the process group it leads if any, when dropped.
+///
+/// [Spawner] implementations wait for the child through the guard, so that a command abandoned
+/// because of a timeout or cancellation does not keep running. The guard owns the [Child], and
+/// only signals it while it has not been reaped, so the signal can't reach a reused pid.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct ChildGuard {
+    child: Child,
+}
+
+#[cfg(feature = "async")]
+impl ChildGuard {
+    pub fn new(child: Child) -> Self {
+        Self { child }
+    }
+
+    /// Wait for the child to exit, see [Child::wait].
+    pub async fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        self.child.wait().await
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        // `try_wait` reaps the child if it has exited, otherwise the pid is still ours.
+        if let Ok(None) = self.child.try_wait() {
+            if let Some(pid) = self.child.id() {
+                // Only succeeds if the child was started with `set_pgid`.
+                let pid = nix::unistd::Pid::from_raw(pid as i32);
+                let _ = nix::sys::signal::killpg(pid, nix::sys::signal::SIGKILL);
+            }
+            let _ = self.child.start_kill();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+async fn read_all<R: AsyncRead + Unpin>(reader: Option<R>) -> std::io::Result<String> {
+    let mut buf = Vec::new();
+    if let Some(mut reader) = reader {
+        reader.read_to_end(&mut buf).await?;
+    }
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
 #[cfg(feature = "async")]
 #[async_trait]
 impl Spawner for DefaultExecutor {
     async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
         let mut cmd = cmd;
-        let child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
+        let mut child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
         let pid = child.id().unwrap();
-        let result = child
-            .wait_with_output()
-            .await
-            .map_err(Error::InvalidCommand)?;
-        let status = result.status;
-        let stdout = String::from_utf8_lossy(&result.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&result.stderr).to_string();
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let mut guard = ChildGuard::new(child);
+        let (status, stdout, stderr) =
+            tokio::try_join!(guard.wait(), read_all(stdout), read_all(stderr))
+                .map_err(Error::InvalidCommand)?;
         Ok((status, pid, stdout, stderr))
     }
 }
@@ -0,0 +1,339 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Reader for the log file written by runc with `--log-format json`.
+//!
+//! Each line of the file is a logrus entry such as
+//! `{"level":"error","msg":"container does not exist","time":"2022-11-26T08:00:00Z"}`.
+
+use std::{
+    fmt::{self, Display},
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Log level of an entry, as emitted by logrus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    #[serde(alias = "warn")]
+    Warning,
+    Error,
+    Fatal,
+    Panic,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+            Level::Panic => "panic",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl From<Level> for log::Level {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Trace => log::Level::Trace,
+            Level::Debug => log::Level::Debug,
+            Level::Info => log::Level::Info,
+            Level::Warning => log::Level::Warn,
+            Level::Error | Level::Fatal | Level::Panic => log::Level::Error,
+        }
+    }
+}
+
+/// A single entry of the runc log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub level: Level,
+    pub msg: String,
+    /// RFC 3339 timestamp of the entry.
+    #[serde(default)]
+    pub time: String,
+}
+
+impl Entry {
+    /// Parse a single line of the log.
+    pub fn parse(line: &str) -> Result<Entry, Error> {
+        serde_json::from_str(line).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Emit the entry through the `log` crate at the matching level.
+    pub fn forward(&self) {
+        log::log!(target: "runc", self.level.into(), "{}", self.msg.trim());
+    }
+}
+
+/// Parse the complete lines of `buf`, returning the entries and the number of bytes consumed.
+///
+/// A trailing line without a newline is left for the next read, as runc may still be writing it.
+/// Lines that are not valid entries, such as a Go panic trace, are skipped.
+fn parse_lines(buf: &[u8], min_level: Level) -> (Vec<Entry>, usize) {
+    let consumed = match buf.iter().rposition(|b| *b == b'\n') {
+        Some(pos) => pos + 1,
+        None => return (Vec::new(), 0),
+    };
+    let entries = String::from_utf8_lossy(&buf[..consumed])
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| match Entry::parse(l) {
+            Ok(e) => Some(e),
+            Err(e) => {
+                debug!("skip invalid runc log line {:?}: {}", l, e);
+                None
+            }
+        })
+        .filter(|e| e.level >= min_level)
+        .collect();
+    (entries, consumed)
+}
+
+/// Read the entries written after `offset`, returning them with the offset to read from next.
+#[cfg(not(feature = "async"))]
+fn read_from(path: &Path, offset: u64, min_level: Level) -> std::io::Result<(Vec<Entry>, u64)> {
+    use std::io::{Read, Seek};
+
+    let mut f = std::fs::File::open(path)?;
+    // start over if the log has been truncated meanwhile
+    let offset = if f.metadata()?.len() < offset {
+        0
+    } else {
+        offset
+    };
+    f.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    f.read_to_end(&mut buf)?;
+    let (entries, consumed) = parse_lines(&buf, min_level);
+    Ok((entries, offset + consumed as u64))
+}
+
+/// Tails a runc log file, returning the entries appended since the previous read.
+#[derive(Debug, Clone)]
+pub struct LogReader {
+    path: PathBuf,
+    offset: u64,
+    min_level: Level,
+}
+
+impl LogReader {
+    /// Create a reader starting from the beginning of the file.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            offset: 0,
+            min_level: Level::Trace,
+        }
+    }
+
+    /// Create a reader that skips the entries already in the file.
+    pub fn tail(path: impl AsRef<Path>) -> Self {
+        let offset = std::fs::metadata(path.as_ref()).map_or(0, |m| m.len());
+        Self {
+            offset,
+            ..Self::new(path)
+        }
+    }
+
+    /// Only return entries at `level` or above.
+    pub fn min_level(mut self, level: Level) -> Self {
+        self.min_level = level;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the entries appended since the previous read.
+    ///
+    /// A missing file is treated as empty, since runc creates it lazily.
+    #[cfg(not(feature = "async"))]
+    pub fn read(&mut self) -> Result<Vec<Entry>, Error> {
+        match read_from(&self.path, self.offset, self.min_level) {
+            Ok((entries, offset)) => {
+                self.offset = offset;
+                Ok(entries)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(Error::FileSystemError(e)),
+        }
+    }
+
+    /// Read the entries appended since the previous read.
+    ///
+    /// A missing file is treated as empty, since runc creates it lazily.
+    #[cfg(feature = "async")]
+    pub async fn read(&mut self) -> Result<Vec<Entry>, Error> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let mut f = match tokio::fs::File::open(&self.path).await {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::FileSystemError(e)),
+        };
+        let len = f.metadata().await.map_err(Error::FileSystemError)?.len();
+        if len < self.offset {
+            self.offset = 0;
+        }
+        f.seek(SeekFrom::Start(self.offset))
+            .await
+            .map_err(Error::FileSystemError)?;
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf)
+            .await
+            .map_err(Error::FileSystemError)?;
+        let (entries, consumed) = parse_lines(&buf, self.min_level);
+        self.offset += consumed as u64;
+        Ok(entries)
+    }
+
+    /// Read the entries appended since the previous read and forward them to the `log` crate.
+    #[cfg(not(feature = "async"))]
+    pub fn forward(&mut self) -> Result<usize, Error> {
+        let entries = self.read()?;
+        entries.iter().for_each(Entry::forward);
+        Ok(entries.len())
+    }
+
+    /// Read the entries appended since the previous read and forward them to the `log` crate.
+    #[cfg(feature = "async")]
+    pub async fn forward(&mut self) -> Result<usize, Error> {
+        let entries = self.read().await?;
+        entries.iter().for_each(Entry::forward);
+        Ok(entries.len())
+    }
+}
+
+/// Return the last error entry of the log file.
+#[cfg(not(feature = "async"))]
+pub fn last_error(path: impl AsRef<Path>) -> Result<Option<Entry>, Error> {
+    let mut reader = LogReader::new(path).min_level(Level::Error);
+    Ok(reader.read()?.pop())
+}
+
+/// Return the last error entry of the log file.
+#[cfg(feature = "async")]
+pub async fn last_error(path: impl AsRef<Path>) -> Result<Option<Entry>, Error> {
+    let mut reader = LogReader::new(path).min_level(Level::Error);
+    Ok(reader.read().await?.pop())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const LOG: &str = "\
+{\"level\":\"info\",\"msg\":\"hello world\",\"time\":\"2022-11-25T08:00:00Z\"}
+{\"level\":\"error\",\"msg\":\"failed error\",\"time\":\"2022-11-26T08:00:00Z\"}
+not a json line
+{\"level\":\"warning\",\"msg\":\"careful\",\"time\":\"2022-11-27T08:00:00Z\"}
+";
+
+    #[test]
+    fn parse_test() {
+        let entry = Entry::parse(LOG.lines().next().unwrap()).unwrap();
+        assert_eq!(entry.level, Level::Info);
+        assert_eq!(entry.msg, "hello world");
+        assert_eq!(entry.time, "2022-11-25T08:00:00Z");
+        assert_eq!(
+            Entry::parse("{\"level\":\"warn\",\"msg\":\"\"}")
+                .unwrap()
+                .level,
+            Level::Warning
+        );
+        assert!(Entry::parse("not a json line").is_err());
+
+        let (entries, consumed) = parse_lines(LOG.as_bytes(), Level::Warning);
+        assert_eq!(consumed, LOG.len());
+        let levels: Vec<_> = entries.iter().map(|e| e.level).collect();
+        assert_eq!(levels, vec![Level::Error, Level::Warning]);
+
+        // an incomplete trailing line is left for the next read
+        let (entries, consumed) =
+            parse_lines(b"{\"level\":\"info\",\"msg\":\"a\"}\n{\"lev", Level::Trace);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(consumed, 27);
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn reader_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.json");
+        let mut reader = LogReader::new(&path);
+        assert!(reader.read().unwrap().is_empty());
+
+        std::fs::write(&path, LOG).unwrap();
+        assert_eq!(reader.read().unwrap().len(), 3);
+        assert!(reader.read().unwrap().is_empty());
+
+        let mut tail = LogReader::tail(&path).min_level(Level::Error);
+        let mut f = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        write!(f, "{{\"level\":\"info\",\"msg\":\"a\"}}\n{{\"level\":\"error\",\"msg\":\"b\"}}\n{{\"level\"").unwrap();
+        let entries = tail.read().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].msg, "b");
+        writeln!(f, ":\"fatal\",\"msg\":\"c\"}}").unwrap();
+        assert_eq!(tail.read().unwrap()[0].msg, "c");
+        assert_eq!(reader.read().unwrap().len(), 3);
+
+        assert_eq!(last_error(&path).unwrap().unwrap().msg, "c");
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn reader_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.json");
+        let mut reader = LogReader::new(&path);
+        assert!(reader.read().await.unwrap().is_empty());
+
+        std::fs::write(&path, LOG).unwrap();
+        assert_eq!(reader.read().await.unwrap().len(), 3);
+        assert!(reader.read().await.unwrap().is_empty());
+
+        let mut tail = LogReader::tail(&path).min_level(Level::Error);
+        let mut f = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        writeln!(f, "{{\"level\":\"error\",\"msg\":\"b\"}}").unwrap();
+        assert_eq!(tail.read().await.unwrap()[0].msg, "b");
+
+        assert_eq!(last_error(&path).await.unwrap().unwrap().msg, "b");
+    }
+}
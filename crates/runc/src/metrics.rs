@@ -0,0 +1,485 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Conversion of the stats reported by `runc events --stats` to the cgroup metrics that shims
+//! return to containerd, as the v1 [Metrics] or the v2 [v2::Metrics] message.
+
+use std::collections::BTreeMap;
+
+use containerd_shim_protos::cgroups::{
+    metrics::{
+        BlkIOEntry, BlkIOStat, CPUStat, CPUUsage, HugetlbStat, MemoryEntry, MemoryStat, Metrics,
+        PidsStat, Throttle,
+    },
+    v2::metrics as v2,
+};
+
+use crate::events;
+
+impl From<events::Stats> for Metrics {
+    fn from(stats: events::Stats) -> Self {
+        let mut metrics = Metrics::new();
+        metrics.set_cpu(stats.cpu.into());
+        metrics.set_memory(stats.memory.into());
+        metrics.set_pids(stats.pids.into());
+        metrics.set_blkio(stats.block_io.into());
+        if let Some(huge_tlb) = stats.huge_tlb {
+            let mut hugetlb: Vec<_> = huge_tlb
+                .into_iter()
+                .map(|(pagesize, h)| {
+                    let mut stat = HugetlbStat::new();
+                    stat.set_pagesize(pagesize);
+                    stat.set_usage(h.usage.unwrap_or_default());
+                    stat.set_max(h.max.unwrap_or_default());
+                    stat.set_failcnt(h.fail_count);
+                    stat
+                })
+                .collect();
+            hugetlb.sort_by(|a, b| a.pagesize.cmp(&b.pagesize));
+            metrics.set_hugetlb(hugetlb);
+        }
+        metrics
+    }
+}
+
+impl From<events::Cpu> for CPUStat {
+    fn from(cpu: events::Cpu) -> Self {
+        let mut stat = CPUStat::new();
+        if let Some(u) = cpu.usage {
+            let mut usage = CPUUsage::new();
+            usage.set_total(u.total.unwrap_or_default());
+            usage.set_kernel(u.kernel);
+            usage.set_user(u.user);
+            usage.set_per_cpu(u.per_cpu.unwrap_or_default());
+            stat.set_usage(usage);
+        }
+        if let Some(t) = cpu.throttling {
+            let mut throttle = Throttle::new();
+            throttle.set_periods(t.periods.unwrap_or_default());
+            throttle.set_throttled_periods(t.throtted_periods.unwrap_or_default());
+            throttle.set_throttled_time(t.throtted_time.unwrap_or_default());
+            stat.set_throttling(throttle);
+        }
+        stat
+    }
+}
+
+impl From<events::MemoryEntry> for MemoryEntry {
+    fn from(entry: events::MemoryEntry) -> Self {
+        let mut e = MemoryEntry::new();
+        e.set_limit(entry.limit);
+        e.set_usage(entry.usage.unwrap_or_default());
+        e.set_max(entry.max.unwrap_or_default());
+        e.set_failcnt(entry.fail_count);
+        e
+    }
+}
+
+impl From<events::Memory> for MemoryStat {
+    /// The counters come from the raw `memory.stat` of runc. Cgroup v2 names are used when the
+    /// cgroup v1 ones are missing, such as `anon` for `rss`.
+    fn from(memory: events::Memory) -> Self {
+        let raw = memory.raw.unwrap_or_default();
+        let get = |keys: &[&str]| -> u64 {
+            keys.iter()
+                .find_map(|k| raw.get(*k).copied())
+                .unwrap_or_default()
+        };
+        let mut stat = MemoryStat::new();
+        stat.set_cache(memory.cache.unwrap_or_else(|| get(&["cache", "file"])));
+        stat.set_rss(get(&["rss", "anon"]));
+        stat.set_rss_huge(get(&["rss_huge", "anon_thp"]));
+        stat.set_mapped_file(get(&["mapped_file", "file_mapped"]));
+        stat.set_dirty(get(&["dirty", "file_dirty"]));
+        stat.set_writeback(get(&["writeback", "file_writeback"]));
+        stat.set_pg_pg_in(get(&["pgpgin"]));
+        stat.set_pg_pg_out(get(&["pgpgout"]));
+        stat.set_pg_fault(get(&["pgfault"]));
+        stat.set_pg_maj_fault(get(&["pgmajfault"]));
+        stat.set_inactive_anon(get(&["inactive_anon"]));
+        stat.set_active_anon(get(&["active_anon"]));
+        stat.set_inactive_file(get(&["inactive_file"]));
+        stat.set_active_file(get(&["active_file"]));
+        stat.set_unevictable(get(&["unevictable"]));
+        stat.set_hierarchical_memory_limit(get(&["hierarchical_memory_limit"]));
+        stat.set_hierarchical_swap_limit(get(&["hierarchical_memsw_limit"]));
+        stat.set_total_cache(get(&["total_cache"]));
+        stat.set_total_rss(get(&["total_rss"]));
+        stat.set_total_rss_huge(get(&["total_rss_huge"]));
+        stat.set_total_mapped_file(get(&["total_mapped_file"]));
+        stat.set_total_dirty(get(&["total_dirty"]));
+        stat.set_total_writeback(get(&["total_writeback"]));
+        stat.set_total_pg_pg_in(get(&["total_pgpgin"]));
+        stat.set_total_pg_pg_out(get(&["total_pgpgout"]));
+        stat.set_total_pg_fault(get(&["total_pgfault"]));
+        stat.set_total_pg_maj_fault(get(&["total_pgmajfault"]));
+        stat.set_total_inactive_anon(get(&["total_inactive_anon"]));
+        stat.set_total_active_anon(get(&["total_active_anon"]));
+        stat.set_total_inactive_file(get(&["total_inactive_file"]));
+        stat.set_total_active_file(get(&["total_active_file"]));
+        stat.set_total_unevictable(get(&["total_unevictable"]));
+        if let Some(usage) = memory.usage {
+            stat.set_usage(usage.into());
+        }
+        if let Some(swap) = memory.swap {
+            stat.set_swap(swap.into());
+        }
+        if let Some(kernel) = memory.kernel {
+            stat.set_kernel(kernel.into());
+        }
+        if let Some(kernel_tcp) = memory.kernel_tcp {
+            stat.set_kernel_tcp(kernel_tcp.into());
+        }
+        stat
+    }
+}
+
+impl From<events::Pids> for PidsStat {
+    fn from(pids: events::Pids) -> Self {
+        let mut stat = PidsStat::new();
+        stat.set_current(pids.current.unwrap_or_default());
+        stat.set_limit(pids.limit.unwrap_or_default());
+        stat
+    }
+}
+
+fn blkio_entries(entries: Option<Vec<events::BlkIOEntry>>) -> Vec<BlkIOEntry> {
+    entries
+        .unwrap_or_default()
+        .into_iter()
+        .map(|entry| {
+            let mut e = BlkIOEntry::new();
+            e.set_major(entry.major.unwrap_or_default());
+            e.set_minor(entry.minor.unwrap_or_default());
+            e.set_op(entry.op.unwrap_or_default());
+            e.set_value(entry.value.unwrap_or_default());
+            e
+        })
+        .collect()
+}
+
+impl From<events::BlkIO> for BlkIOStat {
+    fn from(blkio: events::BlkIO) -> Self {
+        let mut stat = BlkIOStat::new();
+        stat.set_io_service_bytes_recursive(blkio_entries(blkio.io_service_bytes_recursive));
+        stat.set_io_serviced_recursive(blkio_entries(blkio.io_serviced_recursive));
+        stat.set_io_queued_recursive(blkio_entries(blkio.io_queued_recursive));
+        stat.set_io_service_time_recursive(blkio_entries(blkio.io_service_time_recursive));
+        stat.set_io_wait_time_recursive(blkio_entries(blkio.io_wait_time_recursive));
+        stat.set_io_merged_recursive(blkio_entries(blkio.io_merged_recursive));
+        stat.set_io_time_recursive(blkio_entries(blkio.io_time_recursive));
+        stat.set_sectors_recursive(blkio_entries(blkio.sectors_recursive));
+        stat
+    }
+}
+
+impl From<events::Stats> for v2::Metrics {
+    /// runc reports the stats of a cgroup v2 in the v1 layout: cpu times in nanoseconds, the
+    /// raw `memory.stat`, swap including memory, and `io.stat` as blkio entries.
+    ///
+    /// Unlimited values stay `u64::MAX`, as containerd reports them on cgroup v2.
+    fn from(stats: events::Stats) -> Self {
+        let mut metrics = v2::Metrics::new();
+
+        let mut pids = v2::PidsStat::new();
+        pids.set_current(stats.pids.current.unwrap_or_default());
+        pids.set_limit(stats.pids.limit.unwrap_or_default());
+        metrics.set_pids(pids);
+
+        let mut cpu = v2::CPUStat::new();
+        if let Some(u) = stats.cpu.usage {
+            cpu.set_usage_usec(u.total.unwrap_or_default() / 1000);
+            cpu.set_user_usec(u.user / 1000);
+            cpu.set_system_usec(u.kernel / 1000);
+        }
+        if let Some(t) = stats.cpu.throttling {
+            cpu.set_nr_periods(t.periods.unwrap_or_default());
+            cpu.set_nr_throttled(t.throtted_periods.unwrap_or_default());
+            cpu.set_throttled_usec(t.throtted_time.unwrap_or_default() / 1000);
+        }
+        metrics.set_cpu(cpu);
+
+        let raw = stats.memory.raw.unwrap_or_default();
+        let get = |k: &str| raw.get(k).copied().unwrap_or_default();
+        let mut memory = v2::MemoryStat::new();
+        memory.set_anon(get("anon"));
+        memory.set_file(get("file"));
+        memory.set_kernel_stack(get("kernel_stack"));
+        memory.set_slab(get("slab"));
+        memory.set_sock(get("sock"));
+        memory.set_shmem(get("shmem"));
+        memory.set_file_mapped(get("file_mapped"));
+        memory.set_file_dirty(get("file_dirty"));
+        memory.set_file_writeback(get("file_writeback"));
+        memory.set_anon_thp(get("anon_thp"));
+        memory.set_inactive_anon(get("inactive_anon"));
+        memory.set_active_anon(get("active_anon"));
+        memory.set_inactive_file(get("inactive_file"));
+        memory.set_active_file(get("active_file"));
+        memory.set_unevictable(get("unevictable"));
+        memory.set_slab_reclaimable(get("slab_reclaimable"));
+        memory.set_slab_unreclaimable(get("slab_unreclaimable"));
+        memory.set_pgfault(get("pgfault"));
+        memory.set_pgmajfault(get("pgmajfault"));
+        memory.set_workingset_refault(get("workingset_refault"));
+        memory.set_workingset_activate(get("workingset_activate"));
+        memory.set_workingset_nodereclaim(get("workingset_nodereclaim"));
+        memory.set_pgrefill(get("pgrefill"));
+        memory.set_pgscan(get("pgscan"));
+        memory.set_pgsteal(get("pgsteal"));
+        memory.set_pgactivate(get("pgactivate"));
+        memory.set_pgdeactivate(get("pgdeactivate"));
+        memory.set_pglazyfree(get("pglazyfree"));
+        memory.set_pglazyfreed(get("pglazyfreed"));
+        memory.set_thp_fault_alloc(get("thp_fault_alloc"));
+        memory.set_thp_collapse_alloc(get("thp_collapse_alloc"));
+        let (usage, limit) = stats
+            .memory
+            .usage
+            .map_or((0, 0), |u| (u.usage.unwrap_or_default(), u.limit));
+        memory.set_usage(usage);
+        memory.set_usage_limit(limit);
+        if let Some(swap) = stats.memory.swap {
+            // runc adds memory to swap, like memsw of cgroup v1, and to limited swap only. The
+            // addition wraps when memory is unlimited, so it is undone the same way.
+            memory.set_swap_usage(swap.usage.unwrap_or_default().saturating_sub(usage));
+            memory.set_swap_limit(if swap.limit == u64::MAX {
+                u64::MAX
+            } else {
+                swap.limit.wrapping_sub(limit)
+            });
+        }
+        metrics.set_memory(memory);
+
+        let mut devices: BTreeMap<(u64, u64), v2::IOEntry> = BTreeMap::new();
+        let mut add = |entries: Option<Vec<events::BlkIOEntry>>, bytes: bool| {
+            for entry in entries.unwrap_or_default() {
+                let major = entry.major.unwrap_or_default();
+                let minor = entry.minor.unwrap_or_default();
+                let io = devices.entry((major, minor)).or_insert_with(|| {
+                    let mut io = v2::IOEntry::new();
+                    io.set_major(major);
+                    io.set_minor(minor);
+                    io
+                });
+                let value = entry.value.unwrap_or_default();
+                match (entry.op.unwrap_or_default().to_lowercase().as_str(), bytes) {
+                    ("read", true) => io.set_rbytes(value),
+                    ("write", true) => io.set_wbytes(value),
+                    ("read", false) => io.set_rios(value),
+                    ("write", false) => io.set_wios(value),
+                    _ => {}
+                }
+            }
+        };
+        add(stats.block_io.io_service_bytes_recursive, true);
+        add(stats.block_io.io_serviced_recursive, false);
+        let mut io = v2::IOStat::new();
+        io.set_usage(devices.into_values().collect());
+        metrics.set_io(io);
+
+        if let Some(huge_tlb) = stats.huge_tlb {
+            let mut hugetlb: Vec<_> = huge_tlb
+                .into_iter()
+                .map(|(pagesize, h)| {
+                    let mut stat = v2::HugeTlbStat::new();
+                    stat.set_pagesize(pagesize);
+                    stat.set_current(h.usage.unwrap_or_default());
+                    stat.set_max(h.max.unwrap_or_default());
+                    stat
+                })
+                .collect();
+            hugetlb.sort_by(|a, b| a.pagesize.cmp(&b.pagesize));
+            metrics.set_hugetlb(hugetlb);
+        }
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_to_metrics_test() {
+        let stats: events::Stats = serde_json::from_str(
+            r#"{
+                "cpu": {
+                    "usage": {"total": 100, "percpu": [60, 40], "kernel": 30, "user": 70},
+                    "throttling": {"periods": 5, "throttledPeriods": 2, "throttledTime": 1000}
+                },
+                "memory": {
+                    "cache": 4096,
+                    "usage": {"limit": 8192, "usage": 2048, "max": 3072, "failcnt": 1},
+                    "raw": {"anon": 1024, "pgfault": 7, "total_rss": 512}
+                },
+                "pids": {"current": 3, "limit": 10},
+                "blkio": {
+                    "ioServiceBytesRecursive": [{"major": 8, "minor": 0, "op": "Read", "value": 42}]
+                },
+                "hugetlb": {
+                    "2MB": {"usage": 0, "max": 0, "failcnt": 0},
+                    "1GB": {"usage": 1, "max": 2, "failcnt": 3}
+                }
+            }"#,
+        )
+        .unwrap();
+        let metrics = Metrics::from(stats);
+
+        let cpu = &metrics.cpu;
+        assert_eq!(cpu.usage.total, 100);
+        assert_eq!(cpu.usage.per_cpu, vec![60, 40]);
+        assert_eq!(cpu.usage.kernel, 30);
+        assert_eq!(cpu.throttling.throttled_periods, 2);
+        assert_eq!(cpu.throttling.throttled_time, 1000);
+
+        let memory = &metrics.memory;
+        assert_eq!(memory.cache, 4096);
+        assert_eq!(memory.rss, 1024);
+        assert_eq!(memory.pg_fault, 7);
+        assert_eq!(memory.total_rss, 512);
+        assert_eq!(memory.usage.limit, 8192);
+        assert_eq!(memory.usage.max, 3072);
+        assert_eq!(memory.usage.failcnt, 1);
+        assert!(memory.swap.is_none());
+
+        assert_eq!(metrics.pids.current, 3);
+        assert_eq!(metrics.pids.limit, 10);
+
+        let entries = &metrics.blkio.io_service_bytes_recursive;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].op, "Read");
+        assert_eq!(entries[0].value, 42);
+
+        let pagesizes: Vec<_> = metrics
+            .hugetlb
+            .iter()
+            .map(|h| h.pagesize.as_str())
+            .collect();
+        assert_eq!(pagesizes, vec!["1GB", "2MB"]);
+        assert_eq!(metrics.hugetlb[0].failcnt, 3);
+    }
+
+    #[test]
+    fn stats_to_v2_metrics_test() {
+        let stats: events::Stats = serde_json::from_str(
+            r#"{
+                "cpu": {
+                    "usage": {"total": 100000, "kernel": 30000, "user": 70000},
+                    "throttling": {"periods": 5, "throttledPeriods": 2, "throttledTime": 1000000}
+                },
+                "memory": {
+                    "usage": {"limit": 8192, "usage": 2048, "failcnt": 0},
+                    "swap": {"limit": 12288, "usage": 3072, "failcnt": 0},
+                    "raw": {"anon": 1024, "file": 4096, "pgfault": 7, "workingset_refault": 2}
+                },
+                "pids": {"current": 3, "limit": 18446744073709551615},
+                "blkio": {
+                    "ioServiceBytesRecursive": [
+                        {"major": 8, "minor": 0, "op": "Read", "value": 42},
+                        {"major": 8, "minor": 0, "op": "Write", "value": 24}
+                    ],
+                    "ioServicedRecursive": [
+                        {"major": 8, "minor": 0, "op": "Read", "value": 4},
+                        {"major": 8, "minor": 0, "op": "Write", "value": 2}
+                    ]
+                },
+                "hugetlb": {"2MB": {"usage": 1, "max": 2, "failcnt": 0}}
+            }"#,
+        )
+        .unwrap();
+        let metrics = v2::Metrics::from(stats);
+
+        let cpu = &metrics.cpu;
+        assert_eq!(cpu.usage_usec, 100);
+        assert_eq!(cpu.user_usec, 70);
+        assert_eq!(cpu.system_usec, 30);
+        assert_eq!(cpu.nr_periods, 5);
+        assert_eq!(cpu.nr_throttled, 2);
+        assert_eq!(cpu.throttled_usec, 1000);
+
+        let memory = &metrics.memory;
+        assert_eq!(memory.anon, 1024);
+        assert_eq!(memory.file, 4096);
+        assert_eq!(memory.pgfault, 7);
+        assert_eq!(memory.workingset_refault, 2);
+        assert_eq!(memory.usage, 2048);
+        assert_eq!(memory.usage_limit, 8192);
+        assert_eq!(memory.swap_usage, 1024);
+        assert_eq!(memory.swap_limit, 4096);
+
+        assert_eq!(metrics.pids.current, 3);
+        assert_eq!(metrics.pids.limit, u64::MAX);
+
+        let usage = &metrics.io.usage;
+        assert_eq!(usage.len(), 1);
+        assert_eq!((usage[0].major, usage[0].minor), (8, 0));
+        assert_eq!((usage[0].rbytes, usage[0].wbytes), (42, 24));
+        assert_eq!((usage[0].rios, usage[0].wios), (4, 2));
+
+        assert_eq!(metrics.hugetlb.len(), 1);
+        assert_eq!(metrics.hugetlb[0].pagesize, "2MB");
+        assert_eq!(metrics.hugetlb[0].current, 1);
+        assert_eq!(metrics.hugetlb[0].max, 2);
+    }
+
+    #[test]
+    fn stats_to_v2_metrics_unlimited_test() {
+        let stats: events::Stats = serde_json::from_str(
+            r#"{
+                "cpu": {},
+                "memory": {
+                    "usage": {"limit": 18446744073709551615, "usage": 2048, "failcnt": 0},
+                    "swap": {"limit": 18446744073709551615, "usage": 2048, "failcnt": 0}
+                },
+                "pids": {"current": 3, "limit": 18446744073709551615},
+                "blkio": {},
+                "hugetlb": {"2MB": {"usage": 0, "max": 18446744073709551615, "failcnt": 0}}
+            }"#,
+        )
+        .unwrap();
+        let metrics = v2::Metrics::from(stats);
+        assert_eq!(metrics.memory.usage_limit, u64::MAX);
+        assert_eq!(metrics.memory.swap_limit, u64::MAX);
+        assert_eq!(metrics.pids.limit, u64::MAX);
+        assert_eq!(metrics.hugetlb[0].max, u64::MAX);
+
+        // runc wraps limited swap around when memory is unlimited
+        let stats: events::Stats = serde_json::from_str(
+            r#"{
+                "cpu": {},
+                "memory": {
+                    "usage": {"limit": 18446744073709551615, "usage": 2048, "failcnt": 0},
+                    "swap": {"limit": 4095, "usage": 2048, "failcnt": 0}
+                },
+                "pids": {},
+                "blkio": {}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(v2::Metrics::from(stats).memory.swap_limit, 4096);
+    }
+
+    #[test]
+    fn empty_stats_test() {
+        let stats: events::Stats =
+            serde_json::from_str(r#"{"cpu": {}, "memory": {}, "pids": {}, "blkio": {}}"#).unwrap();
+        let metrics = Metrics::from(stats);
+        assert!(metrics.cpu.usage.is_none());
+        assert_eq!(metrics.memory.rss, 0);
+        assert!(metrics.hugetlb.is_empty());
+    }
+}
@@ -34,31 +34,70 @@
  */
 
 use std::{
+    fmt::{self, Display},
+    os::unix::io::RawFd,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
-use crate::{error::Error, io::Io, utils, DefaultExecutor, LogFormat, Runc, Spawner};
+use log::warn;
+use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResources};
+#[cfg(feature = "async")]
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::Error,
+    io::Io,
+    profile::{RootlessSupport, RuntimeProfile},
+    utils, DefaultExecutor, LogFormat, Runc, Spawner,
+};
 
 // constants for log format
 pub const JSON: &str = "json";
 pub const TEXT: &str = "text";
 
 // constants for runc global flags
+pub(crate) const CRIU: &str = "--criu";
 const DEBUG: &str = "--debug";
-const LOG: &str = "--log";
-const LOG_FORMAT: &str = "--log-format";
-const ROOT: &str = "--root";
+pub(crate) const LOG: &str = "--log";
+pub(crate) const LOG_FORMAT: &str = "--log-format";
+pub(crate) const ROOT: &str = "--root";
 const ROOTLESS: &str = "--rootless";
 const SYSTEMD_CGROUP: &str = "--systemd-cgroup";
 
 // constants for runc-create/runc-exec flags
-const CONSOLE_SOCKET: &str = "--console-socket";
+pub(crate) const CONSOLE_SOCKET: &str = "--console-socket";
 const DETACH: &str = "--detach";
 const NO_NEW_KEYRING: &str = "--no-new-keyring";
 const NO_PIVOT: &str = "--no-pivot";
-const PID_FILE: &str = "--pid-file";
+pub(crate) const PID_FILE: &str = "--pid-file";
+
+// constants for runc-exec flags
+const CWD: &str = "--cwd";
+const ENV: &str = "--env";
+const USER: &str = "--user";
+const ADDITIONAL_GIDS: &str = "--additional-gids";
+const CAP: &str = "--cap";
+const APPARMOR: &str = "--apparmor";
+const PROCESS_LABEL: &str = "--process-label";
+const NO_NEW_PRIVS: &str = "--no-new-privs";
+const PRESERVE_FDS: &str = "--preserve-fds";
+const CGROUP: &str = "--cgroup";
+
+// constants for runc-checkpoint/runc-restore flags
+pub(crate) const IMAGE_PATH: &str = "--image-path";
+pub(crate) const WORK_PATH: &str = "--work-path";
+pub(crate) const PARENT_PATH: &str = "--parent-path";
+const LEAVE_RUNNING: &str = "--leave-running";
+const TCP_ESTABLISHED: &str = "--tcp-established";
+const EXT_UNIX_SK: &str = "--ext-unix-sk";
+const SHELL_JOB: &str = "--shell-job";
+const FILE_LOCKS: &str = "--file-locks";
+const EMPTY_NS: &str = "--empty-ns";
+const LAZY_PAGES: &str = "--lazy-pages";
+const MANAGE_CGROUPS_MODE: &str = "--manage-cgroups-mode";
+const NO_SUBREAPER: &str = "--no-subreaper";
 
 // constants for runc-kill flags
 const ALL: &str = "--all";
@@ -66,6 +105,23 @@ const ALL: &str = "--all";
 // constants for runc-delete flags
 const FORCE: &str = "--force";
 
+// constants for runc-update flags
+const MEMORY: &str = "--memory";
+const MEMORY_SWAP: &str = "--memory-swap";
+const MEMORY_RESERVATION: &str = "--memory-reservation";
+const KERNEL_MEMORY: &str = "--kernel-memory";
+const CPU_SHARE: &str = "--cpu-share";
+const CPU_QUOTA: &str = "--cpu-quota";
+const CPU_PERIOD: &str = "--cpu-period";
+const CPU_RT_RUNTIME: &str = "--cpu-rt-runtime";
+const CPU_RT_PERIOD: &str = "--cpu-rt-period";
+const CPUSET_CPUS: &str = "--cpuset-cpus";
+const CPUSET_MEMS: &str = "--cpuset-mems";
+const PIDS_LIMIT: &str = "--pids-limit";
+const L3_CACHE_SCHEMA: &str = "--l3-cache-schema";
+const MEM_BW_SCHEMA: &str = "--mem-bw-schema";
+const BLKIO_WEIGHT: &str = "--blkio-weight";
+
 // constant for command
 pub const DEFAULT_COMMAND: &str = "runc";
 
@@ -79,7 +135,7 @@ pub trait Args {
 ///
 /// These options will be passed for all subsequent runc calls.
 /// See <https://github.com/opencontainers/runc/blob/main/man/runc.8.md#global-options>
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct GlobalOpts {
     /// Override the name of the runc binary. If [`None`], `runc` is used.
     command: Option<PathBuf>,
@@ -93,22 +149,44 @@ pub struct GlobalOpts {
     log_format: LogFormat,
     /// Path to root directory of container rootfs.
     root: Option<PathBuf>,
-    /// Whether to use rootless mode.
+    /// Path to the criu binary used for checkpoint and restore.
+    criu: Option<PathBuf>,
+    /// Rootless mode to pass to runc.
     ///
-    /// If [`None`], `auto` settings is used.
-    /// Note that "auto" is different from explicit "true" or "false".
-    rootless: Option<bool>,
+    /// If [`None`], the flag is omitted and runc falls back to `auto`.
+    rootless: Option<RootlessMode>,
     /// Set process group ID (gpid).
     set_pgid: bool,
     /// Use systemd cgroup.
     systemd_cgroup: bool,
     /// Timeout settings for runc command.
     ///
-    /// Default is 5 seconds.
+    /// Default is zero, which disables the timeout. Set one here, or per call in the `*Opts`.
     /// This will be used only in AsyncClient.
     timeout: Duration,
     /// executor that runs the commands
     executor: Option<Arc<dyn Spawner + Send + Sync>>,
+    /// CLI surface of the runtime, defaults to runc's.
+    profile: RuntimeProfile,
+}
+
+impl Default for GlobalOpts {
+    fn default() -> Self {
+        Self {
+            command: None,
+            debug: false,
+            log: None,
+            log_format: LogFormat::default(),
+            root: None,
+            criu: None,
+            rootless: None,
+            set_pgid: false,
+            systemd_cgroup: false,
+            timeout: Duration::ZERO,
+            executor: None,
+            profile: RuntimeProfile::default(),
+        }
+    }
 }
 
 impl GlobalOpts {
@@ -174,42 +252,76 @@ impl GlobalOpts {
     ///
     // Default is auto, meaning to auto-detect whether rootless should be enabled.
     pub fn rootless(mut self, rootless: bool) -> Self {
-        self.rootless = Some(rootless);
+        self.rootless = Some(if rootless {
+            RootlessMode::Enabled
+        } else {
+            RootlessMode::Disabled
+        });
         self
     }
 
-    /// Set rootless mode to auto.
+    /// Set rootless mode to auto, passing `--rootless=auto` explicitly.
     pub fn rootless_auto(mut self) -> Self {
-        self.rootless = None;
+        self.rootless = Some(RootlessMode::Auto);
         self
     }
 
+    /// Set the path to the criu binary used for checkpoint and restore.
+    pub fn criu(mut self, criu: impl AsRef<Path>) -> Self {
+        self.criu = Some(criu.as_ref().to_path_buf());
+        self
+    }
+
+    /// Run runc in a new process group, which is killed as a whole when a command times out.
     pub fn set_pgid(mut self, set_pgid: bool) -> Self {
         self.set_pgid = set_pgid;
         self
     }
 
+    /// Set the timeout for each runc command, zero (the default) disables it.
     pub fn timeout(&mut self, millis: u64) -> &mut Self {
         self.timeout = Duration::from_millis(millis);
         self
     }
 
+    /// Run commands with a custom [Spawner], such as the layers in [spawner](crate::spawner).
     pub fn custom_spawner(&mut self, executor: Arc<dyn Spawner + Send + Sync>) -> &mut Self {
         self.executor = Some(executor);
         self
     }
 
+    /// Set the profile of the runtime, when `command` is not runc.
+    pub fn profile(mut self, profile: RuntimeProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Detect the profile of the runtime from the output of `<command> --version`.
+    #[cfg(not(feature = "async"))]
+    pub fn detect_profile(self) -> Result<Self, Error> {
+        let profile = RuntimeProfile::detect_binary(self.runtime_command())?;
+        Ok(self.profile(profile))
+    }
+
+    /// Detect the profile of the runtime from the output of `<command> --version`.
+    #[cfg(feature = "async")]
+    pub async fn detect_profile(self) -> Result<Self, Error> {
+        let profile = RuntimeProfile::detect_binary(self.runtime_command()).await?;
+        Ok(self.profile(profile))
+    }
+
+    fn runtime_command(&self) -> PathBuf {
+        self.command
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("runc"))
+    }
+
     pub fn build(self) -> Result<Runc, Error> {
         self.args()
     }
 
     fn output(&self) -> Result<(PathBuf, Vec<String>), Error> {
-        let path = self
-            .command
-            .clone()
-            .unwrap_or_else(|| PathBuf::from("runc"));
-
-        let command = utils::binary_path(path).ok_or(Error::NotFound)?;
+        let command = utils::binary_path(self.runtime_command()).ok_or(Error::NotFound)?;
 
         let mut args = Vec::new();
 
@@ -234,15 +346,43 @@ impl GlobalOpts {
         args.push(LOG_FORMAT.into());
         args.push(self.log_format.to_string());
 
+        let profile = &self.profile;
+
         // --systemd-cgroup : Enable systemd cgroup support.
         if self.systemd_cgroup {
-            args.push(SYSTEMD_CGROUP.into());
+            if profile.systemd_cgroup {
+                args.push(SYSTEMD_CGROUP.into());
+            } else {
+                warn!(
+                    "{} does not support {}, ignored",
+                    profile.name, SYSTEMD_CGROUP
+                );
+            }
+        }
+
+        // --criu path : Path to the criu binary used for checkpoint and restore.
+        if let Some(criu) = &self.criu {
+            if profile.criu {
+                args.push(CRIU.into());
+                args.push(utils::abs_string(criu)?);
+            } else {
+                warn!("{} does not support {}, ignored", profile.name, CRIU);
+            }
         }
 
         // --rootless true|false|auto : Enable or disable rootless mode.
+        // Runtimes without auto mode detect rootless by default, so the flag is just omitted.
         if let Some(mode) = self.rootless {
-            let arg = format!("{}={}", ROOTLESS, mode);
-            args.push(arg);
+            match profile.rootless {
+                RootlessSupport::Auto => args.push(format!("{}={}", ROOTLESS, mode)),
+                RootlessSupport::Bool if mode != RootlessMode::Auto => {
+                    args.push(format!("{}={}", ROOTLESS, mode))
+                }
+                RootlessSupport::Unsupported if mode != RootlessMode::Auto => {
+                    warn!("{} does not support {}, ignored", profile.name, ROOTLESS)
+                }
+                _ => {}
+            }
         }
         Ok((command, args))
     }
@@ -261,6 +401,14 @@ impl Args for GlobalOpts {
         Ok(Runc {
             command,
             args,
+            profile: self.profile.clone(),
+            set_pgid: self.set_pgid,
+            log: match (&self.log, &self.log_format) {
+                (Some(log), LogFormat::Json) => Some(PathBuf::from(utils::abs_string(log)?)),
+                _ => None,
+            },
+            #[cfg(feature = "async")]
+            timeout: self.timeout,
             spawner: executor,
         })
     }
@@ -279,6 +427,12 @@ pub struct CreateOpts {
     pub no_pivot: bool,
     /// A new session keyring for the container will not be created.
     pub no_new_keyring: bool,
+    /// Timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub timeout: Option<Duration>,
+    /// Token to cancel this call, the runc process is killed on cancellation.
+    #[cfg(feature = "async")]
+    pub cancel: Option<CancellationToken>,
 }
 
 impl Args for CreateOpts {
@@ -347,9 +501,27 @@ impl CreateOpts {
         self.no_new_keyring = no_new_keyring;
         self
     }
+
+    /// Set the timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a token to cancel this call.
+    #[cfg(feature = "async")]
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
 }
 
 /// Container execution options
+///
+/// Note that runc ignores `cwd`, `env`, `user`, `additional_gids`, `caps`, `apparmor`,
+/// `process_label` and `no_new_privs` when a full process spec is passed, so they only
+/// take effect with [crate::Runc::exec_args].
 #[derive(Clone, Default)]
 pub struct ExecOpts {
     pub io: Option<Arc<dyn Io>>,
@@ -359,6 +531,32 @@ pub struct ExecOpts {
     pub console_socket: Option<PathBuf>,
     /// Detach from the container's process (only available for run)
     pub detach: bool,
+    /// Current working directory in the container.
+    pub cwd: Option<PathBuf>,
+    /// Environment variables to set, in the `KEY=VALUE` form.
+    pub env: Vec<String>,
+    /// UID (format: `<uid>[:<gid>]`) to run the process as.
+    pub user: Option<String>,
+    /// Additional gids for the process.
+    pub additional_gids: Vec<u32>,
+    /// Capabilities to add to the process.
+    pub caps: Vec<String>,
+    /// Apparmor profile for the process.
+    pub apparmor: Option<String>,
+    /// SELinux label for the process.
+    pub process_label: Option<String>,
+    /// Set the no new privileges value for the process.
+    pub no_new_privs: bool,
+    /// File descriptors to pass to the process, they appear as fd 3 onwards in the container.
+    pub preserve_fds: Vec<RawFd>,
+    /// Sub-cgroup to exec into, as `path` or `controller:path` for cgroup v1.
+    pub cgroup: Vec<String>,
+    /// Timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub timeout: Option<Duration>,
+    /// Token to cancel this call, the runc process is killed on cancellation.
+    #[cfg(feature = "async")]
+    pub cancel: Option<CancellationToken>,
 }
 
 impl Args for ExecOpts {
@@ -377,6 +575,46 @@ impl Args for ExecOpts {
         if self.detach {
             args.push(DETACH.to_string());
         }
+        // The working directory lives inside the container, so don't resolve it here.
+        if let Some(cwd) = &self.cwd {
+            args.push(CWD.to_string());
+            args.push(cwd.to_string_lossy().to_string());
+        }
+        for env in &self.env {
+            args.push(ENV.to_string());
+            args.push(env.to_string());
+        }
+        if let Some(user) = &self.user {
+            args.push(USER.to_string());
+            args.push(user.to_string());
+        }
+        for gid in &self.additional_gids {
+            args.push(ADDITIONAL_GIDS.to_string());
+            args.push(gid.to_string());
+        }
+        for cap in &self.caps {
+            args.push(CAP.to_string());
+            args.push(cap.to_string());
+        }
+        if let Some(apparmor) = &self.apparmor {
+            args.push(APPARMOR.to_string());
+            args.push(apparmor.to_string());
+        }
+        if let Some(process_label) = &self.process_label {
+            args.push(PROCESS_LABEL.to_string());
+            args.push(process_label.to_string());
+        }
+        if self.no_new_privs {
+            args.push(NO_NEW_PRIVS.to_string());
+        }
+        if !self.preserve_fds.is_empty() {
+            args.push(PRESERVE_FDS.to_string());
+            args.push(self.preserve_fds.len().to_string());
+        }
+        for cgroup in &self.cgroup {
+            args.push(CGROUP.to_string());
+            args.push(cgroup.to_string());
+        }
         Ok(args)
     }
 }
@@ -411,177 +649,1219 @@ impl ExecOpts {
         self.detach = detach;
         self
     }
-}
 
-/// Container deletion options
-#[derive(Debug, Clone, Default)]
-pub struct DeleteOpts {
-    /// Forcibly delete the container if it is still running
-    pub force: bool,
+    pub fn cwd<P>(mut self, cwd: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.cwd = Some(cwd.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push(format!("{}={}", key, value));
+        self
+    }
+
+    pub fn user(mut self, uid: u32, gid: u32) -> Self {
+        self.user = Some(format!("{}:{}", uid, gid));
+        self
+    }
+
+    pub fn additional_gid(mut self, gid: u32) -> Self {
+        self.additional_gids.push(gid);
+        self
+    }
+
+    pub fn cap(mut self, cap: impl Into<String>) -> Self {
+        self.caps.push(cap.into());
+        self
+    }
+
+    pub fn apparmor(mut self, profile: impl Into<String>) -> Self {
+        self.apparmor = Some(profile.into());
+        self
+    }
+
+    pub fn process_label(mut self, label: impl Into<String>) -> Self {
+        self.process_label = Some(label.into());
+        self
+    }
+
+    pub fn no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.no_new_privs = no_new_privs;
+        self
+    }
+
+    pub fn preserve_fd(mut self, fd: RawFd) -> Self {
+        self.preserve_fds.push(fd);
+        self
+    }
+
+    pub fn cgroup(mut self, cgroup: impl Into<String>) -> Self {
+        self.cgroup.push(cgroup.into());
+        self
+    }
+
+    /// Set the timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a token to cancel this call.
+    #[cfg(feature = "async")]
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
 }
 
-impl Args for DeleteOpts {
-    type Output = Vec<String>;
+/// Rootless mode of runc, passed as `--rootless`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootlessMode {
+    /// `--rootless=true`
+    Enabled,
+    /// `--rootless=false`
+    Disabled,
+    /// `--rootless=auto`, detecting whether rootless mode should be enabled.
+    Auto,
+}
 
-    fn args(&self) -> Self::Output {
-        let mut args: Vec<String> = vec![];
-        if self.force {
-            args.push(FORCE.to_string());
+impl Display for RootlessMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootlessMode::Enabled => write!(f, "true"),
+            RootlessMode::Disabled => write!(f, "false"),
+            RootlessMode::Auto => write!(f, "auto"),
         }
-        args
     }
 }
 
-impl DeleteOpts {
-    pub fn new() -> Self {
-        Self::default()
-    }
+/// Cgroups management mode used by CRIU during checkpoint and restore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupMode {
+    Soft,
+    Full,
+    Strict,
+    Ignore,
+}
 
-    pub fn force(mut self, force: bool) -> Self {
-        self.force = force;
-        self
+impl Display for CgroupMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CgroupMode::Soft => write!(f, "soft"),
+            CgroupMode::Full => write!(f, "full"),
+            CgroupMode::Strict => write!(f, "strict"),
+            CgroupMode::Ignore => write!(f, "ignore"),
+        }
     }
 }
 
-/// Container killing options
+/// Container checkpoint options
 #[derive(Debug, Clone, Default)]
-pub struct KillOpts {
-    /// Seng the kill signal to all the processes inside the container
-    pub all: bool,
+pub struct CheckpointOpts {
+    /// Path for saving criu image files.
+    pub image_path: Option<PathBuf>,
+    /// Path for saving work files and logs.
+    pub work_path: Option<PathBuf>,
+    /// Path for previous criu image files in pre-dump.
+    pub parent_path: Option<PathBuf>,
+    /// Leave the process running after checkpointing.
+    pub leave_running: bool,
+    /// Allow open tcp connections.
+    pub tcp_established: bool,
+    /// Allow external unix sockets.
+    pub ext_unix_sk: bool,
+    /// Allow shell jobs.
+    pub shell_job: bool,
+    /// Handle file locks, for safety.
+    pub file_locks: bool,
+    /// Create a namespace, but don't restore its properties.
+    pub empty_ns: Vec<String>,
+    /// Use userfaultfd to lazily restore memory pages.
+    pub lazy_pages: bool,
+    /// Cgroups mode used by criu.
+    pub manage_cgroups_mode: Option<CgroupMode>,
+    /// Timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub timeout: Option<Duration>,
+    /// Token to cancel this call, the runc process is killed on cancellation.
+    #[cfg(feature = "async")]
+    pub cancel: Option<CancellationToken>,
 }
 
-impl Args for KillOpts {
-    type Output = Vec<String>;
+impl Args for CheckpointOpts {
+    type Output = Result<Vec<String>, Error>;
 
     fn args(&self) -> Self::Output {
         let mut args: Vec<String> = vec![];
-        if self.all {
-            args.push(ALL.to_string());
+        if let Some(image_path) = &self.image_path {
+            args.push(IMAGE_PATH.to_string());
+            args.push(utils::abs_string(image_path)?);
         }
-        args
+        if let Some(work_path) = &self.work_path {
+            args.push(WORK_PATH.to_string());
+            args.push(utils::abs_string(work_path)?);
+        }
+        if let Some(parent_path) = &self.parent_path {
+            args.push(PARENT_PATH.to_string());
+            args.push(utils::abs_string(parent_path)?);
+        }
+        if self.leave_running {
+            args.push(LEAVE_RUNNING.to_string());
+        }
+        if self.tcp_established {
+            args.push(TCP_ESTABLISHED.to_string());
+        }
+        if self.ext_unix_sk {
+            args.push(EXT_UNIX_SK.to_string());
+        }
+        if self.shell_job {
+            args.push(SHELL_JOB.to_string());
+        }
+        if self.file_locks {
+            args.push(FILE_LOCKS.to_string());
+        }
+        for ns in &self.empty_ns {
+            args.push(EMPTY_NS.to_string());
+            args.push(ns.to_string());
+        }
+        if self.lazy_pages {
+            args.push(LAZY_PAGES.to_string());
+        }
+        if let Some(mode) = &self.manage_cgroups_mode {
+            args.push(MANAGE_CGROUPS_MODE.to_string());
+            args.push(mode.to_string());
+        }
+        Ok(args)
     }
 }
 
-impl KillOpts {
+impl CheckpointOpts {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn all(mut self, all: bool) -> Self {
-        self.all = all;
+    pub fn image_path<P>(mut self, image_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.image_path = Some(image_path.as_ref().to_path_buf());
         self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::env;
+    pub fn work_path<P>(mut self, work_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.work_path = Some(work_path.as_ref().to_path_buf());
+        self
+    }
 
-    use super::*;
+    pub fn parent_path<P>(mut self, parent_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.parent_path = Some(parent_path.as_ref().to_path_buf());
+        self
+    }
 
-    const ARGS_FAIL_MSG: &str = "Args.args() failed.";
+    pub fn leave_running(mut self, leave_running: bool) -> Self {
+        self.leave_running = leave_running;
+        self
+    }
 
-    #[test]
-    fn create_opts_test() {
-        assert_eq!(
-            CreateOpts::new().args().expect(ARGS_FAIL_MSG),
-            vec![String::new(); 0]
-        );
+    pub fn tcp_established(mut self, tcp_established: bool) -> Self {
+        self.tcp_established = tcp_established;
+        self
+    }
 
-        assert_eq!(
-            CreateOpts::new().pid_file(".").args().expect(ARGS_FAIL_MSG),
-            vec![
-                "--pid-file".to_string(),
-                env::current_dir()
-                    .unwrap()
-                    .to_string_lossy()
-                    .parse::<String>()
-                    .unwrap()
-            ]
-        );
+    pub fn ext_unix_sk(mut self, ext_unix_sk: bool) -> Self {
+        self.ext_unix_sk = ext_unix_sk;
+        self
+    }
 
-        assert_eq!(
-            CreateOpts::new()
-                .console_socket("..")
-                .args()
-                .expect(ARGS_FAIL_MSG),
-            vec![
-                "--console-socket".to_string(),
-                env::current_dir()
-                    .unwrap()
-                    .parent()
-                    .unwrap()
-                    .to_string_lossy()
-                    .parse::<String>()
-                    .unwrap()
-            ]
-        );
+    pub fn shell_job(mut self, shell_job: bool) -> Self {
+        self.shell_job = shell_job;
+        self
+    }
 
-        assert_eq!(
-            CreateOpts::new()
-                .detach(true)
-                .no_pivot(true)
-                .no_new_keyring(true)
-                .args()
-                .expect(ARGS_FAIL_MSG),
-            vec![
-                "--no-pivot".to_string(),
-                "--no-new-keyring".to_string(),
-                "--detach".to_string(),
-            ]
-        );
+    pub fn file_locks(mut self, file_locks: bool) -> Self {
+        self.file_locks = file_locks;
+        self
     }
 
-    #[test]
-    fn exec_opts_test() {
-        assert_eq!(
-            ExecOpts::new().args().expect(ARGS_FAIL_MSG),
-            vec![String::new(); 0]
-        );
+    pub fn empty_ns(mut self, ns: impl Into<String>) -> Self {
+        self.empty_ns.push(ns.into());
+        self
+    }
 
-        assert_eq!(
-            ExecOpts::new().pid_file(".").args().expect(ARGS_FAIL_MSG),
-            vec![
-                "--pid-file".to_string(),
-                env::current_dir()
-                    .unwrap()
-                    .to_string_lossy()
-                    .parse::<String>()
-                    .unwrap()
-            ]
-        );
+    pub fn lazy_pages(mut self, lazy_pages: bool) -> Self {
+        self.lazy_pages = lazy_pages;
+        self
+    }
 
-        assert_eq!(
-            ExecOpts::new()
-                .console_socket("..")
-                .args()
-                .expect(ARGS_FAIL_MSG),
-            vec![
-                "--console-socket".to_string(),
-                env::current_dir()
-                    .unwrap()
-                    .parent()
-                    .unwrap()
-                    .to_string_lossy()
-                    .parse::<String>()
-                    .unwrap()
-            ]
-        );
+    pub fn manage_cgroups_mode(mut self, mode: CgroupMode) -> Self {
+        self.manage_cgroups_mode = Some(mode);
+        self
+    }
 
-        assert_eq!(
-            ExecOpts::new().detach(true).args().expect(ARGS_FAIL_MSG),
-            vec!["--detach".to_string(),]
-        );
+    /// Set the timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
-    #[test]
-    fn delete_opts_test() {
-        assert_eq!(
-            DeleteOpts::new().force(false).args(),
-            vec![String::new(); 0]
-        );
+    /// Set a token to cancel this call.
+    #[cfg(feature = "async")]
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
 
-        assert_eq!(
+/// Container restore options
+#[derive(Clone, Default)]
+pub struct RestoreOpts {
+    pub io: Option<Arc<dyn Io>>,
+    /// Path to where a pid file should be created.
+    pub pid_file: Option<PathBuf>,
+    /// Path to where a console socket should be created.
+    pub console_socket: Option<PathBuf>,
+    /// Detach from the container's process.
+    pub detach: bool,
+    /// Don't use pivot_root to jail process inside rootfs.
+    pub no_pivot: bool,
+    /// Disable the use of the subreaper used to reap reparented processes.
+    pub no_subreaper: bool,
+    /// Path to criu image files for restoring.
+    pub image_path: Option<PathBuf>,
+    /// Path for saving work files and logs.
+    pub work_path: Option<PathBuf>,
+    /// Allow open tcp connections.
+    pub tcp_established: bool,
+    /// Allow external unix sockets.
+    pub ext_unix_sk: bool,
+    /// Allow shell jobs.
+    pub shell_job: bool,
+    /// Handle file locks, for safety.
+    pub file_locks: bool,
+    /// Create a namespace, but don't restore its properties.
+    pub empty_ns: Vec<String>,
+    /// Use userfaultfd to lazily restore memory pages.
+    pub lazy_pages: bool,
+    /// Cgroups mode used by criu.
+    pub manage_cgroups_mode: Option<CgroupMode>,
+    /// Timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub timeout: Option<Duration>,
+    /// Token to cancel this call, the runc process is killed on cancellation.
+    #[cfg(feature = "async")]
+    pub cancel: Option<CancellationToken>,
+}
+
+impl Args for RestoreOpts {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        if let Some(image_path) = &self.image_path {
+            args.push(IMAGE_PATH.to_string());
+            args.push(utils::abs_string(image_path)?);
+        }
+        if let Some(work_path) = &self.work_path {
+            args.push(WORK_PATH.to_string());
+            args.push(utils::abs_string(work_path)?);
+        }
+        if self.tcp_established {
+            args.push(TCP_ESTABLISHED.to_string());
+        }
+        if self.ext_unix_sk {
+            args.push(EXT_UNIX_SK.to_string());
+        }
+        if self.shell_job {
+            args.push(SHELL_JOB.to_string());
+        }
+        if self.file_locks {
+            args.push(FILE_LOCKS.to_string());
+        }
+        for ns in &self.empty_ns {
+            args.push(EMPTY_NS.to_string());
+            args.push(ns.to_string());
+        }
+        if self.lazy_pages {
+            args.push(LAZY_PAGES.to_string());
+        }
+        if let Some(mode) = &self.manage_cgroups_mode {
+            args.push(MANAGE_CGROUPS_MODE.to_string());
+            args.push(mode.to_string());
+        }
+        if let Some(pid_file) = &self.pid_file {
+            args.push(PID_FILE.to_string());
+            args.push(utils::abs_string(pid_file)?);
+        }
+        if let Some(console_socket) = &self.console_socket {
+            args.push(CONSOLE_SOCKET.to_string());
+            args.push(utils::abs_string(console_socket)?);
+        }
+        if self.no_pivot {
+            args.push(NO_PIVOT.to_string());
+        }
+        if self.no_subreaper {
+            args.push(NO_SUBREAPER.to_string());
+        }
+        if self.detach {
+            args.push(DETACH.to_string());
+        }
+        Ok(args)
+    }
+}
+
+impl RestoreOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn io(mut self, io: Arc<dyn Io>) -> Self {
+        self.io = Some(io);
+        self
+    }
+
+    pub fn pid_file<P>(mut self, pid_file: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.pid_file = Some(pid_file.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn console_socket<P>(mut self, console_socket: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.console_socket = Some(console_socket.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn detach(mut self, detach: bool) -> Self {
+        self.detach = detach;
+        self
+    }
+
+    pub fn no_pivot(mut self, no_pivot: bool) -> Self {
+        self.no_pivot = no_pivot;
+        self
+    }
+
+    pub fn no_subreaper(mut self, no_subreaper: bool) -> Self {
+        self.no_subreaper = no_subreaper;
+        self
+    }
+
+    pub fn image_path<P>(mut self, image_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.image_path = Some(image_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn work_path<P>(mut self, work_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.work_path = Some(work_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn tcp_established(mut self, tcp_established: bool) -> Self {
+        self.tcp_established = tcp_established;
+        self
+    }
+
+    pub fn ext_unix_sk(mut self, ext_unix_sk: bool) -> Self {
+        self.ext_unix_sk = ext_unix_sk;
+        self
+    }
+
+    pub fn shell_job(mut self, shell_job: bool) -> Self {
+        self.shell_job = shell_job;
+        self
+    }
+
+    pub fn file_locks(mut self, file_locks: bool) -> Self {
+        self.file_locks = file_locks;
+        self
+    }
+
+    pub fn empty_ns(mut self, ns: impl Into<String>) -> Self {
+        self.empty_ns.push(ns.into());
+        self
+    }
+
+    pub fn lazy_pages(mut self, lazy_pages: bool) -> Self {
+        self.lazy_pages = lazy_pages;
+        self
+    }
+
+    pub fn manage_cgroups_mode(mut self, mode: CgroupMode) -> Self {
+        self.manage_cgroups_mode = Some(mode);
+        self
+    }
+
+    /// Set the timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a token to cancel this call.
+    #[cfg(feature = "async")]
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+/// Container deletion options
+#[derive(Debug, Clone, Default)]
+pub struct DeleteOpts {
+    /// Forcibly delete the container if it is still running
+    pub force: bool,
+    /// Timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub timeout: Option<Duration>,
+    /// Token to cancel this call, the runc process is killed on cancellation.
+    #[cfg(feature = "async")]
+    pub cancel: Option<CancellationToken>,
+}
+
+impl Args for DeleteOpts {
+    type Output = Vec<String>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        if self.force {
+            args.push(FORCE.to_string());
+        }
+        args
+    }
+}
+
+impl DeleteOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Set the timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a token to cancel this call.
+    #[cfg(feature = "async")]
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+/// Container killing options
+#[derive(Debug, Clone, Default)]
+pub struct KillOpts {
+    /// Seng the kill signal to all the processes inside the container
+    pub all: bool,
+    /// Timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub timeout: Option<Duration>,
+    /// Token to cancel this call, the runc process is killed on cancellation.
+    #[cfg(feature = "async")]
+    pub cancel: Option<CancellationToken>,
+}
+
+impl Args for KillOpts {
+    type Output = Vec<String>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        if self.all {
+            args.push(ALL.to_string());
+        }
+        args
+    }
+}
+
+impl KillOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    /// Set the timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a token to cancel this call.
+    #[cfg(feature = "async")]
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+/// Container update options
+///
+/// Each limit maps to a flag of `runc update`, so that only the limits that are set change.
+/// When [UpdateOpts::resources] is set, runc ignores the flags, so the limits are merged into
+/// the resources passed as a file instead.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOpts {
+    /// Resources to write to a file and pass with `--resources`.
+    pub resources: Option<LinuxResources>,
+    /// Memory limit in bytes.
+    pub memory: Option<i64>,
+    /// Total memory usage (memory + swap) in bytes, -1 for unlimited.
+    pub memory_swap: Option<i64>,
+    /// Memory soft limit in bytes.
+    pub memory_reservation: Option<i64>,
+    /// Kernel memory limit in bytes, cgroup v1 only.
+    pub kernel_memory: Option<i64>,
+    /// CPU shares, relative weight against other containers.
+    pub cpu_share: Option<u64>,
+    /// CPU CFS quota in microseconds.
+    pub cpu_quota: Option<i64>,
+    /// CPU CFS period in microseconds.
+    pub cpu_period: Option<u64>,
+    /// CPU realtime runtime in microseconds.
+    pub cpu_rt_runtime: Option<i64>,
+    /// CPU realtime period in microseconds.
+    pub cpu_rt_period: Option<u64>,
+    /// CPUs to use, such as "0-3" or "0,1".
+    pub cpuset_cpus: Option<String>,
+    /// Memory nodes to use, such as "0-3" or "0,1".
+    pub cpuset_mems: Option<String>,
+    /// Maximum number of pids, -1 for unlimited.
+    pub pids_limit: Option<i64>,
+    /// Intel RDT L3 cache schema, such as "L3:0=ff".
+    pub l3_cache_schema: Option<String>,
+    /// Intel RDT memory bandwidth schema, such as "MB:0=20".
+    pub mem_bw_schema: Option<String>,
+    /// Block IO weight, between 10 and 1000.
+    pub blkio_weight: Option<u16>,
+    /// Timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub timeout: Option<Duration>,
+    /// Token to cancel this call, the runc process is killed on cancellation.
+    #[cfg(feature = "async")]
+    pub cancel: Option<CancellationToken>,
+}
+
+impl Args for UpdateOpts {
+    type Output = Vec<String>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        let mut push = |flag: &str, value: Option<String>| {
+            if let Some(v) = value {
+                args.push(flag.to_string());
+                args.push(v);
+            }
+        };
+        push(MEMORY, self.memory.map(|v| v.to_string()));
+        push(MEMORY_SWAP, self.memory_swap.map(|v| v.to_string()));
+        push(
+            MEMORY_RESERVATION,
+            self.memory_reservation.map(|v| v.to_string()),
+        );
+        push(KERNEL_MEMORY, self.kernel_memory.map(|v| v.to_string()));
+        push(CPU_SHARE, self.cpu_share.map(|v| v.to_string()));
+        push(CPU_QUOTA, self.cpu_quota.map(|v| v.to_string()));
+        push(CPU_PERIOD, self.cpu_period.map(|v| v.to_string()));
+        push(CPU_RT_RUNTIME, self.cpu_rt_runtime.map(|v| v.to_string()));
+        push(CPU_RT_PERIOD, self.cpu_rt_period.map(|v| v.to_string()));
+        push(CPUSET_CPUS, self.cpuset_cpus.clone());
+        push(CPUSET_MEMS, self.cpuset_mems.clone());
+        push(PIDS_LIMIT, self.pids_limit.map(|v| v.to_string()));
+        push(BLKIO_WEIGHT, self.blkio_weight.map(|v| v.to_string()));
+        args.append(&mut self.rdt_args());
+        args
+    }
+}
+
+impl UpdateOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pass `resources` as a file, with the limits set on these options merged into it.
+    pub fn resources(mut self, resources: LinuxResources) -> Self {
+        self.resources = Some(resources);
+        self
+    }
+
+    pub fn memory(mut self, limit: i64) -> Self {
+        self.memory = Some(limit);
+        self
+    }
+
+    pub fn memory_swap(mut self, limit: i64) -> Self {
+        self.memory_swap = Some(limit);
+        self
+    }
+
+    pub fn memory_reservation(mut self, limit: i64) -> Self {
+        self.memory_reservation = Some(limit);
+        self
+    }
+
+    pub fn kernel_memory(mut self, limit: i64) -> Self {
+        self.kernel_memory = Some(limit);
+        self
+    }
+
+    pub fn cpu_share(mut self, shares: u64) -> Self {
+        self.cpu_share = Some(shares);
+        self
+    }
+
+    pub fn cpu_quota(mut self, quota: i64) -> Self {
+        self.cpu_quota = Some(quota);
+        self
+    }
+
+    pub fn cpu_period(mut self, period: u64) -> Self {
+        self.cpu_period = Some(period);
+        self
+    }
+
+    pub fn cpu_rt_runtime(mut self, runtime: i64) -> Self {
+        self.cpu_rt_runtime = Some(runtime);
+        self
+    }
+
+    pub fn cpu_rt_period(mut self, period: u64) -> Self {
+        self.cpu_rt_period = Some(period);
+        self
+    }
+
+    pub fn cpuset_cpus(mut self, cpus: impl Into<String>) -> Self {
+        self.cpuset_cpus = Some(cpus.into());
+        self
+    }
+
+    pub fn cpuset_mems(mut self, mems: impl Into<String>) -> Self {
+        self.cpuset_mems = Some(mems.into());
+        self
+    }
+
+    pub fn pids_limit(mut self, limit: i64) -> Self {
+        self.pids_limit = Some(limit);
+        self
+    }
+
+    pub fn l3_cache_schema(mut self, schema: impl Into<String>) -> Self {
+        self.l3_cache_schema = Some(schema.into());
+        self
+    }
+
+    pub fn mem_bw_schema(mut self, schema: impl Into<String>) -> Self {
+        self.mem_bw_schema = Some(schema.into());
+        self
+    }
+
+    pub fn blkio_weight(mut self, weight: u16) -> Self {
+        self.blkio_weight = Some(weight);
+        self
+    }
+
+    /// Set the timeout for this call, overriding the global one.
+    #[cfg(feature = "async")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a token to cancel this call.
+    #[cfg(feature = "async")]
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// The Intel RDT flags, which runc reads even along with `--resources`.
+    pub(crate) fn rdt_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(schema) = &self.l3_cache_schema {
+            args.push(L3_CACHE_SCHEMA.to_string());
+            args.push(schema.clone());
+        }
+        if let Some(schema) = &self.mem_bw_schema {
+            args.push(MEM_BW_SCHEMA.to_string());
+            args.push(schema.clone());
+        }
+        args
+    }
+
+    /// Return [UpdateOpts::resources] with the limits of these options merged into it, or
+    /// [`None`] without resources.
+    pub fn merged_resources(&self) -> Result<Option<LinuxResources>, Error> {
+        let mut resources = match &self.resources {
+            Some(resources) => resources.clone(),
+            None => return Ok(None),
+        };
+
+        let memory_set = self.memory.is_some()
+            || self.memory_swap.is_some()
+            || self.memory_reservation.is_some()
+            || self.kernel_memory.is_some();
+        if memory_set {
+            let m = resources.memory().unwrap_or_default();
+            let mut builder = LinuxMemoryBuilder::default();
+            if let Some(v) = self.memory.or_else(|| m.limit()) {
+                builder = builder.limit(v);
+            }
+            if let Some(v) = self.memory_reservation.or_else(|| m.reservation()) {
+                builder = builder.reservation(v);
+            }
+            if let Some(v) = self.memory_swap.or_else(|| m.swap()) {
+                builder = builder.swap(v);
+            }
+            if let Some(v) = self.kernel_memory.or_else(|| m.kernel()) {
+                builder = builder.kernel(v);
+            }
+            if let Some(v) = m.kernel_tcp() {
+                builder = builder.kernel_tcp(v);
+            }
+            if let Some(v) = m.swappiness() {
+                builder = builder.swappiness(v);
+            }
+            if let Some(v) = m.disable_oom_killer() {
+                builder = builder.disable_oom_killer(v);
+            }
+            if let Some(v) = m.use_hierarchy() {
+                builder = builder.use_hierarchy(v);
+            }
+            if let Some(v) = m.check_before_update() {
+                builder = builder.check_before_update(v);
+            }
+            let memory = builder.build().map_err(|e| Error::Other(Box::new(e)))?;
+            resources.set_memory(Some(memory));
+        }
+
+        let cpu_set = self.cpu_share.is_some()
+            || self.cpu_quota.is_some()
+            || self.cpu_period.is_some()
+            || self.cpu_rt_runtime.is_some()
+            || self.cpu_rt_period.is_some()
+            || self.cpuset_cpus.is_some()
+            || self.cpuset_mems.is_some();
+        if cpu_set {
+            let c = resources.cpu_mut().get_or_insert_with(Default::default);
+            c.set_shares(self.cpu_share.or_else(|| c.shares()));
+            c.set_quota(self.cpu_quota.or_else(|| c.quota()));
+            c.set_period(self.cpu_period.or_else(|| c.period()));
+            c.set_realtime_runtime(self.cpu_rt_runtime.or_else(|| c.realtime_runtime()));
+            c.set_realtime_period(self.cpu_rt_period.or_else(|| c.realtime_period()));
+            if let Some(cpus) = &self.cpuset_cpus {
+                c.set_cpus(Some(cpus.clone()));
+            }
+            if let Some(mems) = &self.cpuset_mems {
+                c.set_mems(Some(mems.clone()));
+            }
+        }
+
+        if let Some(v) = self.pids_limit {
+            resources
+                .pids_mut()
+                .get_or_insert_with(Default::default)
+                .set_limit(v);
+        }
+        if let Some(v) = self.blkio_weight {
+            resources
+                .block_io_mut()
+                .get_or_insert_with(Default::default)
+                .set_weight(Some(v));
+        }
+        Ok(Some(resources))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const ARGS_FAIL_MSG: &str = "Args.args() failed.";
+
+    #[test]
+    fn create_opts_test() {
+        assert_eq!(
+            CreateOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            CreateOpts::new().pid_file(".").args().expect(ARGS_FAIL_MSG),
+            vec![
+                "--pid-file".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            CreateOpts::new()
+                .console_socket("..")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--console-socket".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .parent()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            CreateOpts::new()
+                .detach(true)
+                .no_pivot(true)
+                .no_new_keyring(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--no-pivot".to_string(),
+                "--no-new-keyring".to_string(),
+                "--detach".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn exec_opts_test() {
+        assert_eq!(
+            ExecOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            ExecOpts::new().pid_file(".").args().expect(ARGS_FAIL_MSG),
+            vec![
+                "--pid-file".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .console_socket("..")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--console-socket".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .parent()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            ExecOpts::new().detach(true).args().expect(ARGS_FAIL_MSG),
+            vec!["--detach".to_string(),]
+        );
+
+        assert_eq!(
+            ExecOpts::new().cwd("/root").args().expect(ARGS_FAIL_MSG),
+            vec!["--cwd".to_string(), "/root".to_string()]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .env("FOO", "bar")
+                .env("PATH", "/bin")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--env".to_string(),
+                "FOO=bar".to_string(),
+                "--env".to_string(),
+                "PATH=/bin".to_string(),
+            ]
+        );
+
+        assert_eq!(
+            ExecOpts::new().user(1000, 100).args().expect(ARGS_FAIL_MSG),
+            vec!["--user".to_string(), "1000:100".to_string()]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .additional_gid(10)
+                .additional_gid(20)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--additional-gids".to_string(),
+                "10".to_string(),
+                "--additional-gids".to_string(),
+                "20".to_string(),
+            ]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .cap("CAP_NET_ADMIN")
+                .cap("CAP_SYS_PTRACE")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--cap".to_string(),
+                "CAP_NET_ADMIN".to_string(),
+                "--cap".to_string(),
+                "CAP_SYS_PTRACE".to_string(),
+            ]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .apparmor("docker-default")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--apparmor".to_string(), "docker-default".to_string()]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .process_label("system_u:system_r:container_t:s0")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--process-label".to_string(),
+                "system_u:system_r:container_t:s0".to_string()
+            ]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .no_new_privs(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--no-new-privs".to_string()]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .preserve_fd(7)
+                .preserve_fd(9)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--preserve-fds".to_string(), "2".to_string()]
+        );
+
+        assert_eq!(
+            ExecOpts::new()
+                .cgroup("memory:/sub")
+                .cgroup("cpu:/sub")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--cgroup".to_string(),
+                "memory:/sub".to_string(),
+                "--cgroup".to_string(),
+                "cpu:/sub".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn checkpoint_opts_test() {
+        assert_eq!(
+            CheckpointOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .image_path(".")
+                .work_path("..")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap(),
+                "--work-path".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .parent()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .parent_path("/tmp/parent")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--parent-path".to_string(), "/tmp/parent".to_string()]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .leave_running(true)
+                .tcp_established(true)
+                .ext_unix_sk(true)
+                .shell_job(true)
+                .file_locks(true)
+                .empty_ns("network")
+                .empty_ns("ipc")
+                .lazy_pages(true)
+                .manage_cgroups_mode(CgroupMode::Soft)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--leave-running".to_string(),
+                "--tcp-established".to_string(),
+                "--ext-unix-sk".to_string(),
+                "--shell-job".to_string(),
+                "--file-locks".to_string(),
+                "--empty-ns".to_string(),
+                "network".to_string(),
+                "--empty-ns".to_string(),
+                "ipc".to_string(),
+                "--lazy-pages".to_string(),
+                "--manage-cgroups-mode".to_string(),
+                "soft".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_opts_test() {
+        assert_eq!(
+            RestoreOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            RestoreOpts::new()
+                .image_path("/tmp/image")
+                .work_path("/tmp/work")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path".to_string(),
+                "/tmp/image".to_string(),
+                "--work-path".to_string(),
+                "/tmp/work".to_string(),
+            ]
+        );
+
+        assert_eq!(
+            RestoreOpts::new()
+                .pid_file(".")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--pid-file".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            RestoreOpts::new()
+                .console_socket("/tmp/console.sock")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--console-socket".to_string(),
+                "/tmp/console.sock".to_string()
+            ]
+        );
+
+        assert_eq!(
+            RestoreOpts::new()
+                .tcp_established(true)
+                .ext_unix_sk(true)
+                .shell_job(true)
+                .file_locks(true)
+                .empty_ns("network")
+                .lazy_pages(true)
+                .manage_cgroups_mode(CgroupMode::Full)
+                .no_pivot(true)
+                .no_subreaper(true)
+                .detach(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--tcp-established".to_string(),
+                "--ext-unix-sk".to_string(),
+                "--shell-job".to_string(),
+                "--file-locks".to_string(),
+                "--empty-ns".to_string(),
+                "network".to_string(),
+                "--lazy-pages".to_string(),
+                "--manage-cgroups-mode".to_string(),
+                "full".to_string(),
+                "--no-pivot".to_string(),
+                "--no-subreaper".to_string(),
+                "--detach".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_opts_test() {
+        assert_eq!(
+            DeleteOpts::new().force(false).args(),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
             DeleteOpts::new().force(true).args(),
             vec!["--force".to_string()],
         );
@@ -628,5 +1908,113 @@ mod tests {
         assert!(args.contains(&"--rootless=true".to_string()));
         assert!(args.contains(&SYSTEMD_CGROUP.to_string()));
         assert_eq!(args.len(), 9);
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .criu("/usr/sbin/criu")
+            .rootless_auto();
+        let runc = cfg.build().unwrap();
+        let args = &runc.args;
+        assert!(args.contains(&CRIU.to_string()));
+        assert!(args.contains(&"/usr/sbin/criu".to_string()));
+        assert!(args.contains(&"--rootless=auto".to_string()));
+        assert_eq!(args.len(), 5);
+
+        let cfg = GlobalOpts::default().command("true").rootless(false);
+        let runc = cfg.build().unwrap();
+        assert!(runc.args.contains(&"--rootless=false".to_string()));
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .profile(RuntimeProfile::crun())
+            .criu("/usr/sbin/criu")
+            .systemd_cgroup(true)
+            .rootless_auto();
+        let runc = cfg.build().unwrap();
+        assert_eq!(
+            runc.args,
+            vec![
+                LOG_FORMAT.to_string(),
+                TEXT.to_string(),
+                SYSTEMD_CGROUP.to_string()
+            ]
+        );
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .profile(RuntimeProfile::youki())
+            .rootless(true);
+        let runc = cfg.build().unwrap();
+        assert_eq!(runc.args.len(), 2);
+    }
+
+    #[test]
+    fn update_opts_test() {
+        assert_eq!(UpdateOpts::new().args(), vec![String::new(); 0]);
+
+        assert_eq!(
+            UpdateOpts::new()
+                .memory(1024)
+                .memory_swap(-1)
+                .cpu_quota(50000)
+                .cpu_period(100000)
+                .cpu_rt_runtime(950)
+                .cpuset_cpus("0-1")
+                .pids_limit(100)
+                .blkio_weight(500)
+                .mem_bw_schema("MB:0=20")
+                .args(),
+            vec![
+                "--memory",
+                "1024",
+                "--memory-swap",
+                "-1",
+                "--cpu-quota",
+                "50000",
+                "--cpu-period",
+                "100000",
+                "--cpu-rt-runtime",
+                "950",
+                "--cpuset-cpus",
+                "0-1",
+                "--pids-limit",
+                "100",
+                "--blkio-weight",
+                "500",
+                "--mem-bw-schema",
+                "MB:0=20",
+            ]
+        );
+
+        assert!(UpdateOpts::new()
+            .memory(1)
+            .merged_resources()
+            .unwrap()
+            .is_none());
+
+        let mut base = LinuxResources::default();
+        let memory = LinuxMemoryBuilder::default()
+            .limit(10)
+            .swap(20)
+            .swappiness(60u64)
+            .disable_oom_killer(true)
+            .build()
+            .unwrap();
+        base.set_memory(Some(memory));
+        let merged = UpdateOpts::new()
+            .resources(base)
+            .memory(30)
+            .cpuset_mems("0")
+            .merged_resources()
+            .unwrap()
+            .unwrap();
+        let memory = merged.memory().unwrap();
+        assert_eq!(memory.limit(), Some(30));
+        assert_eq!(memory.swap(), Some(20));
+        assert_eq!(memory.swappiness(), Some(60));
+        assert_eq!(memory.disable_oom_killer(), Some(true));
+        assert_eq!(merged.cpu().as_ref().unwrap().mems().as_deref(), Some("0"));
+        assert!(merged.pids().is_none());
+        assert!(merged.block_io().is_none());
     }
 }
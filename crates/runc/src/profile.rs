@@ -0,0 +1,177 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Profiles describing how the CLI of other OCI runtimes differs from runc's.
+//!
+//! [`Runc`](crate::Runc) builds its arguments and parses the outputs according to its profile,
+//! so the same client can drive runc, crun or youki.
+
+use std::path::Path;
+
+use crate::{error::Error, utils};
+
+/// How a runtime accepts the global `--rootless` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootlessSupport {
+    /// The flag is not accepted, rootless mode is detected by the runtime itself.
+    Unsupported,
+    /// `--rootless=true|false` is accepted.
+    Bool,
+    /// `--rootless=true|false|auto` is accepted.
+    Auto,
+}
+
+/// The CLI surface of an OCI runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeProfile {
+    /// Name of the runtime, as printed by `--version`.
+    pub name: String,
+    /// How the global `--rootless` flag is accepted.
+    pub rootless: RootlessSupport,
+    /// Whether the global `--systemd-cgroup` flag is accepted.
+    pub systemd_cgroup: bool,
+    /// Whether the global `--criu` flag is accepted.
+    pub criu: bool,
+    /// Whether `ps` accepts `--format json`, otherwise the pids are parsed from its table output.
+    pub ps_json: bool,
+}
+
+impl Default for RuntimeProfile {
+    fn default() -> Self {
+        Self::runc()
+    }
+}
+
+impl RuntimeProfile {
+    pub fn runc() -> Self {
+        Self {
+            name: "runc".to_string(),
+            rootless: RootlessSupport::Auto,
+            systemd_cgroup: true,
+            criu: true,
+            ps_json: true,
+        }
+    }
+
+    /// crun checkpoints through libcriu, so it has no `--criu` flag.
+    pub fn crun() -> Self {
+        Self {
+            name: "crun".to_string(),
+            rootless: RootlessSupport::Bool,
+            criu: false,
+            ..Self::runc()
+        }
+    }
+
+    /// youki always detects rootless mode by itself and checkpoints through libcriu.
+    pub fn youki() -> Self {
+        Self {
+            name: "youki".to_string(),
+            rootless: RootlessSupport::Unsupported,
+            criu: false,
+            ..Self::runc()
+        }
+    }
+
+    /// Pick the profile matching the output of `<runtime> --version`.
+    ///
+    /// Unknown runtimes are assumed to be compatible with runc.
+    pub fn detect(version_output: &str) -> Result<Self, Error> {
+        let name = version_output
+            .lines()
+            .filter_map(|l| l.trim().split_once(" version "))
+            .map(|(name, _)| name.trim())
+            .find(|name| !name.is_empty() && !name.contains(char::is_whitespace))
+            .ok_or(Error::InvalidVersion)?;
+        Ok(match name {
+            "crun" => Self::crun(),
+            "youki" => Self::youki(),
+            "runc" => Self::runc(),
+            other => Self {
+                name: other.to_string(),
+                ..Self::runc()
+            },
+        })
+    }
+
+    /// Run `<command> --version` and pick the matching profile.
+    #[cfg(not(feature = "async"))]
+    pub fn detect_binary(command: impl AsRef<Path>) -> Result<Self, Error> {
+        let command = utils::binary_path(command).ok_or(Error::NotFound)?;
+        let output = std::process::Command::new(command)
+            .arg("--version")
+            .output()
+            .map_err(Error::ProcessSpawnFailed)?;
+        Self::detect_output(output)
+    }
+
+    /// Run `<command> --version` and pick the matching profile.
+    #[cfg(feature = "async")]
+    pub async fn detect_binary(command: impl AsRef<Path>) -> Result<Self, Error> {
+        let command = utils::binary_path(command).ok_or(Error::NotFound)?;
+        let output = tokio::process::Command::new(command)
+            .arg("--version")
+            .output()
+            .await
+            .map_err(Error::ProcessSpawnFailed)?;
+        Self::detect_output(output)
+    }
+
+    fn detect_output(output: std::process::Output) -> Result<Self, Error> {
+        if !output.status.success() {
+            return Err(Error::command_failed(
+                output.status,
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        Self::detect(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_test() {
+        let crun = "crun version 1.8.4\ncommit: 5a8fa99a5e41facba2eda4af12fa26313918805b\n\
+            rundir: /run/user/1000/crun\nspec: 1.0.0\n+SYSTEMD +SELINUX +APPARMOR +CAP +SECCOMP\n";
+        assert_eq!(
+            RuntimeProfile::detect(crun).unwrap(),
+            RuntimeProfile::crun()
+        );
+
+        let youki = "youki version 0.1.0\ncommit: 0.1.0-0-abcdef1\n";
+        assert_eq!(
+            RuntimeProfile::detect(youki).unwrap(),
+            RuntimeProfile::youki()
+        );
+
+        let runc = "runc version 1.1.4\ncommit: v1.1.4-0-g5fd4c4d1\nspec: 1.0.2-dev\n";
+        assert_eq!(
+            RuntimeProfile::detect(runc).unwrap(),
+            RuntimeProfile::runc()
+        );
+
+        let other = RuntimeProfile::detect("kata-runtime version 3.0.0\n").unwrap();
+        assert_eq!(other.name, "kata-runtime");
+        assert_eq!(other.rootless, RootlessSupport::Auto);
+
+        assert!(RuntimeProfile::detect("").is_err());
+        assert!(RuntimeProfile::detect("License GPLv3+: GNU GPL version 3 or later\n").is_err());
+    }
+}
@@ -0,0 +1,442 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers to run containers as an unprivileged user.
+//!
+//! [IdMappings::current] maps root in the container to the current user and the rest of the ids
+//! to the user's subordinate ids, [to_rootless] turns a spec into its rootless form, and
+//! [cgroup_delegation] tells whether the container can have cgroup resources.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use nix::unistd::{access, getegid, geteuid, AccessFlags, User};
+use oci_spec::runtime::{
+    LinuxIdMapping, LinuxNamespace, LinuxNamespaceType, Mount, MountBuilder, Spec,
+};
+
+use crate::{bundle::id_mapping, error::Error, Result};
+
+pub const SUBUID_FILE: &str = "/etc/subuid";
+pub const SUBGID_FILE: &str = "/etc/subgid";
+pub const CGROUP_MOUNTPOINT: &str = "/sys/fs/cgroup";
+
+/// A range of subordinate ids from `/etc/subuid` or `/etc/subgid`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubIdRange {
+    /// User name or numeric id owning the range.
+    pub owner: String,
+    pub start: u32,
+    pub count: u32,
+}
+
+/// Parse the `owner:start:count` lines of a subid file, skipping comments and invalid lines.
+pub fn parse_subid(content: &str) -> Vec<SubIdRange> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| {
+            let mut fields = l.split(':');
+            let owner = fields.next()?;
+            let start = fields.next()?.parse().ok()?;
+            let count = fields.next()?.parse().ok()?;
+            if owner.is_empty() || count == 0 || fields.next().is_some() {
+                return None;
+            }
+            Some(SubIdRange {
+                owner: owner.to_string(),
+                start,
+                count,
+            })
+        })
+        .collect()
+}
+
+/// Read the ranges of the subid file at `path`, none if it does not exist.
+pub fn read_subid(path: impl AsRef<Path>) -> Result<Vec<SubIdRange>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(parse_subid(&content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(Error::FileSystemError(e)),
+    }
+}
+
+/// Map root in the container to `id`, then the following ids to the ranges owned by `name` or
+/// `id`, in file order.
+pub fn id_mappings_for(ranges: &[SubIdRange], name: &str, id: u32) -> Vec<LinuxIdMapping> {
+    let id_str = id.to_string();
+    let mut mappings = vec![id_mapping(0, id, 1)];
+    let mut next = 1u32;
+    for r in ranges
+        .iter()
+        .filter(|r| r.owner == name || r.owner == id_str)
+    {
+        match next.checked_add(r.count) {
+            Some(end) => {
+                mappings.push(id_mapping(next, r.start, r.count));
+                next = end;
+            }
+            None => break,
+        }
+    }
+    mappings
+}
+
+/// The uid and gid mappings of a rootless container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMappings {
+    pub uid_mappings: Vec<LinuxIdMapping>,
+    pub gid_mappings: Vec<LinuxIdMapping>,
+}
+
+impl IdMappings {
+    /// Mappings for the current user from [SUBUID_FILE] and [SUBGID_FILE].
+    pub fn current() -> Result<Self> {
+        let (uid, gid) = (geteuid(), getegid());
+        let name = User::from_uid(uid)
+            .ok()
+            .flatten()
+            .map(|u| u.name)
+            .unwrap_or_default();
+        Self::from_files(SUBUID_FILE, SUBGID_FILE, &name, uid.as_raw(), gid.as_raw())
+    }
+
+    /// Mappings for user `name` with `uid` and `gid` from the given subid files.
+    pub fn from_files(
+        subuid: impl AsRef<Path>,
+        subgid: impl AsRef<Path>,
+        name: &str,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            uid_mappings: id_mappings_for(&read_subid(subuid)?, name, uid),
+            gid_mappings: id_mappings_for(&read_subid(subgid)?, name, gid),
+        })
+    }
+
+    /// Whether any subordinate ids are mapped besides root.
+    pub fn has_subids(&self) -> bool {
+        self.uid_mappings.len() > 1 && self.gid_mappings.len() > 1
+    }
+}
+
+/// Turn `spec` into its rootless form, like `runc spec --rootless` does.
+///
+/// The spec gets a user namespace with `mappings` and no network namespace. `/sys` is bind
+/// mounted from the host, as sysfs can't be mounted without a network namespace, and `uid=` and
+/// `gid=` mount options are dropped. Cgroup resources are dropped unless `delegated`.
+pub fn to_rootless(spec: &mut Spec, mappings: &IdMappings, delegated: bool) {
+    let linux = spec.linux_mut().get_or_insert_with(Default::default);
+    let mut namespaces = linux.namespaces().clone().unwrap_or_default();
+    namespaces
+        .retain(|n| n.typ() != LinuxNamespaceType::Network && n.typ() != LinuxNamespaceType::User);
+    let mut user_ns = LinuxNamespace::default();
+    user_ns.set_typ(LinuxNamespaceType::User);
+    namespaces.push(user_ns);
+    linux.set_namespaces(Some(namespaces));
+    linux.set_uid_mappings(Some(mappings.uid_mappings.clone()));
+    linux.set_gid_mappings(Some(mappings.gid_mappings.clone()));
+    if !delegated {
+        linux.set_resources(None);
+    }
+
+    if let Some(mounts) = spec.mounts_mut() {
+        for mount in mounts.iter_mut() {
+            if mount.destination() == Path::new("/sys") {
+                *mount = sys_bind_mount();
+            } else if let Some(options) = mount.options_mut() {
+                options.retain(|o| !o.starts_with("uid=") && !o.starts_with("gid="));
+            }
+        }
+    }
+}
+
+fn sys_bind_mount() -> Mount {
+    MountBuilder::default()
+        .destination("/sys")
+        .typ("none")
+        .source("/sys")
+        .options(
+            ["rbind", "nosuid", "noexec", "nodev", "ro"]
+                .iter()
+                .map(|o| o.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .build()
+        .expect("all fields of the mount are set")
+}
+
+/// Root dir for the state of rootless containers, `$XDG_RUNTIME_DIR/runc` like runc uses.
+///
+/// Without `$XDG_RUNTIME_DIR`, this is `/run/user/<euid>/runc` if that dir exists, or a dir
+/// under the temp dir otherwise.
+pub fn root_dir() -> PathBuf {
+    root_dir_from(
+        env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from),
+        Path::new("/run/user"),
+        geteuid().as_raw(),
+    )
+}
+
+fn root_dir_from(xdg_runtime_dir: Option<PathBuf>, run_user: &Path, euid: u32) -> PathBuf {
+    if let Some(dir) = xdg_runtime_dir.filter(|d| d.is_absolute()) {
+        return dir.join("runc");
+    }
+    let user_dir = run_user.join(euid.to_string());
+    if user_dir.is_dir() {
+        user_dir.join("runc")
+    } else {
+        env::temp_dir().join(format!("runc-{}", euid))
+    }
+}
+
+/// The cgroup v2 of a process, and what it may do with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupDelegation {
+    /// Path of the cgroup under the mountpoint.
+    pub path: PathBuf,
+    /// Controllers available in the cgroup.
+    pub controllers: Vec<String>,
+    /// Whether the process can create child cgroups and move processes into them.
+    pub writable: bool,
+}
+
+impl CgroupDelegation {
+    /// Controllers of `wanted` that are not available.
+    pub fn missing_controllers(&self, wanted: &[&str]) -> Vec<String> {
+        wanted
+            .iter()
+            .filter(|w| !self.controllers.iter().any(|c| c == *w))
+            .map(|w| w.to_string())
+            .collect()
+    }
+
+    /// Fail with [Error::CgroupNotDelegated] unless the cgroup is writable and has all the
+    /// `wanted` controllers.
+    pub fn check(&self, wanted: &[&str]) -> Result<()> {
+        if !self.writable {
+            return Err(Error::CgroupNotDelegated(format!(
+                "{} is not writable",
+                self.path.display()
+            )));
+        }
+        let missing = self.missing_controllers(wanted);
+        if !missing.is_empty() {
+            return Err(Error::CgroupNotDelegated(format!(
+                "{} lacks controllers {}",
+                self.path.display(),
+                missing.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Check the delegation of the cgroup of the current process.
+///
+/// Returns [None] without a unified cgroup v2 hierarchy, where rootless containers can't use
+/// cgroups at all.
+pub fn cgroup_delegation() -> Result<Option<CgroupDelegation>> {
+    let proc_cgroup = fs::read_to_string("/proc/self/cgroup").map_err(Error::FileSystemError)?;
+    cgroup_delegation_at(Path::new(CGROUP_MOUNTPOINT), &proc_cgroup)
+}
+
+/// Check the delegation of the cgroup found in `proc_cgroup`, the content of
+/// `/proc/<pid>/cgroup`, in the cgroup v2 hierarchy mounted at `mountpoint`.
+pub fn cgroup_delegation_at(
+    mountpoint: &Path,
+    proc_cgroup: &str,
+) -> Result<Option<CgroupDelegation>> {
+    if !mountpoint.join("cgroup.controllers").exists() {
+        return Ok(None);
+    }
+    let path = match proc_cgroup.lines().find_map(|l| l.strip_prefix("0::")) {
+        Some(path) => mountpoint.join(path.trim().trim_start_matches('/')),
+        None => return Ok(None),
+    };
+    let controllers = fs::read_to_string(path.join("cgroup.controllers"))
+        .map_err(Error::FileSystemError)?
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    let writable = [path.clone(), path.join("cgroup.procs")]
+        .iter()
+        .all(|p| access(p.as_path(), AccessFlags::W_OK).is_ok());
+    Ok(Some(CgroupDelegation {
+        path,
+        controllers,
+        writable,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBUID: &str = "
+# comment
+alice:100000:65536
+1000:300000:1000
+bob:200000:65536
+invalid line
+carol:x:10
+alice:500000:0
+";
+
+    #[test]
+    fn parse_subid_test() {
+        let ranges = parse_subid(SUBUID);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(
+            ranges[0],
+            SubIdRange {
+                owner: "alice".to_string(),
+                start: 100000,
+                count: 65536,
+            }
+        );
+        assert_eq!(ranges[1].owner, "1000");
+        assert_eq!(ranges[2].start, 200000);
+    }
+
+    #[test]
+    fn id_mappings_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let subuid = dir.path().join("subuid");
+        fs::write(&subuid, SUBUID).unwrap();
+        let missing = dir.path().join("subgid");
+
+        let mappings = IdMappings::from_files(&subuid, missing, "alice", 1000, 1000).unwrap();
+        assert_eq!(
+            mappings.uid_mappings,
+            vec![
+                id_mapping(0, 1000, 1),
+                id_mapping(1, 100000, 65536),
+                id_mapping(65537, 300000, 1000),
+            ]
+        );
+        assert_eq!(mappings.gid_mappings, vec![id_mapping(0, 1000, 1)]);
+        assert!(!mappings.has_subids());
+
+        let mappings = IdMappings::from_files(&subuid, &subuid, "bob", 1001, 1001).unwrap();
+        assert_eq!(mappings.uid_mappings[1], id_mapping(1, 200000, 65536));
+        assert!(mappings.has_subids());
+    }
+
+    #[test]
+    fn to_rootless_test() {
+        let mut spec = Spec::default();
+        assert!(spec.linux().as_ref().unwrap().resources().is_some());
+        let mappings = IdMappings {
+            uid_mappings: vec![id_mapping(0, 1000, 1), id_mapping(1, 100000, 65536)],
+            gid_mappings: vec![id_mapping(0, 1000, 1)],
+        };
+        to_rootless(&mut spec, &mappings, false);
+
+        let linux = spec.linux().as_ref().unwrap();
+        let types: Vec<_> = linux
+            .namespaces()
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|n| n.typ())
+            .collect();
+        assert!(!types.contains(&LinuxNamespaceType::Network));
+        assert_eq!(
+            types
+                .iter()
+                .filter(|t| **t == LinuxNamespaceType::User)
+                .count(),
+            1
+        );
+        assert_eq!(linux.uid_mappings().as_ref(), Some(&mappings.uid_mappings));
+        assert!(linux.resources().is_none());
+
+        let mounts = spec.mounts().as_ref().unwrap();
+        let sys = mounts
+            .iter()
+            .find(|m| m.destination() == Path::new("/sys"))
+            .unwrap();
+        assert_eq!(sys.typ().as_deref(), Some("none"));
+        assert!(mounts
+            .iter()
+            .flat_map(|m| m.options().clone().unwrap_or_default())
+            .all(|o| !o.starts_with("gid=") && !o.starts_with("uid=")));
+
+        let mut spec = Spec::default();
+        to_rootless(&mut spec, &mappings, true);
+        assert!(spec.linux().as_ref().unwrap().resources().is_some());
+    }
+
+    #[test]
+    fn root_dir_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let xdg = Some(PathBuf::from("/run/user/1000"));
+        assert_eq!(
+            root_dir_from(xdg, dir.path(), 1000),
+            PathBuf::from("/run/user/1000/runc")
+        );
+
+        fs::create_dir(dir.path().join("1000")).unwrap();
+        assert_eq!(
+            root_dir_from(Some(PathBuf::from("relative")), dir.path(), 1000),
+            dir.path().join("1000/runc")
+        );
+        assert_eq!(
+            root_dir_from(None, dir.path(), 1001),
+            env::temp_dir().join("runc-1001")
+        );
+    }
+
+    #[test]
+    fn cgroup_delegation_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let proc_cgroup = "0::/user.slice/user-1000.slice/app.scope\n";
+        assert_eq!(cgroup_delegation_at(root, proc_cgroup).unwrap(), None);
+
+        fs::write(root.join("cgroup.controllers"), "cpu memory pids io").unwrap();
+        let cgroup = root.join("user.slice/user-1000.slice/app.scope");
+        fs::create_dir_all(&cgroup).unwrap();
+        fs::write(cgroup.join("cgroup.controllers"), "memory pids\n").unwrap();
+        fs::write(cgroup.join("cgroup.procs"), "").unwrap();
+
+        let delegation = cgroup_delegation_at(root, proc_cgroup).unwrap().unwrap();
+        assert_eq!(delegation.path, cgroup);
+        assert_eq!(delegation.controllers, vec!["memory", "pids"]);
+        assert_eq!(
+            delegation.missing_controllers(&["cpu", "memory"]),
+            vec!["cpu"]
+        );
+        if delegation.writable {
+            assert!(delegation.check(&["memory", "pids"]).is_ok());
+        }
+        assert!(matches!(
+            delegation.check(&["cpu"]),
+            Err(Error::CgroupNotDelegated(_))
+        ));
+
+        // cgroup v1 only
+        assert_eq!(
+            cgroup_delegation_at(root, "4:memory:/user.slice\n").unwrap(),
+            None
+        );
+    }
+}
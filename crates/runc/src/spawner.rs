@@ -0,0 +1,1144 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Composable [Spawner] layers.
+//!
+//! Each layer wraps another [Spawner], so that they can be stacked and passed to
+//! [GlobalOpts::custom_spawner](crate::options::GlobalOpts::custom_spawner):
+//!
+//! ```ignore
+//! let spawner = LoggingSpawner::new(RetrySpawner::new(LimitSpawner::new(DefaultExecutor {}, 8)));
+//! opts.custom_spawner(Arc::new(spawner));
+//! ```
+//!
+//! [RecordSpawner] saves the commands run and their results to a file, which [ReplaySpawner]
+//! plays back without a runc binary. Temp files and dirs in the arguments are saved as
+//! [PATH_PLACEHOLDER], so that a transcript plays back on another run.
+
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ::log::{debug, warn};
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    options::{
+        CONSOLE_SOCKET, CRIU, IMAGE_PATH, LOG, LOG_FORMAT, PARENT_PATH, PID_FILE, ROOT, WORK_PATH,
+    },
+    utils, Command, Result, Spawner,
+};
+
+/// What [Spawner::execute] returns: exit status, pid, stdout and stderr.
+type Output = (ExitStatus, u32, String, String);
+
+#[cfg(not(feature = "async"))]
+fn std_command(cmd: &Command) -> &std::process::Command {
+    cmd
+}
+
+#[cfg(feature = "async")]
+fn std_command(cmd: &Command) -> &std::process::Command {
+    cmd.as_std()
+}
+
+/// The arguments of `cmd`, without the program.
+fn command_args(cmd: &Command) -> Vec<String> {
+    std_command(cmd)
+        .get_args()
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// The runc subcommand of `args`, after the global flags.
+fn subcommand(args: &[String]) -> Option<&str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if [ROOT, LOG, LOG_FORMAT, CRIU].contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            return Some(arg);
+        }
+    }
+    None
+}
+
+/// Build a copy of `cmd` with the program, arguments, environment and working directory.
+///
+/// Stdio and pre-exec hooks can't be read back from a command, so the copy gets the default stdio
+/// of [Runc](crate::Runc), and a new process group if `set_pgid` is set. It is only the same
+/// command for those [Runc](crate::Runc) runs without [Io](crate::io::Io) or preserved fds.
+fn copy_command(cmd: &Command, set_pgid: bool) -> Command {
+    let orig = std_command(cmd);
+    let mut cmd = Command::new(orig.get_program());
+    cmd.args(orig.get_args());
+    for (key, value) in orig.get_envs() {
+        match value {
+            Some(value) => cmd.env(key, value),
+            None => cmd.env_remove(key),
+        };
+    }
+    if let Some(dir) = orig.get_current_dir() {
+        cmd.current_dir(dir);
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if set_pgid {
+        utils::set_pgid(&mut cmd);
+    }
+    cmd
+}
+
+fn log_result(args: &[String], start: Instant, res: &Result<Output>) {
+    let elapsed = start.elapsed();
+    match res {
+        Ok((status, pid, _, _)) => debug!(
+            "runc {:?} (pid {}) exited with {} in {:?}",
+            args, pid, status, elapsed
+        ),
+        Err(e) => warn!("runc {:?} failed after {:?}: {}", args, elapsed, e),
+    }
+}
+
+fn log_spawn(args: &[String], res: std::result::Result<Option<u32>, &Error>) {
+    match res {
+        Ok(pid) => debug!("runc {:?} started (pid {:?})", args, pid),
+        Err(e) => warn!("runc {:?} failed to start: {}", args, e),
+    }
+}
+
+/// Logs the arguments, duration and exit status of each command.
+#[derive(Debug)]
+pub struct LoggingSpawner<S> {
+    inner: S,
+}
+
+impl<S> LoggingSpawner<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<S: Spawner> Spawner for LoggingSpawner<S> {
+    fn execute(&self, cmd: Command) -> Result<Output> {
+        let args = command_args(&cmd);
+        let start = Instant::now();
+        let res = self.inner.execute(cmd);
+        log_result(&args, start, &res);
+        res
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<std::process::Child> {
+        let args = command_args(&cmd);
+        let res = self.inner.spawn(cmd);
+        log_spawn(&args, res.as_ref().map(|c| Some(c.id())));
+        res
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<S: Spawner + Send + Sync> Spawner for LoggingSpawner<S> {
+    async fn execute(&self, cmd: Command) -> Result<Output> {
+        let args = command_args(&cmd);
+        let start = Instant::now();
+        let res = self.inner.execute(cmd).await;
+        log_result(&args, start, &res);
+        res
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<tokio::process::Child> {
+        let args = command_args(&cmd);
+        let res = self.inner.spawn(cmd);
+        log_spawn(&args, res.as_ref().map(|c| c.id()));
+        res
+    }
+}
+
+/// Messages runc prints when a syscall failed with `EAGAIN` or `EBUSY`.
+const TRANSIENT_MESSAGES: &[&str] = &[
+    "resource temporarily unavailable",
+    "device or resource busy",
+];
+
+/// Whether a command failed with `EAGAIN` or `EBUSY`, either when spawning runc or in runc.
+fn is_transient(res: &Result<Output>) -> bool {
+    match res {
+        Ok((status, _, _, stderr)) => {
+            let stderr = stderr.to_lowercase();
+            !status.success() && TRANSIENT_MESSAGES.iter().any(|m| stderr.contains(m))
+        }
+        Err(Error::ProcessSpawnFailed(e)) => matches!(
+            e.raw_os_error(),
+            Some(libc::EAGAIN) | Some(libc::EBUSY) | Some(libc::ETXTBSY)
+        ),
+        Err(_) => false,
+    }
+}
+
+/// Subcommands that [Runc](crate::Runc) always runs with its default stdio and no pre-exec hooks.
+const RETRY_COMMANDS: &[&str] = &[
+    "state", "ps", "list", "pause", "resume", "kill", "delete", "update",
+];
+
+/// Retries commands that failed with `EAGAIN` or `EBUSY`, doubling the backoff each time.
+///
+/// The retried commands are copies of the original one, see [RetrySpawner::set_pgid], so only
+/// the subcommands set by [RetrySpawner::commands] are retried. Other commands run once, as is.
+#[derive(Debug)]
+pub struct RetrySpawner<S> {
+    inner: S,
+    attempts: u32,
+    backoff: Duration,
+    set_pgid: bool,
+    commands: Vec<String>,
+}
+
+impl<S> RetrySpawner<S> {
+    /// Retry up to 3 attempts, starting with a 100ms backoff.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            attempts: 3,
+            backoff: Duration::from_millis(100),
+            set_pgid: false,
+            commands: RETRY_COMMANDS.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// Set the total number of attempts, including the first one.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Set the backoff before the first retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Run retried commands in a new process group, matching
+    /// [GlobalOpts::set_pgid](crate::options::GlobalOpts::set_pgid).
+    pub fn set_pgid(mut self, set_pgid: bool) -> Self {
+        self.set_pgid = set_pgid;
+        self
+    }
+
+    /// Set the subcommands to retry, `state`, `ps`, `list`, `pause`, `resume`, `kill`, `delete`
+    /// and `update` by default.
+    ///
+    /// A retry runs a copy of the command with runc's default stdio, so commands run with
+    /// [Io](crate::io::Io) or preserved fds, like `create`, `run` or `exec`, must not be listed.
+    pub fn commands<I, C>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = C>,
+        C: Into<String>,
+    {
+        self.commands = commands.into_iter().map(Into::into).collect();
+        self
+    }
+
+    fn attempts_for(&self, cmd: &Command) -> u32 {
+        match subcommand(&command_args(cmd)) {
+            Some(sub) if self.commands.iter().any(|c| c == sub) => self.attempts,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<S: Spawner> Spawner for RetrySpawner<S> {
+    fn execute(&self, cmd: Command) -> Result<Output> {
+        let mut cmd = cmd;
+        let mut backoff = self.backoff;
+        for _ in 1..self.attempts_for(&cmd) {
+            let next = copy_command(&cmd, self.set_pgid);
+            let res = self.inner.execute(cmd);
+            if !is_transient(&res) {
+                return res;
+            }
+            debug!("retrying runc {:?} in {:?}", command_args(&next), backoff);
+            std::thread::sleep(backoff);
+            backoff *= 2;
+            cmd = next;
+        }
+        self.inner.execute(cmd)
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<std::process::Child> {
+        self.inner.spawn(cmd)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<S: Spawner + Send + Sync> Spawner for RetrySpawner<S> {
+    async fn execute(&self, cmd: Command) -> Result<Output> {
+        let mut cmd = cmd;
+        let mut backoff = self.backoff;
+        for _ in 1..self.attempts_for(&cmd) {
+            let next = copy_command(&cmd, self.set_pgid);
+            let res = self.inner.execute(cmd).await;
+            if !is_transient(&res) {
+                return res;
+            }
+            debug!("retrying runc {:?} in {:?}", command_args(&next), backoff);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            cmd = next;
+        }
+        self.inner.execute(cmd).await
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<tokio::process::Child> {
+        self.inner.spawn(cmd)
+    }
+}
+
+/// Limits the number of commands running at the same time.
+#[derive(Debug)]
+pub struct LimitSpawner<S> {
+    inner: S,
+    #[cfg(not(feature = "async"))]
+    running: Mutex<usize>,
+    #[cfg(not(feature = "async"))]
+    released: std::sync::Condvar,
+    #[cfg(not(feature = "async"))]
+    limit: usize,
+    #[cfg(feature = "async")]
+    semaphore: tokio::sync::Semaphore,
+}
+
+impl<S> LimitSpawner<S> {
+    /// Run at most `limit` commands at once, at least one.
+    pub fn new(inner: S, limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            inner,
+            #[cfg(not(feature = "async"))]
+            running: Mutex::new(0),
+            #[cfg(not(feature = "async"))]
+            released: std::sync::Condvar::new(),
+            #[cfg(not(feature = "async"))]
+            limit,
+            #[cfg(feature = "async")]
+            semaphore: tokio::sync::Semaphore::new(limit),
+        }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<S> LimitSpawner<S> {
+    /// Wait for a free slot, released when the returned guard is dropped, even on panic.
+    fn acquire(&self) -> Slot<'_, S> {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        while *running >= self.limit {
+            running = self
+                .released
+                .wait(running)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *running += 1;
+        Slot { spawner: self }
+    }
+}
+
+/// A running command of a [LimitSpawner], like the permit of the async semaphore.
+#[cfg(not(feature = "async"))]
+struct Slot<'a, S> {
+    spawner: &'a LimitSpawner<S>,
+}
+
+#[cfg(not(feature = "async"))]
+impl<S> Drop for Slot<'_, S> {
+    fn drop(&mut self) {
+        *self
+            .spawner
+            .running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) -= 1;
+        self.spawner.released.notify_one();
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<S: Spawner> Spawner for LimitSpawner<S> {
+    fn execute(&self, cmd: Command) -> Result<Output> {
+        let _slot = self.acquire();
+        self.inner.execute(cmd)
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<std::process::Child> {
+        self.inner.spawn(cmd)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<S: Spawner + Send + Sync> Spawner for LimitSpawner<S> {
+    async fn execute(&self, cmd: Command) -> Result<Output> {
+        let _permit = self
+            .semaphore
+            .acquire()
+            .await
+            .map_err(|e| Error::Other(Box::new(e)))?;
+        self.inner.execute(cmd).await
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<tokio::process::Child> {
+        self.inner.spawn(cmd)
+    }
+}
+
+/// Flags taking a path, most often to a temp file or dir that changes from one run to the next.
+const PATH_FLAGS: &[&str] = &[
+    ROOT,
+    LOG,
+    CRIU,
+    CONSOLE_SOCKET,
+    PID_FILE,
+    IMAGE_PATH,
+    WORK_PATH,
+    PARENT_PATH,
+    "--bundle",
+    "--process",
+    "--resources",
+];
+
+/// Saved by [RecordSpawner] instead of the values of the flags taking a path.
+pub const PATH_PLACEHOLDER: &str = "<path>";
+
+/// Replace the values of the flags taking a path, like `--process` or `--pid-file`, with
+/// [PATH_PLACEHOLDER], so that a transcript matches the same command run with other temp files.
+pub fn normalize_args(args: &[String]) -> Vec<String> {
+    let mut normalized = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.split_once('=') {
+            Some((flag, _)) if PATH_FLAGS.contains(&flag) => {
+                normalized.push(format!("{}={}", flag, PATH_PLACEHOLDER));
+            }
+            _ if PATH_FLAGS.contains(&arg.as_str()) => {
+                normalized.push(arg.clone());
+                if args.next().is_some() {
+                    normalized.push(PATH_PLACEHOLDER.to_string());
+                }
+            }
+            _ => normalized.push(arg.clone()),
+        }
+    }
+    normalized
+}
+
+/// A command run by runc and its result, as saved by [RecordSpawner].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transcript {
+    /// Arguments of the command, without the program, see [normalize_args].
+    pub args: Vec<String>,
+    /// Raw wait status of the command.
+    pub status: i32,
+    pub pid: u32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Transcript {
+    /// A spawned command, whose exit is waited for by the caller and is saved as a success.
+    fn spawned(args: Vec<String>, pid: u32, stdout: &[u8]) -> Self {
+        Self {
+            args,
+            status: 0,
+            pid,
+            stdout: String::from_utf8_lossy(stdout).into_owned(),
+            stderr: String::new(),
+        }
+    }
+}
+
+/// Read the transcripts saved by [RecordSpawner] at `path`.
+pub fn read_transcripts(path: impl AsRef<Path>) -> Result<Vec<Transcript>> {
+    let file = std::fs::File::open(path).map_err(Error::FileSystemError)?;
+    let mut transcripts = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(Error::FileSystemError)?;
+        if !line.trim().is_empty() {
+            transcripts.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(transcripts)
+}
+
+/// The file [RecordSpawner] appends to, shared with the relays of the spawned commands.
+#[derive(Debug)]
+struct TranscriptFile {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl TranscriptFile {
+    fn append(&self, transcript: &Transcript) -> Result<()> {
+        let mut line = serde_json::to_string(transcript)?;
+        line.push('\n');
+        let _guard = self.lock.lock().unwrap();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| f.write_all(line.as_bytes()))
+            .map_err(Error::FileSystemError)
+    }
+
+    fn append_spawned(&self, transcript: &Transcript) {
+        if let Err(e) = self.append(transcript) {
+            warn!("failed to record runc {:?}: {}", transcript.args, e);
+        }
+    }
+}
+
+/// Appends a [Transcript] of each command to a file, one JSON object per line.
+///
+/// The arguments are saved with [normalize_args]. The stdout of a spawned command, like
+/// `runc events`, is relayed to the caller through `cat` and saved once runc closes it.
+#[derive(Debug)]
+pub struct RecordSpawner<S> {
+    inner: S,
+    file: Arc<TranscriptFile>,
+}
+
+impl<S> RecordSpawner<S> {
+    pub fn new(inner: S, path: impl AsRef<Path>) -> Self {
+        Self {
+            inner,
+            file: Arc::new(TranscriptFile {
+                path: path.as_ref().to_path_buf(),
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    fn record(&self, args: Vec<String>, res: &Result<Output>) -> Result<()> {
+        let (status, pid, stdout, stderr) = match res {
+            Ok(output) => output,
+            // Nothing ran, so there is nothing to replay.
+            Err(_) => return Ok(()),
+        };
+        self.file.append(&Transcript {
+            args,
+            status: status.into_raw(),
+            pid: *pid,
+            stdout: stdout.clone(),
+            stderr: stderr.clone(),
+        })
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<S: Spawner> Spawner for RecordSpawner<S> {
+    fn execute(&self, cmd: Command) -> Result<Output> {
+        let args = normalize_args(&command_args(&cmd));
+        let res = self.inner.execute(cmd);
+        self.record(args, &res)?;
+        res
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<std::process::Child> {
+        use std::io::Read;
+
+        let args = normalize_args(&command_args(&cmd));
+        let mut relay = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(Error::ProcessSpawnFailed)?;
+        let mut input = relay.stdin.take();
+        let mut child = match self.inner.spawn(cmd) {
+            Ok(child) => child,
+            Err(e) => {
+                drop(input);
+                let _ = relay.wait();
+                return Err(e);
+            }
+        };
+        let stdout = std::mem::replace(&mut child.stdout, relay.stdout.take());
+        let (file, pid) = (self.file.clone(), child.id());
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Some(mut stdout) = stdout {
+                let mut buf = [0; 4096];
+                while let Ok(n @ 1..) = stdout.read(&mut buf) {
+                    output.extend_from_slice(&buf[..n]);
+                    // Keep reading for the transcript when the caller stopped reading.
+                    if let Some(Err(_)) = input.as_mut().map(|i| i.write_all(&buf[..n])) {
+                        input = None;
+                    }
+                }
+            }
+            // Saved before the caller gets EOF, so that it is there once the output is read.
+            file.append_spawned(&Transcript::spawned(args, pid, &output));
+            drop(input);
+            let _ = relay.wait();
+        });
+        Ok(child)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<S: Spawner + Send + Sync> Spawner for RecordSpawner<S> {
+    async fn execute(&self, cmd: Command) -> Result<Output> {
+        let args = normalize_args(&command_args(&cmd));
+        let res = self.inner.execute(cmd).await;
+        self.record(args, &res)?;
+        res
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<tokio::process::Child> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let args = normalize_args(&command_args(&cmd));
+        let mut relay = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(Error::ProcessSpawnFailed)?;
+        let mut input = relay.stdin.take();
+        let mut child = match self.inner.spawn(cmd) {
+            Ok(child) => child,
+            Err(e) => {
+                drop(input);
+                tokio::spawn(async move { relay.wait().await });
+                return Err(e);
+            }
+        };
+        let stdout = std::mem::replace(&mut child.stdout, relay.stdout.take());
+        let (file, pid) = (self.file.clone(), child.id().unwrap_or_default());
+        tokio::spawn(async move {
+            let mut output = Vec::new();
+            if let Some(mut stdout) = stdout {
+                let mut buf = [0; 4096];
+                while let Ok(n @ 1..) = stdout.read(&mut buf).await {
+                    output.extend_from_slice(&buf[..n]);
+                    // Keep reading for the transcript when the caller stopped reading.
+                    if let Some(i) = input.as_mut() {
+                        if i.write_all(&buf[..n]).await.is_err() {
+                            input = None;
+                        }
+                    }
+                }
+            }
+            // Saved before the caller gets EOF, so that it is there once the output is read.
+            file.append_spawned(&Transcript::spawned(args, pid, &output));
+            drop(input);
+            let _ = relay.wait().await;
+        });
+        Ok(child)
+    }
+}
+
+/// Plays back recorded [Transcript]s instead of running commands.
+///
+/// Each command gets the first remaining transcript with the same arguments, compared with
+/// [normalize_args], so commands run several times are played back in the recorded order.
+/// Spawned commands are played back by a shell printing the recorded output.
+#[derive(Debug)]
+pub struct ReplaySpawner {
+    transcripts: Mutex<VecDeque<Transcript>>,
+}
+
+impl ReplaySpawner {
+    pub fn new(transcripts: Vec<Transcript>) -> Self {
+        let transcripts = transcripts
+            .into_iter()
+            .map(|t| Transcript {
+                args: normalize_args(&t.args),
+                ..t
+            })
+            .collect();
+        Self {
+            transcripts: Mutex::new(transcripts),
+        }
+    }
+
+    /// Load the transcripts saved by [RecordSpawner] at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(read_transcripts(path)?))
+    }
+
+    /// Number of transcripts not played back yet.
+    pub fn remaining(&self) -> usize {
+        self.transcripts.lock().unwrap().len()
+    }
+
+    fn take(&self, cmd: &Command) -> Result<Transcript> {
+        let args = normalize_args(&command_args(cmd));
+        let mut transcripts = self.transcripts.lock().unwrap();
+        let index = transcripts
+            .iter()
+            .position(|t| t.args == args)
+            .ok_or_else(|| Error::TranscriptNotFound(args.join(" ")))?;
+        Ok(transcripts.remove(index).unwrap())
+    }
+
+    fn replay(&self, cmd: &Command) -> Result<Output> {
+        let t = self.take(cmd)?;
+        Ok((ExitStatus::from_raw(t.status), t.pid, t.stdout, t.stderr))
+    }
+
+    /// A shell printing the output of the transcript of `cmd` and exiting with its status.
+    fn replay_command(&self, cmd: &Command) -> Result<Command> {
+        let t = self.take(cmd)?;
+        let status = ExitStatus::from_raw(t.status);
+        let exit = match status.signal() {
+            Some(signal) => format!("kill -{} $$", signal),
+            None => format!("exit {}", status.code().unwrap_or(1)),
+        };
+        // Fed through stdin, as the output of a long running command may not fit in an argument.
+        let mut stdout = tempfile::tempfile().map_err(Error::FileSystemError)?;
+        stdout
+            .write_all(t.stdout.as_bytes())
+            .and_then(|_| stdout.seek(SeekFrom::Start(0)))
+            .map_err(Error::FileSystemError)?;
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c")
+            .arg(format!("cat; printf '%s' \"$1\" >&2; {}", exit))
+            .arg("sh")
+            .arg(t.stderr)
+            .stdin(stdout)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        Ok(cmd)
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Spawner for ReplaySpawner {
+    fn execute(&self, cmd: Command) -> Result<Output> {
+        self.replay(&cmd)
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<std::process::Child> {
+        self.replay_command(&cmd)?
+            .spawn()
+            .map_err(Error::ProcessSpawnFailed)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl Spawner for ReplaySpawner {
+    async fn execute(&self, cmd: Command) -> Result<Output> {
+        self.replay(&cmd)
+    }
+
+    fn spawn(&self, cmd: Command) -> Result<tokio::process::Child> {
+        self.replay_command(&cmd)?
+            .spawn()
+            .map_err(Error::ProcessSpawnFailed)
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use oci_spec::runtime::{LinuxResources, Process};
+
+    use super::*;
+    use crate::{
+        events::EventType,
+        fake_runc,
+        options::{ExecOpts, GlobalOpts},
+        DefaultExecutor, Runc,
+    };
+
+    /// Fails with EAGAIN on the first run, then succeeds.
+    const FLAKY_SCRIPT: &str = r#"
+dir=$(dirname "$0")
+if [ ! -e "$dir/ran" ]; then
+    touch "$dir/ran"
+    echo "resource temporarily unavailable" >&2
+    exit 1
+fi
+echo "runc version 1.1.4"
+"#;
+
+    /// Prints a version for `--version`, an event for `events`, accepts `exec` and `update`, and
+    /// fails anything else.
+    const VERSION_SCRIPT: &str = r#"
+case "$*" in
+    *--version*) echo "runc version 1.1.4" ;;
+    *events*) echo '{"type":"oom","id":"fake-id"}' ;;
+    *exec*|*update*) ;;
+    *) echo "container \"fake-id\" does not exist" >&2; exit 1 ;;
+esac
+"#;
+
+    fn client(command: &Path, spawner: impl Spawner + Send + Sync + 'static) -> Runc {
+        let mut opts = GlobalOpts::new().command(command);
+        opts.custom_spawner(Arc::new(spawner));
+        opts.build().unwrap()
+    }
+
+    /// Counts the commands running at the same time.
+    #[derive(Debug, Default)]
+    struct Counting {
+        running: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    impl Counting {
+        fn enter(&self) {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(running, Ordering::SeqCst);
+        }
+
+        fn leave(&self) -> Result<Output> {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok((ExitStatus::from_raw(0), 1, String::new(), String::new()))
+        }
+    }
+
+    #[test]
+    fn subcommand_test() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            subcommand(&args(&[
+                "--root",
+                "/run/runc",
+                "--debug",
+                "--log-format",
+                "json",
+                "--rootless=auto",
+                "state",
+                "exec"
+            ])),
+            Some("state")
+        );
+        assert_eq!(subcommand(&args(&["--version"])), None);
+    }
+
+    #[test]
+    fn normalize_args_test() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            normalize_args(&args(&[
+                "--root",
+                "/tmp/.tmpAbc",
+                "--log=/tmp/.tmpAbc/log.json",
+                "exec",
+                "--process",
+                "/run/user/0/runc-process-1234",
+                "--detach",
+                "fake-id",
+            ])),
+            args(&[
+                "--root",
+                PATH_PLACEHOLDER,
+                "--log=<path>",
+                "exec",
+                "--process",
+                PATH_PLACEHOLDER,
+                "--detach",
+                "fake-id",
+            ])
+        );
+    }
+
+    #[test]
+    fn copy_command_test() {
+        let mut cmd = Command::new("runc");
+        cmd.args(["--root", "/run/runc", "state", "id"])
+            .env("FOO", "bar")
+            .env_remove("NOTIFY_SOCKET")
+            .current_dir("/tmp");
+        let copy = copy_command(&cmd, false);
+        let (orig, copy) = (std_command(&cmd), std_command(&copy));
+        assert_eq!(copy.get_program(), "runc");
+        assert!(orig.get_args().eq(copy.get_args()));
+        assert!(orig.get_envs().eq(copy.get_envs()));
+        assert_eq!(copy.get_current_dir(), Some(Path::new("/tmp")));
+    }
+
+    #[cfg(not(feature = "async"))]
+    impl Spawner for Counting {
+        fn execute(&self, _cmd: Command) -> Result<Output> {
+            self.enter();
+            std::thread::sleep(Duration::from_millis(50));
+            self.leave()
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[async_trait]
+    impl Spawner for Counting {
+        async fn execute(&self, _cmd: Command) -> Result<Output> {
+            self.enter();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.leave()
+        }
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn retry_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_runc(&dir, FLAKY_SCRIPT);
+        let spawner = RetrySpawner::new(DefaultExecutor {}).backoff(Duration::from_millis(1));
+        let retrying = client(&runc, LoggingSpawner::new(spawner));
+        retrying.pause("fake-id").unwrap();
+
+        // not a retried subcommand
+        std::fs::remove_file(dir.path().join("ran")).unwrap();
+        assert!(retrying.version().is_err());
+
+        std::fs::remove_file(dir.path().join("ran")).unwrap();
+        let spawner = RetrySpawner::new(DefaultExecutor {}).attempts(1);
+        assert!(client(&runc, spawner).pause("fake-id").is_err());
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn limit_test() {
+        let spawner = Arc::new(LimitSpawner::new(Counting::default(), 2));
+        let handles: Vec<_> = (0..6)
+            .map(|_| {
+                let spawner = spawner.clone();
+                std::thread::spawn(move || spawner.execute(Command::new("runc")).unwrap())
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(spawner.inner.max.load(Ordering::SeqCst), 2);
+        assert_eq!(spawner.inner.running.load(Ordering::SeqCst), 0);
+    }
+
+    /// Panics on every command.
+    #[cfg(not(feature = "async"))]
+    #[derive(Debug)]
+    struct Panicking;
+
+    #[cfg(not(feature = "async"))]
+    impl Spawner for Panicking {
+        fn execute(&self, _cmd: Command) -> Result<Output> {
+            panic!("runc exploded")
+        }
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn limit_panic_test() {
+        let spawner = Arc::new(LimitSpawner::new(Panicking, 1));
+        for _ in 0..2 {
+            let spawner = spawner.clone();
+            let res = std::thread::spawn(move || spawner.execute(Command::new("runc"))).join();
+            assert!(res.is_err());
+        }
+        assert_eq!(*spawner.running.lock().unwrap(), 0);
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn record_replay_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_runc(&dir, VERSION_SCRIPT);
+        let transcript = dir.path().join("transcript.json");
+
+        let recorder = client(&runc, RecordSpawner::new(DefaultExecutor {}, &transcript));
+        recorder.version().unwrap();
+        assert!(recorder.pause("fake-id").is_err());
+        let transcripts = read_transcripts(&transcript).unwrap();
+        assert_eq!(transcripts.len(), 2);
+        assert!(transcripts[0].args.ends_with(&["--version".to_string()]));
+
+        let replayer = Arc::new(ReplaySpawner::load(&transcript).unwrap());
+        let mut opts = GlobalOpts::new().command("/bin/false");
+        opts.custom_spawner(replayer.clone());
+        let runc = opts.build().unwrap();
+        let version = runc.version().unwrap();
+        assert_eq!(version.runc_version.as_deref(), Some("1.1.4"));
+        assert!(runc.pause("fake-id").is_err());
+        assert_eq!(replayer.remaining(), 0);
+        assert!(matches!(
+            runc.pause("fake-id"),
+            Err(Error::TranscriptNotFound(_))
+        ));
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn replay_temp_paths_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_runc(&dir, VERSION_SCRIPT);
+        let transcript = dir.path().join("transcript.json");
+        let opts = ExecOpts::new().pid_file(dir.path().join("exec.pid"));
+
+        let recorder = client(&runc, RecordSpawner::new(DefaultExecutor {}, &transcript));
+        recorder
+            .exec("fake-id", &Process::default(), Some(&opts))
+            .unwrap();
+        recorder
+            .update("fake-id", &LinuxResources::default())
+            .unwrap();
+        let events = recorder.events("fake-id", &Duration::from_secs(1)).unwrap();
+        assert_eq!(events.count(), 1);
+        let transcripts = read_transcripts(&transcript).unwrap();
+        assert_eq!(transcripts.len(), 3);
+        assert!(transcripts[0].args.contains(&PATH_PLACEHOLDER.to_string()));
+
+        // other temp files, and another pid file
+        let replayer = Arc::new(ReplaySpawner::load(&transcript).unwrap());
+        let mut opts = GlobalOpts::new().command("/bin/false");
+        opts.custom_spawner(replayer.clone());
+        let runc = opts.build().unwrap();
+        let opts = ExecOpts::new().pid_file("/run/other.pid");
+        runc.exec("fake-id", &Process::default(), Some(&opts))
+            .unwrap();
+        runc.update("fake-id", &LinuxResources::default()).unwrap();
+        let mut events = runc.events("fake-id", &Duration::from_secs(1)).unwrap();
+        let event = events.next().unwrap().unwrap();
+        assert!(matches!(event.event_type, EventType::Oom));
+        assert!(events.next().is_none());
+        assert_eq!(replayer.remaining(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn retry_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_runc(&dir, FLAKY_SCRIPT);
+        let spawner = RetrySpawner::new(DefaultExecutor {}).backoff(Duration::from_millis(1));
+        let retrying = client(&runc, LoggingSpawner::new(spawner));
+        retrying.pause("fake-id").await.unwrap();
+
+        // not a retried subcommand
+        std::fs::remove_file(dir.path().join("ran")).unwrap();
+        assert!(retrying.version().await.is_err());
+
+        std::fs::remove_file(dir.path().join("ran")).unwrap();
+        let spawner = RetrySpawner::new(DefaultExecutor {}).attempts(1);
+        assert!(client(&runc, spawner).pause("fake-id").await.is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn limit_test() {
+        let spawner = LimitSpawner::new(Counting::default(), 2);
+        let runs = (0..6).map(|_| spawner.execute(Command::new("runc")));
+        for res in futures::future::join_all(runs).await {
+            res.unwrap();
+        }
+        assert_eq!(spawner.inner.max.load(Ordering::SeqCst), 2);
+        assert_eq!(spawner.inner.running.load(Ordering::SeqCst), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn record_replay_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_runc(&dir, VERSION_SCRIPT);
+        let transcript = dir.path().join("transcript.json");
+
+        let recorder = client(&runc, RecordSpawner::new(DefaultExecutor {}, &transcript));
+        recorder.version().await.unwrap();
+        assert!(recorder.pause("fake-id").await.is_err());
+        let transcripts = read_transcripts(&transcript).unwrap();
+        assert_eq!(transcripts.len(), 2);
+        assert!(transcripts[0].args.ends_with(&["--version".to_string()]));
+
+        let replayer = Arc::new(ReplaySpawner::load(&transcript).unwrap());
+        let mut opts = GlobalOpts::new().command("/bin/false");
+        opts.custom_spawner(replayer.clone());
+        let runc = opts.build().unwrap();
+        let version = runc.version().await.unwrap();
+        assert_eq!(version.runc_version.as_deref(), Some("1.1.4"));
+        assert!(runc.pause("fake-id").await.is_err());
+        assert_eq!(replayer.remaining(), 0);
+        assert!(matches!(
+            runc.pause("fake-id").await,
+            Err(Error::TranscriptNotFound(_))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn replay_temp_paths_test() {
+        use futures::StreamExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_runc(&dir, VERSION_SCRIPT);
+        let transcript = dir.path().join("transcript.json");
+        let opts = ExecOpts::new().pid_file(dir.path().join("exec.pid"));
+
+        let recorder = client(&runc, RecordSpawner::new(DefaultExecutor {}, &transcript));
+        recorder
+            .exec("fake-id", &Process::default(), Some(&opts))
+            .await
+            .unwrap();
+        recorder
+            .update("fake-id", &LinuxResources::default())
+            .await
+            .unwrap();
+        let events: Vec<_> = recorder
+            .events("fake-id", &Duration::from_secs(1))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(events.len(), 1);
+        let transcripts = read_transcripts(&transcript).unwrap();
+        assert_eq!(transcripts.len(), 3);
+        assert!(transcripts[0].args.contains(&PATH_PLACEHOLDER.to_string()));
+
+        // other temp files, and another pid file
+        let replayer = Arc::new(ReplaySpawner::load(&transcript).unwrap());
+        let mut opts = GlobalOpts::new().command("/bin/false");
+        opts.custom_spawner(replayer.clone());
+        let runc = opts.build().unwrap();
+        let opts = ExecOpts::new().pid_file("/run/other.pid");
+        runc.exec("fake-id", &Process::default(), Some(&opts))
+            .await
+            .unwrap();
+        runc.update("fake-id", &LinuxResources::default())
+            .await
+            .unwrap();
+        let mut events = runc
+            .events("fake-id", &Duration::from_secs(1))
+            .await
+            .unwrap();
+        let event = events.next().await.unwrap().unwrap();
+        assert!(matches!(event.event_type, EventType::Oom));
+        assert!(events.next().await.is_none());
+        assert_eq!(replayer.remaining(), 0);
+    }
+}
@@ -0,0 +1,402 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Zero-copy forwarding of container stdio with `splice(2)` and `tee(2)`.
+//!
+//! The data moves from the container pipes to the target FIFOs or files without going through
+//! userspace. When the kernel refuses to splice between the given fds, a buffered copy is used.
+
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use log::debug;
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, splice, tee, FcntlArg, OFlag, SpliceFFlags},
+    poll::{poll, PollFd, PollFlags},
+    sys::stat::{fstat, SFlag},
+    unistd::{read, write},
+};
+use tokio::io::unix::AsyncFd;
+
+/// Upper bound of bytes moved by a single splice or tee, the default pipe capacity.
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// A borrowed fd, never closed by the [AsyncFd] wrapping it.
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// One end of the copy.
+///
+/// Regular files can't be registered to epoll, but are always ready for io.
+///
+/// The file status flags are shared with the caller, so they are never changed. A pipe or FIFO
+/// may be blocking: `splice(2)` and `tee(2)` don't block on it with `SPLICE_F_NONBLOCK`, and it
+/// is only read or written once it polls ready. Other pollable fds must be non-blocking.
+enum Endpoint {
+    Pollable { fd: AsyncFd<Fd>, blocking: bool },
+    File(RawFd),
+}
+
+impl Endpoint {
+    fn new(fd: RawFd) -> io::Result<Self> {
+        let stat = fstat(fd)?;
+        let kind = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT;
+        if kind == SFlag::S_IFREG || kind == SFlag::S_IFBLK {
+            return Ok(Endpoint::File(fd));
+        }
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+        let blocking = !flags.contains(OFlag::O_NONBLOCK);
+        if blocking && kind != SFlag::S_IFIFO {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("fd {} is neither a pipe nor in non-blocking mode", fd),
+            ));
+        }
+        Ok(Endpoint::Pollable {
+            fd: AsyncFd::new(Fd(fd))?,
+            blocking,
+        })
+    }
+
+    fn fd(&self) -> RawFd {
+        match self {
+            Endpoint::Pollable { fd, .. } => fd.as_raw_fd(),
+            Endpoint::File(fd) => *fd,
+        }
+    }
+
+    /// Most bytes to write at once, a blocking pipe that polls writable has room for `PIPE_BUF`.
+    fn write_limit(&self) -> usize {
+        match self {
+            Endpoint::Pollable { blocking: true, .. } => libc::PIPE_BUF,
+            _ => MAX_CHUNK,
+        }
+    }
+
+    /// Wait until the fd is actually readable, or writable, according to `poll(2)`.
+    async fn wait(&self, flags: PollFlags) -> io::Result<()> {
+        let f = match self {
+            Endpoint::Pollable { fd, .. } => fd,
+            Endpoint::File(_) => return Ok(()),
+        };
+        loop {
+            let mut guard = if flags == PollFlags::POLLIN {
+                f.readable().await?
+            } else {
+                f.writable().await?
+            };
+            if is_ready(f.as_raw_fd(), flags)? {
+                return Ok(());
+            }
+            guard.clear_ready();
+        }
+    }
+}
+
+/// Whether `fd` is ready for `flags`, a hang up counts as ready so that the error or EOF is seen.
+fn is_ready(fd: RawFd, flags: PollFlags) -> io::Result<bool> {
+    let mut fds = [PollFd::new(fd, flags)];
+    poll(&mut fds, 0)?;
+    Ok(fds[0].revents().map_or(false, |r| !r.is_empty()))
+}
+
+/// Wait for whichever of `src` and `dst` made the last call return EAGAIN.
+///
+/// When both poll ready, the state changed since the call, which can be retried right away.
+async fn wait_ready(src: &Endpoint, dst: &[&Endpoint]) -> io::Result<()> {
+    src.wait(PollFlags::POLLIN).await?;
+    for d in dst {
+        d.wait(PollFlags::POLLOUT).await?;
+    }
+    Ok(())
+}
+
+/// Splice exactly `len` bytes from the pipe `src` to `dst`, or until EOF.
+async fn splice_exact(src: &Endpoint, dst: &Endpoint, mut len: usize) -> io::Result<usize> {
+    let flags = SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_NONBLOCK;
+    let mut moved = 0;
+    while len > 0 {
+        match splice(src.fd(), None, dst.fd(), None, len, flags) {
+            Ok(0) => break,
+            Ok(n) => {
+                moved += n;
+                len -= n;
+            }
+            Err(Errno::EAGAIN) => wait_ready(src, &[dst]).await?,
+            Err(Errno::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(moved)
+}
+
+/// Write all of `buf` to `dst`.
+async fn write_all(dst: &Endpoint, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        dst.wait(PollFlags::POLLOUT).await?;
+        let len = buf.len().min(dst.write_limit());
+        match write(dst.fd(), &buf[..len]) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => buf = &buf[n..],
+            Err(Errno::EAGAIN) | Err(Errno::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Copy from `src` to every endpoint of `dst` through a userspace buffer, until EOF.
+async fn buffered_copy(src: &Endpoint, dst: &[&Endpoint]) -> io::Result<u64> {
+    let mut buf = vec![0u8; MAX_CHUNK];
+    let mut total = 0;
+    loop {
+        src.wait(PollFlags::POLLIN).await?;
+        let n = match read(src.fd(), &mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(n) => n,
+            Err(Errno::EAGAIN) | Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for d in dst {
+            write_all(d, &buf[..n]).await?;
+        }
+        total += n as u64;
+    }
+}
+
+/// Copy everything from `src` to `dst` until EOF, returning the number of bytes copied.
+///
+/// At least one of the two must be a pipe for `splice(2)` to be used, otherwise or if the kernel
+/// rejects the pair, the data goes through a userspace buffer. Both fds stay owned by the caller
+/// and their flags are left alone, so fds other than pipes, FIFOs and files must be non-blocking.
+pub async fn splice_copy(src: &impl AsRawFd, dst: &impl AsRawFd) -> io::Result<u64> {
+    let src = Endpoint::new(src.as_raw_fd())?;
+    let dst = Endpoint::new(dst.as_raw_fd())?;
+    let flags =
+        SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_MORE | SpliceFFlags::SPLICE_F_NONBLOCK;
+    let mut total = 0;
+    loop {
+        match splice(src.fd(), None, dst.fd(), None, MAX_CHUNK, flags) {
+            Ok(0) => return Ok(total),
+            Ok(n) => total += n as u64,
+            Err(Errno::EAGAIN) => wait_ready(&src, &[&dst]).await?,
+            Err(Errno::EINTR) => {}
+            Err(Errno::EINVAL) if total == 0 => {
+                debug!("splice not supported, fall back to buffered copy");
+                return buffered_copy(&src, &[&dst]).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Copy everything from the pipe `src` to both `dst` and `copy` until EOF, returning the number
+/// of bytes copied.
+///
+/// The data is duplicated into the pipe `copy` with `tee(2)`, then moved to `dst` with
+/// `splice(2)`. When either is not a pipe, a buffered copy is used instead.
+pub async fn tee_copy(
+    src: &impl AsRawFd,
+    dst: &impl AsRawFd,
+    copy: &impl AsRawFd,
+) -> io::Result<u64> {
+    let src = Endpoint::new(src.as_raw_fd())?;
+    let dst = Endpoint::new(dst.as_raw_fd())?;
+    let copy = Endpoint::new(copy.as_raw_fd())?;
+    let mut total = 0;
+    loop {
+        match tee(
+            src.fd(),
+            copy.fd(),
+            MAX_CHUNK,
+            SpliceFFlags::SPLICE_F_NONBLOCK,
+        ) {
+            // an empty pipe with writers left gives EAGAIN, so this is EOF
+            Ok(0) => return Ok(total),
+            Ok(n) => {
+                let moved = splice_exact(&src, &dst, n).await?;
+                total += moved as u64;
+                if moved < n {
+                    return Ok(total);
+                }
+            }
+            Err(Errno::EAGAIN) => wait_ready(&src, &[&copy]).await?,
+            Err(Errno::EINTR) => {}
+            Err(Errno::EINVAL) if total == 0 => {
+                debug!("tee not supported, fall back to buffered copy");
+                return buffered_copy(&src, &[&dst, &copy]).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Copy `src` to `dst` through a userspace buffer, for comparison with [splice_copy].
+#[cfg(test)]
+pub(crate) async fn buffered_copy_fd(src: &impl AsRawFd, dst: &impl AsRawFd) -> io::Result<u64> {
+    let src = Endpoint::new(src.as_raw_fd())?;
+    let dst = Endpoint::new(dst.as_raw_fd())?;
+    buffered_copy(&src, &[&dst]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    const PATTERN: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz\n";
+
+    /// Write `size` bytes of [PATTERN] to a new pipe from a thread, returning the read side.
+    fn producer(size: usize) -> (os_pipe::PipeReader, std::thread::JoinHandle<()>) {
+        let (r, mut w) = os_pipe::pipe().unwrap();
+        let handle = std::thread::spawn(move || {
+            let chunk = PATTERN.repeat(MAX_CHUNK / PATTERN.len());
+            let mut left = size;
+            while left > 0 {
+                let n = left.min(chunk.len());
+                w.write_all(&chunk[..n]).unwrap();
+                left -= n;
+            }
+        });
+        (r, handle)
+    }
+
+    fn check_file(path: &std::path::Path, size: usize) {
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(data.len(), size);
+        assert!(data.starts_with(PATTERN));
+    }
+
+    #[tokio::test]
+    async fn splice_to_file_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out");
+        let file = std::fs::File::create(&path).unwrap();
+        let size = 1024 * 1024 + 7;
+        let (r, h) = producer(size);
+        assert_eq!(splice_copy(&r, &file).await.unwrap(), size as u64);
+        h.join().unwrap();
+        check_file(&path, size);
+    }
+
+    #[tokio::test]
+    async fn splice_to_pipe_test() {
+        let size = 512 * 1024;
+        let (r, h) = producer(size);
+        let (mut out_r, out_w) = os_pipe::pipe().unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            out_r.read_to_end(&mut buf).unwrap();
+            buf
+        });
+        assert_eq!(splice_copy(&r, &out_w).await.unwrap(), size as u64);
+        // the flags are shared with the caller, and left alone
+        for fd in [r.as_raw_fd(), out_w.as_raw_fd()] {
+            let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL).unwrap());
+            assert!(!flags.contains(OFlag::O_NONBLOCK));
+        }
+        drop(out_w);
+        h.join().unwrap();
+        assert_eq!(reader.join().unwrap().len(), size);
+
+        // a blocking socket could stall the runtime
+        let (a, b) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(splice_copy(&a, &b).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fallback_test() {
+        // neither side is a pipe, so splice is rejected with EINVAL
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        std::fs::write(&src, PATTERN.repeat(100)).unwrap();
+        let src_file = std::fs::File::open(&src).unwrap();
+        let dst_file = std::fs::File::create(&dst).unwrap();
+        let size = PATTERN.len() * 100;
+        assert_eq!(
+            splice_copy(&src_file, &dst_file).await.unwrap(),
+            size as u64
+        );
+        check_file(&dst, size);
+    }
+
+    #[tokio::test]
+    async fn tee_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out");
+        let file = std::fs::File::create(&path).unwrap();
+        let size = 256 * 1024 + 3;
+        let (r, h) = producer(size);
+        let (mut copy_r, copy_w) = os_pipe::pipe().unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            copy_r.read_to_end(&mut buf).unwrap();
+            buf
+        });
+        assert_eq!(tee_copy(&r, &file, &copy_w).await.unwrap(), size as u64);
+        drop(copy_w);
+        h.join().unwrap();
+        check_file(&path, size);
+        assert_eq!(reader.join().unwrap().len(), size);
+    }
+
+    /// Compare the throughput of splice and buffered copy from a pipe to a file.
+    ///
+    /// Run with `--ignored --nocapture` to see the numbers.
+    #[tokio::test]
+    #[ignore]
+    async fn throughput_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let size = 32 * 1024 * 1024;
+
+        let run = |name: &'static str| {
+            let path = dir.path().join(name);
+            async move {
+                let file = std::fs::File::create(&path).unwrap();
+                let (r, h) = producer(size);
+                let start = Instant::now();
+                let n = if name == "splice" {
+                    splice_copy(&r, &file).await.unwrap()
+                } else {
+                    buffered_copy_fd(&r, &file).await.unwrap()
+                };
+                let elapsed = start.elapsed().max(Duration::from_micros(1));
+                h.join().unwrap();
+                assert_eq!(n, size as u64);
+                check_file(&path, size);
+                println!(
+                    "{}: {:.1} MiB/s",
+                    name,
+                    size as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+                );
+            }
+        };
+        run("buffered").await;
+        run("splice").await;
+    }
+}
@@ -18,6 +18,7 @@
 use std::io::Write;
 use std::{
     env,
+    os::unix::io::RawFd,
     path::{Path, PathBuf},
 };
 
@@ -130,3 +131,47 @@ where
         })
     })
 }
+
+/// Build a `pre_exec` hook moving `fds` to 3, 4, ... in the child.
+pub(crate) fn preserve_fds_hook(
+    fds: &[RawFd],
+) -> impl FnMut() -> std::io::Result<()> + Send + Sync + 'static {
+    let fds = fds.to_vec();
+    // Allocated before fork, the closure below must not allocate.
+    let mut tmp: Vec<RawFd> = vec![-1; fds.len()];
+    let base = 3 + fds.len() as RawFd;
+    move || -> std::io::Result<()> {
+        // Move every fd out of the target range first, so that sources and targets may overlap.
+        for (i, fd) in fds.iter().enumerate() {
+            tmp[i] = nix::fcntl::fcntl(*fd, nix::fcntl::FcntlArg::F_DUPFD(base))?;
+        }
+        for (i, fd) in tmp.iter().enumerate() {
+            nix::unistd::dup2(*fd, 3 + i as RawFd)?;
+            nix::unistd::close(*fd)?;
+        }
+        Ok(())
+    }
+}
+
+/// Put the command into a new process group led by itself, so that it can be killed as a whole.
+pub fn set_pgid(cmd: &mut crate::Command) {
+    pre_exec(cmd, || {
+        nix::unistd::setpgid(nix::unistd::Pid::from_raw(0), nix::unistd::Pid::from_raw(0))?;
+        Ok(())
+    });
+}
+
+pub(crate) fn pre_exec<F>(cmd: &mut crate::Command, f: F)
+where
+    F: FnMut() -> std::io::Result<()> + Send + Sync + 'static,
+{
+    #[cfg(not(feature = "async"))]
+    unsafe {
+        use std::os::unix::process::CommandExt;
+        cmd.pre_exec(f);
+    }
+    #[cfg(feature = "async")]
+    unsafe {
+        cmd.pre_exec(f);
+    }
+}
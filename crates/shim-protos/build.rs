@@ -32,6 +32,7 @@ fn main() {
             "vendor/github.com/containerd/containerd/protobuf/plugin/fieldpath.proto",
             "vendor/github.com/containerd/containerd/api/types/mount.proto",
             "vendor/github.com/containerd/containerd/api/types/task/task.proto",
+            "vendor/github.com/containerd/containerd/api/types/introspection.proto",
         ],
         false,
     );
@@ -42,6 +43,12 @@ fn main() {
         false,
     );
 
+    genmodule(
+        "cgroups_v2",
+        &["vendor/github.com/containerd/cgroups/v2/stats/metrics.proto"],
+        false,
+    );
+
     genmodule(
         "events",
         &[
@@ -21,3 +21,14 @@ pub mod metrics {
 mod gogo {
     pub use crate::types::gogo::*;
 }
+
+/// Metrics of cgroup v2 hierarchies.
+pub mod v2 {
+    pub mod metrics {
+        include!(concat!(env!("OUT_DIR"), "/cgroups_v2/metrics.rs"));
+    }
+
+    mod gogo {
+        pub use crate::types::gogo::*;
+    }
+}
@@ -33,3 +33,7 @@ pub mod task {
 pub mod fieldpath {
     include!(concat!(env!("OUT_DIR"), "/types/fieldpath.rs"));
 }
+
+pub mod introspection {
+    include!(concat!(env!("OUT_DIR"), "/types/introspection.rs"));
+}
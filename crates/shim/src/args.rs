@@ -36,6 +36,10 @@ pub struct Flags {
     pub address: String,
     /// Path to publish binary (used for publishing events).
     pub publish_binary: String,
+    /// Print the version of the shim and exit.
+    pub version: bool,
+    /// Print the runtime info of the shim as protobuf and exit.
+    pub info: bool,
     /// Shim action (start / delete).
     /// See https://github.com/containerd/containerd/blob/master/runtime/v2/shim/shim.go#L191
     pub action: String,
@@ -54,6 +58,8 @@ pub fn parse<S: AsRef<OsStr>>(args: &[S]) -> Result<Flags> {
         f.add_flag("bundle", &mut flags.bundle);
         f.add_flag("address", &mut flags.address);
         f.add_flag("publish-binary", &mut flags.publish_binary);
+        f.add_flag("v", &mut flags.version);
+        f.add_flag("info", &mut flags.info);
     })
     .map_err(|e| Error::InvalidArgument(e.to_string()))?;
 
@@ -61,7 +67,8 @@ pub fn parse<S: AsRef<OsStr>>(args: &[S]) -> Result<Flags> {
         flags.action = action.into();
     }
 
-    if flags.namespace.is_empty() {
+    // Informational flags are passed without a namespace
+    if flags.namespace.is_empty() && !flags.version && !flags.info {
         return Err(Error::InvalidArgument(String::from(
             "Shim namespace cannot be empty",
         )));
@@ -126,6 +133,18 @@ mod tests {
         assert_eq!(flags.id, "");
     }
 
+    #[test]
+    fn parse_info() {
+        let flags = parse(&["-info"]).unwrap();
+        assert!(flags.info);
+        assert!(!flags.version);
+        assert_eq!(flags.namespace, "");
+
+        let flags = parse(&["-v"]).unwrap();
+        assert!(flags.version);
+        assert!(!flags.info);
+    }
+
     #[test]
     fn no_namespace() {
         let empty: [String; 0] = [];
@@ -17,8 +17,7 @@
 use std::{
     convert::TryFrom,
     env,
-    os::unix::{fs::FileTypeExt, io::AsRawFd, net::UnixListener},
-    path::Path,
+    os::unix::{fs::FileTypeExt, io::AsRawFd},
     process,
     process::{Command, Stdio},
     sync::{
@@ -31,9 +30,10 @@ use async_trait::async_trait;
 use command_fds::{CommandFdExt, FdMapping};
 use containerd_shim_protos::{
     api::DeleteResponse,
-    protobuf::Message,
-    shim_async::{create_task, Client, Task},
+    protobuf::{well_known_types::any::Any, Message},
+    shim_async::{create_task, Task},
     ttrpc::r#async::Server,
+    types::introspection::RuntimeInfo,
 };
 use futures::StreamExt;
 use libc::{SIGCHLD, SIGINT, SIGPIPE, SIGTERM};
@@ -51,10 +51,14 @@ use tokio::{io::AsyncWriteExt, sync::Notify};
 
 use crate::{
     args,
-    asynchronous::{monitor::monitor_notify_by_pid, publisher::RemotePublisher},
+    asynchronous::{monitor::MONITOR, publisher::RemotePublisher},
+    bootstrap::{start_response, BootstrapParams},
     error::{Error, Result},
-    logger, parse_sockaddr, reap, socket_address,
-    util::{asyncify, read_file_to_str, write_str_to_file},
+    event::CLOSE_TIMEOUT,
+    info, logger, reap,
+    socket::{Listener, SocketAddress},
+    socket_address,
+    util::{asyncify, connect, read_file_to_str, read_stdin, write_str_to_file},
     Config, StartOpts, SOCKET_FD, TTRPC_ADDRESS,
 };
 
@@ -79,10 +83,19 @@ pub trait Shim {
     /// - `config`: for the shim to pass back configuration information
     async fn new(runtime_id: &str, id: &str, namespace: &str, config: &mut Config) -> Self;
 
+    /// Runtime info of the shim, printed as protobuf by `-info` and as text by `-v`.
+    ///
+    /// `options` are the runtime options containerd passes to `-info`, if any. The default
+    /// info has the runtime id, the version of this crate and the options.
+    async fn info(runtime_id: &str, options: Option<Any>) -> Result<RuntimeInfo> {
+        Ok(info::default_info(runtime_id, options))
+    }
+
     /// Start shim will be called by containerd when launching new shim instance.
     ///
     /// It expected to return TTRPC address containerd daemon can use to communicate with
-    /// the given shim instance.
+    /// the given shim instance. The address is written as is for older containerd, and in the
+    /// negotiated [BootstrapParams] for containerd 2.0.
     /// See https://github.com/containerd/containerd/tree/master/runtime/v2#start
     /// this is an asynchronous call
     async fn start_shim(&mut self, opts: StartOpts) -> Result<String>;
@@ -117,6 +130,30 @@ where
     let os_args: Vec<_> = env::args_os().collect();
     let flags = args::parse(&os_args[1..])?;
 
+    if flags.version {
+        let info = T::info(runtime_id, None).await?;
+        let argv0 = os_args[0].to_string_lossy();
+        let mut stdout = tokio::io::stdout();
+        stdout
+            .write_all(info::version_output(&argv0, &info).as_bytes())
+            .await
+            .map_err(io_error!(e, "write stdout"))?;
+        stdout.flush().await.map_err(io_error!(e, "flush stdout"))?;
+        return Ok(());
+    }
+
+    if flags.info {
+        let options = info::read_options(&read_stdin()?)?;
+        let info = T::info(runtime_id, options).await?;
+        let mut stdout = tokio::io::stdout();
+        stdout
+            .write_all(&info.write_to_bytes()?)
+            .await
+            .map_err(io_error!(e, "write stdout"))?;
+        stdout.flush().await.map_err(io_error!(e, "flush stdout"))?;
+        return Ok(());
+    }
+
     let ttrpc_address = env::var(TTRPC_ADDRESS)?;
     // Create shim instance
     let mut config = opts.unwrap_or_default();
@@ -139,12 +176,15 @@ where
                 ttrpc_address,
                 namespace: flags.namespace,
                 debug: flags.debug,
+                bootstrap: BootstrapParams::parse(&read_stdin()?),
             };
 
+            let params = args.bootstrap.clone();
             let address = shim.start_shim(args).await?;
+            let response = start_response(params.as_ref(), &address)?;
             let mut stdout = tokio::io::stdout();
             stdout
-                .write_all(address.as_bytes())
+                .write_all(response.as_bytes())
                 .await
                 .map_err(io_error!(e, "write stdout"))?;
             // containerd occasionally read an empty string without flushing the stdout
@@ -170,11 +210,12 @@ where
             }
 
             let publisher = RemotePublisher::new(&ttrpc_address).await?;
+            let closer = publisher.closer();
             let task = shim.create_task_service(publisher).await;
             let task_service = create_task(Arc::new(Box::new(task)));
             let mut server = Server::new().register_service(task_service);
             server = server.add_listener(SOCKET_FD)?;
-            server = server.set_domain_unix();
+            server = set_domain(server);
             server.start().await?;
 
             info!("Shim successfully started, waiting for exit signal...");
@@ -185,6 +226,7 @@ where
 
             info!("Shutting down shim instance");
             server.shutdown().await.unwrap_or_default();
+            closer.close(CLOSE_TIMEOUT).await;
 
             // NOTE: If the shim server is down(like oom killer), the address
             // socket might be leaking.
@@ -311,6 +353,8 @@ async fn handle_signals(signals: Signals) {
                 debug!("received {}", sig);
             }
             SIGCHLD => loop {
+                // Hold the monitor from reaping until the exit is sent, see monitor_kill_unreaped.
+                let monitor = MONITOR.lock().await;
                 // Note: see comment at the counterpart in synchronous/mod.rs for details.
                 match asyncify(move || {
                     Ok(wait::waitpid(
@@ -320,15 +364,15 @@ async fn handle_signals(signals: Signals) {
                 })
                 .await
                 {
-                    Ok(WaitStatus::Exited(pid, status)) => {
-                        monitor_notify_by_pid(pid.as_raw(), status)
-                            .await
-                            .unwrap_or_else(|e| error!("failed to send exit event {}", e))
-                    }
+                    Ok(WaitStatus::Exited(pid, status)) => monitor
+                        .notify_by_pid(pid.as_raw(), status)
+                        .await
+                        .unwrap_or_else(|e| error!("failed to send exit event {}", e)),
                     Ok(WaitStatus::Signaled(pid, sig, _)) => {
                         debug!("child {} terminated({})", pid, sig);
                         let exit_code = 128 + sig as i32;
-                        monitor_notify_by_pid(pid.as_raw(), exit_code)
+                        monitor
+                            .notify_by_pid(pid.as_raw(), exit_code)
                             .await
                             .unwrap_or_else(|e| error!("failed to send signal event {}", e))
                     }
@@ -359,8 +403,13 @@ async fn remove_socket_silently(address: &str) {
 }
 
 async fn remove_socket(address: &str) -> Result<()> {
-    let path = parse_sockaddr(address);
-    if let Ok(md) = Path::new(path).metadata() {
+    let addr = SocketAddress::parse(address)?;
+    let path = match addr.path() {
+        Some(path) => path,
+        // abstract sockets and vsock have no file to remove
+        None => return Ok(()),
+    };
+    if let Ok(md) = path.metadata() {
         if md.file_type().is_socket() {
             tokio::fs::remove_file(path).await.map_err(io_error!(
                 e,
@@ -372,9 +421,9 @@ async fn remove_socket(address: &str) -> Result<()> {
     Ok(())
 }
 
-async fn start_listener(address: &str) -> Result<UnixListener> {
+async fn start_listener(address: &str) -> Result<Listener> {
     let addr = address.to_string();
-    asyncify(move || -> Result<UnixListener> {
+    asyncify(move || -> Result<Listener> {
         crate::start_listener(&addr).map_err(|e| Error::IoError {
             context: format!("failed to start listener {}", addr),
             err: e,
@@ -383,10 +432,20 @@ async fn start_listener(address: &str) -> Result<UnixListener> {
     .await
 }
 
+/// Set the domain of the server by the socket passed in by `spawn`.
+fn set_domain(server: Server) -> Server {
+    #[cfg(target_os = "linux")]
+    if crate::socket::is_vsock(SOCKET_FD) {
+        return server.set_domain_vsock();
+    }
+    server.set_domain_unix()
+}
+
 async fn wait_socket_working(address: &str, interval_in_ms: u64, count: u32) -> Result<()> {
     for _i in 0..count {
-        match Client::connect(address) {
-            Ok(_) => {
+        match connect(address) {
+            Ok(fd) => {
+                let _ = nix::unistd::close(fd);
                 return Ok(());
             }
             Err(_) => {
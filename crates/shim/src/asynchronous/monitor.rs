@@ -18,6 +18,10 @@ use std::collections::HashMap;
 
 use lazy_static::lazy_static;
 use log::error;
+use nix::{
+    sys::signal::{kill, killpg, Signal},
+    unistd::Pid,
+};
 use tokio::sync::{
     mpsc::{channel, Receiver, Sender},
     Mutex,
@@ -55,6 +59,24 @@ pub async fn monitor_notify_by_pid(pid: i32, exit_code: i32) -> Result<()> {
     monitor.notify_by_pid(pid, exit_code).await
 }
 
+/// Kill `pid`, and the process group it leads if any, unless its exit has been sent to
+/// `subscription`, which must be subscribed to [Topic::Pid] since before `pid` was spawned.
+///
+/// The reaper holds the monitor from reaping a child until its exit is sent, so a pid without
+/// a sent exit is not reaped yet, and the signal can't reach a reused pid.
+pub async fn monitor_kill_unreaped(pid: i32, subscription: &mut Subscription) {
+    let _monitor = MONITOR.lock().await;
+    while let Ok(e) = subscription.rx.try_recv() {
+        if matches!(e.subject, Subject::Pid(p) if p == pid) {
+            return;
+        }
+    }
+    let pid = Pid::from_raw(pid);
+    // Only succeeds if the child was started in its own process group.
+    let _ = killpg(pid, Signal::SIGKILL);
+    let _ = kill(pid, Signal::SIGKILL);
+}
+
 pub async fn monitor_notify_by_exec(id: &str, exec_id: &str, exit_code: i32) -> Result<()> {
     let monitor = MONITOR.lock().await;
     monitor.notify_by_exec(id, exec_id, exit_code).await
@@ -146,9 +168,12 @@ impl Monitor {
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
     use crate::{
         asynchronous::monitor::{
-            monitor_notify_by_exec, monitor_notify_by_pid, monitor_subscribe, monitor_unsubscribe,
+            monitor_kill_unreaped, monitor_notify_by_exec, monitor_notify_by_pid,
+            monitor_subscribe, monitor_unsubscribe,
         },
         monitor::{ExitEvent, Subject, Topic},
     };
@@ -213,4 +238,24 @@ mod tests {
         monitor_unsubscribe(s1.id).await.unwrap();
         monitor_unsubscribe(s2.id).await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_kill_unreaped() {
+        let mut s = monitor_subscribe(Topic::Pid).await.unwrap();
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let pid = child.id() as i32;
+
+        // an exit was sent, so the pid may be reused and must not be killed
+        monitor_notify_by_pid(pid, 0).await.unwrap();
+        monitor_kill_unreaped(pid, &mut s).await;
+        assert!(child.try_wait().unwrap().is_none());
+
+        monitor_kill_unreaped(pid, &mut s).await;
+        let status = child.wait().unwrap();
+        assert_eq!(status.signal(), Some(9));
+        monitor_unsubscribe(s.id).await.unwrap();
+    }
 }
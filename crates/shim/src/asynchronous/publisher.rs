@@ -14,7 +14,11 @@
    limitations under the License.
 */
 
-use std::os::unix::io::RawFd;
+use std::{
+    os::unix::io::RawFd,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use containerd_shim_protos::{
@@ -25,15 +29,110 @@ use containerd_shim_protos::{
     ttrpc,
     ttrpc::{context::Context, r#async::TtrpcContext},
 };
+use log::warn;
+use tokio::sync::Notify;
 
 use crate::{
     error::Result,
+    event::{
+        log_undelivered, next_backoff, EventQueue, EVENT_QUEUE_CAPACITY, RECONNECT_BACKOFF_MIN,
+    },
     util::{asyncify, connect, convert_to_any, timestamp},
 };
 
 /// Async Remote publisher connects to containerd's TTRPC endpoint to publish events from shim.
+///
+/// Events are queued and forwarded in order by a background task, which reconnects with
+/// backoff when the connection drops and retries the event that failed, so events are
+/// delivered at least once. See [EventQueue] for what is dropped when too many events are
+/// pending.
 pub struct RemotePublisher {
-    client: EventsClient,
+    inner: Arc<Inner>,
+}
+
+struct State {
+    queue: EventQueue,
+    closed: bool,
+    done: bool,
+}
+
+struct Inner {
+    address: String,
+    state: Mutex<State>,
+    /// Notified when an event is queued or the publisher is closed.
+    notify: Notify,
+    /// Notified when the publisher is closed, to cut the backoff short.
+    closing: Notify,
+    /// Notified when the background task stops.
+    done: Notify,
+}
+
+impl Inner {
+    /// Wait for the next event to deliver, `None` when the publisher is closed and all the
+    /// events are delivered.
+    async fn next(&self) -> Option<(Context, events::ForwardRequest)> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(event) = state.queue.pop() {
+                    return Some(event);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    fn is_done(&self) -> bool {
+        self.state.lock().unwrap().done
+    }
+
+    fn delivered(&self) {
+        self.state.lock().unwrap().queue.delivered();
+    }
+
+    fn set_closed(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+        self.closing.notify_one();
+    }
+
+    /// Close the publisher and wait up to `timeout` for the pending events to be delivered,
+    /// dropping the rest.
+    async fn close(&self, timeout: Duration) {
+        self.set_closed();
+        let done = async {
+            loop {
+                let notified = self.done.notified();
+                if self.is_done() {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        if tokio::time::timeout(timeout, done).await.is_err() {
+            let reason = format!("not delivered in {:?} before the shim exits", timeout);
+            self.state.lock().unwrap().queue.drop_pending(&reason);
+        }
+    }
+}
+
+/// Closes a [RemotePublisher] that was handed over to the task service.
+pub(crate) struct Closer {
+    inner: Arc<Inner>,
+}
+
+impl Closer {
+    /// Wait up to `timeout` for the pending events to be delivered, dropping the rest.
+    pub(crate) async fn close(&self, timeout: Duration) {
+        self.inner.close(timeout).await;
+    }
 }
 
 impl RemotePublisher {
@@ -41,11 +140,28 @@ impl RemotePublisher {
     ///
     /// containerd uses `/run/containerd/containerd.sock.ttrpc` by default
     pub async fn new(address: impl AsRef<str>) -> Result<RemotePublisher> {
-        let client = Self::connect(address).await?;
+        let client = Self::connect(address.as_ref()).await?;
+        let inner = Arc::new(Inner {
+            address: address.as_ref().to_string(),
+            state: Mutex::new(State {
+                queue: EventQueue::new(EVENT_QUEUE_CAPACITY),
+                closed: false,
+                done: false,
+            }),
+            notify: Notify::new(),
+            closing: Notify::new(),
+            done: Notify::new(),
+        });
 
-        Ok(RemotePublisher {
-            client: EventsClient::new(client),
-        })
+        tokio::spawn(Self::run(inner.clone(), EventsClient::new(client)));
+
+        Ok(RemotePublisher { inner })
+    }
+
+    pub(crate) fn closer(&self) -> Closer {
+        Closer {
+            inner: self.inner.clone(),
+        }
     }
 
     async fn connect(address: impl AsRef<str>) -> Result<Client> {
@@ -60,9 +176,72 @@ impl RemotePublisher {
         Ok(Client::new(fd))
     }
 
+    /// Forward the queued events until the publisher is closed, reconnecting when a delivery
+    /// fails. Once closed, each remaining event is tried once.
+    async fn run(inner: Arc<Inner>, client: EventsClient) {
+        let mut client = Some(client);
+        let mut backoff = RECONNECT_BACKOFF_MIN;
+        let mut retry = None;
+        loop {
+            let (ctx, req) = match retry.take() {
+                Some(event) => event,
+                None => match inner.next().await {
+                    Some(event) => event,
+                    None => break,
+                },
+            };
+            let c = match client.take() {
+                Some(c) => Ok(c),
+                None => Self::connect(&inner.address).await.map(EventsClient::new),
+            };
+            let result = match c {
+                Ok(c) => c
+                    .forward(ctx.clone(), &req)
+                    .await
+                    .map(|_| c)
+                    .map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(c) => {
+                    inner.delivered();
+                    client = Some(c);
+                    backoff = RECONNECT_BACKOFF_MIN;
+                }
+                Err(e) if inner.closed() => {
+                    inner.delivered();
+                    log_undelivered(&req, &format!("publisher is closed: {}", e));
+                }
+                Err(e) => {
+                    warn!(
+                        "publish {} to containerd: {}, retry in {:?}",
+                        req.envelope().topic(),
+                        e,
+                        backoff
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => {}
+                        _ = inner.closing.notified() => {}
+                    }
+                    backoff = next_backoff(backoff);
+                    retry = Some((ctx, req));
+                }
+            }
+        }
+        inner.state.lock().unwrap().done = true;
+        inner.done.notify_waiters();
+    }
+
+    fn enqueue(&self, ctx: Context, req: events::ForwardRequest) {
+        self.inner.state.lock().unwrap().queue.push(ctx, req);
+        self.inner.notify.notify_one();
+    }
+
     /// Publish a new event.
     ///
     /// Event object can be anything that Protobuf able serialize (e.g. implement `Message` trait).
+    /// The event is queued and this returns before it is delivered. `ctx` applies to each
+    /// delivery attempt, with a default timeout when it has none.
     pub async fn publish(
         &self,
         ctx: Context,
@@ -79,12 +258,19 @@ impl RemotePublisher {
         let mut req = events::ForwardRequest::new();
         req.set_envelope(envelope);
 
-        self.client.forward(ctx, &req).await?;
+        self.enqueue(ctx, req);
 
         Ok(())
     }
 }
 
+impl Drop for RemotePublisher {
+    /// The background task delivers the pending events, trying each once, then stops.
+    fn drop(&mut self) {
+        self.inner.set_closed();
+    }
+}
+
 #[async_trait]
 impl Events for RemotePublisher {
     async fn forward(
@@ -92,7 +278,8 @@ impl Events for RemotePublisher {
         _ctx: &TtrpcContext,
         req: events::ForwardRequest,
     ) -> ttrpc::Result<Empty> {
-        self.client.forward(Context::default(), &req).await
+        self.enqueue(Context::default(), req);
+        Ok(Empty::new())
     }
 }
 
@@ -105,7 +292,7 @@ mod tests {
 
     use containerd_shim_protos::{
         api::{Empty, ForwardRequest},
-        events::task::TaskOOM,
+        events::task::{TaskExit, TaskOOM},
         shim_async::create_events,
         ttrpc::asynchronous::Server,
     };
@@ -180,4 +367,133 @@ mod tests {
         barrier.wait().await;
         server_thread.await.unwrap();
     }
+
+    struct RecordingServer {
+        tx: Sender<String>,
+    }
+
+    #[async_trait]
+    impl Events for RecordingServer {
+        async fn forward(&self, _ctx: &TtrpcContext, req: ForwardRequest) -> ttrpc::Result<Empty> {
+            let env = req.envelope();
+            let event = format!("{} {}", env.namespace(), env.topic());
+            self.tx.send(event).await.unwrap();
+            Ok(Empty::default())
+        }
+    }
+
+    async fn start_server(path: &str, tx: Sender<String>) -> Server {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        let t = Arc::new(Box::new(RecordingServer { tx }) as Box<dyn Events + Send + Sync>);
+        let service = create_events(t);
+        let mut server = Server::new()
+            .set_domain_unix()
+            .add_listener(listener.as_raw_fd())
+            .unwrap()
+            .register_service(service);
+        std::mem::forget(listener);
+        server.start().await.unwrap();
+        server
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = format!("{}/socket", tmpdir.as_ref().to_str().unwrap());
+        let (tx, mut rx) = channel(8);
+
+        let mut server = start_server(&path, tx.clone()).await;
+        let publisher = RemotePublisher::new(&path).await.unwrap();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/oom",
+                "ns1",
+                Box::new(TaskOOM::new()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "ns1 /tasks/oom");
+
+        // events published while containerd is down are delivered in order once it is back
+        server.shutdown().await.unwrap();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/oom",
+                "ns2",
+                Box::new(TaskOOM::new()),
+            )
+            .await
+            .unwrap();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/exit",
+                "ns3",
+                Box::new(TaskExit::new()),
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+        let mut server = start_server(&path, tx).await;
+        let recv = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            (rx.recv().await.unwrap(), rx.recv().await.unwrap())
+        });
+        assert_eq!(
+            recv.await.unwrap(),
+            ("ns2 /tasks/oom".to_string(), "ns3 /tasks/exit".to_string())
+        );
+
+        drop(publisher);
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = format!("{}/socket", tmpdir.as_ref().to_str().unwrap());
+        let (tx, mut rx) = channel(8);
+
+        // the pending events are delivered before close returns
+        let mut server = start_server(&path, tx).await;
+        let publisher = RemotePublisher::new(&path).await.unwrap();
+        let closer = publisher.closer();
+        for ns in ["ns1", "ns2"] {
+            publisher
+                .publish(
+                    Context::default(),
+                    "/tasks/exit",
+                    ns,
+                    Box::new(TaskExit::new()),
+                )
+                .await
+                .unwrap();
+        }
+        closer.close(crate::event::CLOSE_TIMEOUT).await;
+        assert_eq!(rx.try_recv().unwrap(), "ns1 /tasks/exit");
+        assert_eq!(rx.try_recv().unwrap(), "ns2 /tasks/exit");
+        drop(publisher);
+
+        // and dropped when containerd is gone
+        let publisher = RemotePublisher::new(&path).await.unwrap();
+        let closer = publisher.closer();
+        server.shutdown().await.unwrap();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/exit",
+                "ns3",
+                Box::new(TaskExit::new()),
+            )
+            .await
+            .unwrap();
+        let start = std::time::Instant::now();
+        closer.close(Duration::from_millis(200)).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(closer.inner.state.lock().unwrap().queue.len(), 0);
+        assert!(rx.try_recv().is_err());
+    }
 }
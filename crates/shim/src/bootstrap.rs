@@ -0,0 +1,134 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Bootstrap protocol of the `start` action.
+//!
+//! containerd 2.0 writes its [BootstrapParams] as JSON to the stdin of `start`, and reads the
+//! params of the shim, with the negotiated shim API version, as JSON from its stdout. Older
+//! containerd writes the runtime options to stdin and reads a plain address.
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// Version of the shim API where `start` writes a plain address.
+pub const SHIM_VERSION_LEGACY: u32 = 2;
+
+/// Latest version of the shim API implemented by this crate.
+pub const SHIM_VERSION: u32 = 3;
+
+/// Protocol containerd uses to connect to the shim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Ttrpc,
+    Grpc,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol::Ttrpc
+    }
+}
+
+/// Bootstrap params exchanged between containerd and the shim on `start`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootstrapParams {
+    /// Version of the shim API.
+    pub version: u32,
+    /// Address containerd connects to.
+    #[serde(default)]
+    pub address: String,
+    /// Protocol served at the address.
+    #[serde(default)]
+    pub protocol: Protocol,
+}
+
+impl BootstrapParams {
+    /// Parse the stdin of `start`, `None` when it is not from containerd 2.0.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice::<Self>(data)
+            .ok()
+            .filter(|p| p.version >= SHIM_VERSION_LEGACY)
+    }
+
+    /// The params of the shim serving TTRPC at `address`, with the latest version supported by
+    /// both containerd and the shim.
+    pub fn negotiate(&self, address: &str) -> Self {
+        if self.protocol != Protocol::Ttrpc {
+            debug!(
+                "containerd asks for {:?}, the shim only serves ttrpc",
+                self.protocol
+            );
+        }
+        Self {
+            version: self.version.min(SHIM_VERSION),
+            address: address.to_string(),
+            protocol: Protocol::Ttrpc,
+        }
+    }
+}
+
+/// The output of `start` for the shim at `address`: the negotiated params as JSON when
+/// containerd sent `params`, and the plain address for older containerd.
+pub fn start_response(params: Option<&BootstrapParams>, address: &str) -> Result<String> {
+    match params {
+        Some(params) => Ok(serde_json::to_string(&params.negotiate(address))?),
+        None => Ok(address.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "unix:///run/containerd/s/1234";
+
+    #[test]
+    fn test_legacy_daemon() {
+        // containerd 1.x writes the runtime options as protobuf, or nothing
+        for stdin in &[&b""[..], &b"\x0a\x04test"[..], &b"{\"version\":1}"[..]] {
+            let params = BootstrapParams::parse(stdin);
+            assert_eq!(params, None);
+            assert_eq!(start_response(params.as_ref(), ADDRESS).unwrap(), ADDRESS);
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_daemon() {
+        let params =
+            BootstrapParams::parse(br#"{"version":3,"address":"","protocol":"ttrpc"}"#).unwrap();
+        let response = start_response(Some(&params), ADDRESS).unwrap();
+        let response: BootstrapParams = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response,
+            BootstrapParams {
+                version: 3,
+                address: ADDRESS.to_string(),
+                protocol: Protocol::Ttrpc,
+            }
+        );
+
+        // a newer containerd gets the latest version of the shim, an older one its own
+        let params = BootstrapParams::parse(br#"{"version":9,"protocol":"grpc"}"#).unwrap();
+        let response = params.negotiate(ADDRESS);
+        assert_eq!(response.version, SHIM_VERSION);
+        assert_eq!(response.protocol, Protocol::Ttrpc);
+        let params = BootstrapParams::parse(br#"{"version":2}"#).unwrap();
+        assert_eq!(params.negotiate(ADDRESS).version, SHIM_VERSION_LEGACY);
+    }
+}
@@ -16,19 +16,32 @@
 
 #![cfg(target_os = "linux")]
 
-use std::{fs, io::Read, path::Path};
-
-use cgroups_rs::{
-    cgroup::get_cgroups_relative_paths_by_pid, hierarchies, Cgroup, CgroupPid, MaxValue, Subsystem,
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
 };
+
+use cgroups_rs::{hierarchies, Cgroup, CgroupPid};
 use containerd_shim_protos::{
-    cgroups::metrics::{CPUStat, CPUUsage, MemoryEntry, MemoryStat, Metrics},
+    cgroups::{
+        metrics::{
+            BlkIOEntry, BlkIOStat, CPUStat, CPUUsage, HugetlbStat, MemoryEntry, MemoryOomControl,
+            MemoryStat, Metrics, PidsStat, RdmaEntry, RdmaStat, Throttle,
+        },
+        v2::metrics as v2,
+    },
     protobuf::{well_known_types::any::Any, Message},
     shim::oci::Options,
 };
+use log::{debug, warn};
 use oci_spec::runtime::LinuxResources;
 
-use crate::error::{Error, Result};
+use crate::{
+    bootstrap::BootstrapParams,
+    error::{Error, Result},
+    util::{convert_to_any, read_stdin},
+};
 
 // OOM_SCORE_ADJ_MAX is from https://github.com/torvalds/linux/blob/master/include/uapi/linux/oom.h#L10
 const OOM_SCORE_ADJ_MAX: i64 = 1000;
@@ -38,13 +51,10 @@ pub fn set_cgroup_and_oom_score(pid: u32) -> Result<()> {
         return Ok(());
     }
 
-    // set cgroup
-    let mut data: Vec<u8> = Vec::new();
-    std::io::stdin()
-        .read_to_end(&mut data)
-        .map_err(io_error!(e, "read stdin"))?;
+    // set cgroup, from the runtime options unless containerd sent bootstrap params
+    let data = read_stdin()?;
 
-    if !data.is_empty() {
+    if !data.is_empty() && BootstrapParams::parse(&data).is_none() {
         let opts =
             Any::parse_from_bytes(&data).and_then(|any| Options::parse_from_bytes(&any.value))?;
 
@@ -93,138 +103,1339 @@ fn write_process_oom_score(pid: u32, score: i64) -> Result<()> {
         .map_err(io_error!(e, "write oom score"))
 }
 
-/// Collect process cgroup stats, return only necessary parts of it
+/// Mountpoint of the unified cgroup v2 hierarchy.
+const CGROUP_V2_MOUNTPOINT: &str = "/sys/fs/cgroup";
+
+/// Cgroup metrics of a process, in the message of its cgroup version.
+#[derive(Debug, Clone)]
+pub enum CgroupMetrics {
+    /// `io.containerd.cgroups.v1.Metrics`
+    V1(Metrics),
+    /// `io.containerd.cgroups.v2.Metrics`
+    V2(v2::Metrics),
+}
+
+impl CgroupMetrics {
+    /// Wrap the metrics in an [Any], as returned by the stats of a task.
+    pub fn to_any(&self) -> Result<Any> {
+        match self {
+            CgroupMetrics::V1(m) => convert_to_any(Box::new(m.clone())),
+            CgroupMetrics::V2(m) => convert_to_any(Box::new(m.clone())),
+        }
+    }
+}
+
+impl From<Metrics> for CgroupMetrics {
+    fn from(m: Metrics) -> Self {
+        CgroupMetrics::V1(m)
+    }
+}
+
+impl From<v2::Metrics> for CgroupMetrics {
+    fn from(m: v2::Metrics) -> Self {
+        CgroupMetrics::V2(m)
+    }
+}
+
+/// Whether the host runs the unified cgroup v2 hierarchy, whose metrics are the v2 message.
+pub fn is_cgroup2_unified_mode() -> bool {
+    hierarchies::is_cgroup2_unified_mode()
+}
+
+/// Collect the cgroup metrics of a process, as the v2 message on a unified cgroup v2 hierarchy
+/// and as the v1 message otherwise.
+pub fn collect_cgroup_metrics(pid: u32) -> Result<CgroupMetrics> {
+    let paths = read_process_cgroups(pid)?;
+    if hierarchies::is_cgroup2_unified_mode() {
+        let path = paths
+            .get("")
+            .ok_or_else(|| other!("no cgroup v2 for process {}", pid))?;
+        let dir = Path::new(CGROUP_V2_MOUNTPOINT).join(path.trim_start_matches('/'));
+        check_cgroup_procs(&dir, pid)?;
+        Ok(collect_metrics_v2(&dir)?.into())
+    } else {
+        let mountinfo =
+            fs::read_to_string("/proc/self/mountinfo").map_err(io_error!(e, "read mountinfo"))?;
+        let dirs = cgroup_v1_dirs(&mountinfo, &paths);
+        if dirs.is_empty() {
+            return Err(other!("no cgroup v1 for process {}", pid));
+        }
+        for dir in dirs.values() {
+            check_cgroup_procs(dir, pid)?;
+        }
+        Ok(collect_metrics_v1(&dirs).into())
+    }
+}
+
+/// Check that the process is in the `cgroup.procs` of `dir`.
+///
+/// The metrics are read as zero from files that can't be read, so this makes a cgroup the shim
+/// can't read, or sees at another path such as from another cgroup namespace, an error instead.
+fn check_cgroup_procs(dir: &Path, pid: u32) -> Result<()> {
+    let path = dir.join("cgroup.procs");
+    let procs = fs::read_to_string(&path).map_err(io_error!(e, "read {}", path.display()))?;
+    let pid = pid.to_string();
+    if procs.lines().any(|p| p.trim() == pid) {
+        Ok(())
+    } else {
+        Err(other!("process {} is not in {}", pid, path.display()))
+    }
+}
+
+/// Collect the cgroup metrics of a process as the v1 message, converting the v2 metrics on a
+/// unified cgroup v2 hierarchy.
 pub fn collect_metrics(pid: u32) -> Result<Metrics> {
-    let mut metrics = Metrics::new();
-    // get container main process cgroup
-    let path =
-        get_cgroups_relative_paths_by_pid(pid).map_err(other_error!(e, "get process cgroup"))?;
-    let cgroup = Cgroup::load_with_relative_paths(hierarchies::auto(), Path::new("."), path);
-
-    // to make it easy, fill the necessary metrics only.
-    for sub_system in Cgroup::subsystems(&cgroup) {
-        match sub_system {
-            Subsystem::CpuAcct(cpuacct_ctr) => {
-                let mut cpu_usage = CPUUsage::new();
-                cpu_usage.set_total(cpuacct_ctr.cpuacct().usage);
-                let mut cpu_stat = CPUStat::new();
-                cpu_stat.set_usage(cpu_usage);
-                metrics.set_cpu(cpu_stat);
-            }
-            Subsystem::Mem(mem_ctr) => {
-                let mem = mem_ctr.memory_stat();
-                let mut mem_entry = MemoryEntry::new();
-                mem_entry.set_usage(mem.usage_in_bytes);
-                let mut mem_stat = MemoryStat::new();
-                mem_stat.set_usage(mem_entry);
-                mem_stat.set_total_inactive_file(mem.stat.total_inactive_file);
-                metrics.set_memory(mem_stat);
-            }
-            _ => {}
+    match collect_cgroup_metrics(pid)? {
+        CgroupMetrics::V1(m) => Ok(m),
+        CgroupMetrics::V2(m) => Ok(v2_to_v1(&m)),
+    }
+}
+
+/// The memory cgroup directory of a process, and whether it is on the unified cgroup v2
+/// hierarchy.
+pub(crate) fn memory_cgroup_dir(pid: u32) -> Result<(PathBuf, bool)> {
+    let paths = read_process_cgroups(pid)?;
+    if hierarchies::is_cgroup2_unified_mode() {
+        let path = paths
+            .get("")
+            .ok_or_else(|| other!("no cgroup v2 for process {}", pid))?;
+        Ok((
+            Path::new(CGROUP_V2_MOUNTPOINT).join(path.trim_start_matches('/')),
+            true,
+        ))
+    } else {
+        let mountinfo =
+            fs::read_to_string("/proc/self/mountinfo").map_err(io_error!(e, "read mountinfo"))?;
+        let dir = cgroup_v1_dirs(&mountinfo, &paths)
+            .remove("memory")
+            .ok_or_else(|| other!("no memory cgroup for process {}", pid))?;
+        Ok((dir, false))
+    }
+}
+
+/// Read `/proc/<pid>/cgroup`, mapping each controller to the cgroup path of the process.
+///
+/// The path in the unified cgroup v2 hierarchy has an empty controller.
+fn read_process_cgroups(pid: u32) -> Result<HashMap<String, String>> {
+    let content = fs::read_to_string(format!("/proc/{}/cgroup", pid)).map_err(io_error!(
+        e,
+        "read cgroup of process {}",
+        pid
+    ))?;
+    Ok(parse_process_cgroups(&content))
+}
+
+fn parse_process_cgroups(content: &str) -> HashMap<String, String> {
+    let mut paths = HashMap::new();
+    for line in content.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(_), Some(controllers), Some(path)) => (controllers, path),
+            _ => continue,
+        };
+        for c in controllers.split(',') {
+            paths.insert(c.to_string(), path.to_string());
+        }
+    }
+    paths
+}
+
+/// Map each cgroup v1 controller of `paths` to its dir, from the cgroup mounts of `mountinfo`.
+fn cgroup_v1_dirs(mountinfo: &str, paths: &HashMap<String, String>) -> HashMap<String, PathBuf> {
+    let mut dirs = HashMap::new();
+    for line in mountinfo.lines() {
+        let (mount, sb) = match line.split_once(" - ") {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let mut sb = sb.split_whitespace();
+        if sb.next() != Some("cgroup") {
+            continue;
+        }
+        let options = sb.nth(1).unwrap_or_default();
+        let mount: Vec<&str> = mount.split_whitespace().collect();
+        let (root, mountpoint) = match mount.as_slice() {
+            [_, _, _, root, mountpoint, ..] => (*root, *mountpoint),
+            _ => continue,
+        };
+        for c in options.split(',') {
+            if let Some(path) = paths.get(c).filter(|p| !c.is_empty() && !p.is_empty()) {
+                // The mount may expose only a subtree of the hierarchy, like in a container.
+                let path = Path::new(path);
+                let rel = path
+                    .strip_prefix(root)
+                    .or_else(|_| path.strip_prefix("/"))
+                    .unwrap_or(path);
+                let dir = Path::new(mountpoint).join(rel);
+                dirs.insert(c.to_string(), dir);
+            }
         }
     }
+    dirs
+}
+
+fn read_string(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+/// Read a single value, where `max` is [u64::MAX].
+fn read_u64(path: &Path) -> Option<u64> {
+    parse_u64(read_string(path)?.trim())
+}
+
+fn parse_u64(value: &str) -> Option<u64> {
+    if value == "max" {
+        Some(u64::MAX)
+    } else {
+        value.parse().ok()
+    }
+}
+
+/// Read the `key value` lines of a file like `memory.stat`.
+fn read_flat_keyed(path: &Path) -> HashMap<String, u64> {
+    read_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| {
+            let (key, value) = l.split_once(' ')?;
+            Some((key.to_string(), parse_u64(value.trim())?))
+        })
+        .collect()
+}
+
+/// Read the `name key=value...` lines of a file like `io.stat`.
+fn read_nested_keyed(path: &Path) -> Vec<(String, HashMap<String, String>)> {
+    read_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            let name = fields.next()?.to_string();
+            let values = fields
+                .filter_map(|f| f.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            Some((name, values))
+        })
+        .collect()
+}
+
+/// Page sizes of the hugetlb files in `dir` with `suffix`, such as `2MB` for
+/// `hugetlb.2MB.current`.
+fn hugetlb_sizes(dir: &Path, suffix: &str) -> Vec<String> {
+    let mut sizes: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| {
+            let name = e.ok()?.file_name().into_string().ok()?;
+            let size = name.strip_prefix("hugetlb.")?.strip_suffix(suffix)?;
+            Some(size.to_string())
+        })
+        .collect();
+    sizes.sort();
+    sizes
+}
+
+/// Read the `device hca_handle=N hca_object=N` lines of an rdma file, where `max` is
+/// [u32::MAX].
+fn read_rdma(path: &Path) -> Vec<(String, u32, u32)> {
+    let value = |v: Option<&String>| match v.map(String::as_str) {
+        Some("max") => u32::MAX,
+        Some(v) => v.parse().unwrap_or_default(),
+        None => 0,
+    };
+    read_nested_keyed(path)
+        .into_iter()
+        .map(|(device, values)| {
+            (
+                device,
+                value(values.get("hca_handle")),
+                value(values.get("hca_object")),
+            )
+        })
+        .collect()
+}
+
+/// Collect the metrics of the cgroup v2 at `dir`.
+///
+/// Unlimited values are reported as [u64::MAX], like containerd does on cgroup v2.
+pub fn collect_metrics_v2(dir: &Path) -> Result<v2::Metrics> {
+    if !dir.is_dir() {
+        return Err(other!("cgroup {} does not exist", dir.display()));
+    }
+    let mut metrics = v2::Metrics::new();
+
+    let mut pids = v2::PidsStat::new();
+    pids.set_current(read_u64(&dir.join("pids.current")).unwrap_or_default());
+    pids.set_limit(read_u64(&dir.join("pids.max")).unwrap_or_default());
+    metrics.set_pids(pids);
+
+    let stat = read_flat_keyed(&dir.join("cpu.stat"));
+    let get = |k: &str| stat.get(k).copied().unwrap_or_default();
+    let mut cpu = v2::CPUStat::new();
+    cpu.set_usage_usec(get("usage_usec"));
+    cpu.set_user_usec(get("user_usec"));
+    cpu.set_system_usec(get("system_usec"));
+    cpu.set_nr_periods(get("nr_periods"));
+    cpu.set_nr_throttled(get("nr_throttled"));
+    cpu.set_throttled_usec(get("throttled_usec"));
+    metrics.set_cpu(cpu);
+
+    let stat = read_flat_keyed(&dir.join("memory.stat"));
+    let get = |k: &str| stat.get(k).copied().unwrap_or_default();
+    let mut memory = v2::MemoryStat::new();
+    memory.set_anon(get("anon"));
+    memory.set_file(get("file"));
+    memory.set_kernel_stack(get("kernel_stack"));
+    memory.set_slab(get("slab"));
+    memory.set_sock(get("sock"));
+    memory.set_shmem(get("shmem"));
+    memory.set_file_mapped(get("file_mapped"));
+    memory.set_file_dirty(get("file_dirty"));
+    memory.set_file_writeback(get("file_writeback"));
+    memory.set_anon_thp(get("anon_thp"));
+    memory.set_inactive_anon(get("inactive_anon"));
+    memory.set_active_anon(get("active_anon"));
+    memory.set_inactive_file(get("inactive_file"));
+    memory.set_active_file(get("active_file"));
+    memory.set_unevictable(get("unevictable"));
+    memory.set_slab_reclaimable(get("slab_reclaimable"));
+    memory.set_slab_unreclaimable(get("slab_unreclaimable"));
+    memory.set_pgfault(get("pgfault"));
+    memory.set_pgmajfault(get("pgmajfault"));
+    memory.set_workingset_refault(get("workingset_refault"));
+    memory.set_workingset_activate(get("workingset_activate"));
+    memory.set_workingset_nodereclaim(get("workingset_nodereclaim"));
+    memory.set_pgrefill(get("pgrefill"));
+    memory.set_pgscan(get("pgscan"));
+    memory.set_pgsteal(get("pgsteal"));
+    memory.set_pgactivate(get("pgactivate"));
+    memory.set_pgdeactivate(get("pgdeactivate"));
+    memory.set_pglazyfree(get("pglazyfree"));
+    memory.set_pglazyfreed(get("pglazyfreed"));
+    memory.set_thp_fault_alloc(get("thp_fault_alloc"));
+    memory.set_thp_collapse_alloc(get("thp_collapse_alloc"));
+    memory.set_usage(read_u64(&dir.join("memory.current")).unwrap_or_default());
+    memory.set_usage_limit(read_u64(&dir.join("memory.max")).unwrap_or_default());
+    memory.set_swap_usage(read_u64(&dir.join("memory.swap.current")).unwrap_or_default());
+    memory.set_swap_limit(read_u64(&dir.join("memory.swap.max")).unwrap_or_default());
+    metrics.set_memory(memory);
+
+    let events = read_flat_keyed(&dir.join("memory.events"));
+    let get = |k: &str| events.get(k).copied().unwrap_or_default();
+    let mut memory_events = v2::MemoryEvents::new();
+    memory_events.set_low(get("low"));
+    memory_events.set_high(get("high"));
+    memory_events.set_max(get("max"));
+    memory_events.set_oom(get("oom"));
+    memory_events.set_oom_kill(get("oom_kill"));
+    metrics.set_memory_events(memory_events);
+
+    let usage = read_nested_keyed(&dir.join("io.stat"))
+        .into_iter()
+        .filter_map(|(device, values)| {
+            let (major, minor) = device.split_once(':')?;
+            let get = |k: &str| {
+                values
+                    .get(k)
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_default()
+            };
+            let mut entry = v2::IOEntry::new();
+            entry.set_major(major.parse().ok()?);
+            entry.set_minor(minor.parse().ok()?);
+            entry.set_rbytes(get("rbytes"));
+            entry.set_wbytes(get("wbytes"));
+            entry.set_rios(get("rios"));
+            entry.set_wios(get("wios"));
+            Some(entry)
+        })
+        .collect();
+    let mut io = v2::IOStat::new();
+    io.set_usage(usage);
+    metrics.set_io(io);
+
+    let hugetlb = hugetlb_sizes(dir, ".current")
+        .into_iter()
+        .map(|size| {
+            let mut stat = v2::HugeTlbStat::new();
+            let file = |name: &str| dir.join(format!("hugetlb.{}.{}", size, name));
+            stat.set_current(read_u64(&file("current")).unwrap_or_default());
+            stat.set_max(read_u64(&file("max")).unwrap_or_default());
+            stat.set_pagesize(size);
+            stat
+        })
+        .collect();
+    metrics.set_hugetlb(hugetlb);
+
+    let rdma_entries = |name: &str| {
+        read_rdma(&dir.join(name))
+            .into_iter()
+            .map(|(device, handles, objects)| {
+                let mut entry = v2::RdmaEntry::new();
+                entry.set_device(device);
+                entry.set_hca_handles(handles);
+                entry.set_hca_objects(objects);
+                entry
+            })
+            .collect()
+    };
+    let mut rdma = v2::RdmaStat::new();
+    rdma.set_current(rdma_entries("rdma.current"));
+    rdma.set_limit(rdma_entries("rdma.max"));
+    metrics.set_rdma(rdma);
+
     Ok(metrics)
 }
 
+/// Name of the block device `major:minor`, empty if unknown.
+fn block_device(major: u64, minor: u64) -> String {
+    read_string(Path::new(&format!(
+        "/sys/dev/block/{}:{}/uevent",
+        major, minor
+    )))
+    .and_then(|uevent| {
+        uevent
+            .lines()
+            .find_map(|l| l.strip_prefix("DEVNAME="))
+            .map(|name| format!("/dev/{}", name))
+    })
+    .unwrap_or_default()
+}
+
+/// Read the `major:minor [op] value` lines of a blkio file.
+fn blkio_entries(path: &Path) -> Vec<BlkIOEntry> {
+    read_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| {
+            let fields: Vec<&str> = l.split_whitespace().collect();
+            let (device, op, value) = match fields.as_slice() {
+                [device, op, value] => (*device, *op, *value),
+                [device, value] => (*device, "", *value),
+                _ => return None,
+            };
+            // Skips the "Total" line.
+            let (major, minor) = device.split_once(':')?;
+            let mut entry = BlkIOEntry::new();
+            entry.set_major(major.parse().ok()?);
+            entry.set_minor(minor.parse().ok()?);
+            entry.set_op(op.to_string());
+            entry.set_value(value.parse().ok()?);
+            entry.set_device(block_device(entry.major, entry.minor));
+            Some(entry)
+        })
+        .collect()
+}
+
+fn memory_entry(dir: &Path, prefix: &str) -> Option<MemoryEntry> {
+    let file = |name: &str| dir.join(format!("{}.{}", prefix, name));
+    let mut entry = MemoryEntry::new();
+    entry.set_usage(read_u64(&file("usage_in_bytes"))?);
+    entry.set_limit(read_u64(&file("limit_in_bytes")).unwrap_or_default());
+    entry.set_max(read_u64(&file("max_usage_in_bytes")).unwrap_or_default());
+    entry.set_failcnt(read_u64(&file("failcnt")).unwrap_or_default());
+    Some(entry)
+}
+
+/// Collect the cgroup v1 metrics, from the dirs of the cgroup by controller.
+pub fn collect_metrics_v1(dirs: &HashMap<String, PathBuf>) -> Metrics {
+    let mut metrics = Metrics::new();
+
+    if let Some(dir) = dirs.get("pids") {
+        let mut pids = PidsStat::new();
+        pids.set_current(read_u64(&dir.join("pids.current")).unwrap_or_default());
+        pids.set_limit(
+            read_u64(&dir.join("pids.max"))
+                .filter(|v| *v != u64::MAX)
+                .unwrap_or_default(),
+        );
+        metrics.set_pids(pids);
+    }
+
+    let mut cpu = CPUStat::new();
+    if let Some(dir) = dirs.get("cpuacct") {
+        let mut usage = CPUUsage::new();
+        usage.set_total(read_u64(&dir.join("cpuacct.usage")).unwrap_or_default());
+        usage.set_per_cpu(
+            read_string(&dir.join("cpuacct.usage_percpu"))
+                .unwrap_or_default()
+                .split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect(),
+        );
+        // cpuacct.stat is in clock ticks.
+        let ticks = nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK)
+            .ok()
+            .flatten()
+            .filter(|t| *t > 0)
+            .unwrap_or(100) as u64;
+        let stat = read_flat_keyed(&dir.join("cpuacct.stat"));
+        let nanos = |k: &str| stat.get(k).copied().unwrap_or_default() * 1_000_000_000 / ticks;
+        usage.set_user(nanos("user"));
+        usage.set_kernel(nanos("system"));
+        cpu.set_usage(usage);
+    }
+    if let Some(dir) = dirs.get("cpu") {
+        let stat = read_flat_keyed(&dir.join("cpu.stat"));
+        let get = |k: &str| stat.get(k).copied().unwrap_or_default();
+        let mut throttle = Throttle::new();
+        throttle.set_periods(get("nr_periods"));
+        throttle.set_throttled_periods(get("nr_throttled"));
+        throttle.set_throttled_time(get("throttled_time"));
+        cpu.set_throttling(throttle);
+    }
+    metrics.set_cpu(cpu);
+
+    if let Some(dir) = dirs.get("memory") {
+        let stat = read_flat_keyed(&dir.join("memory.stat"));
+        let get = |k: &str| stat.get(k).copied().unwrap_or_default();
+        let mut memory = MemoryStat::new();
+        memory.set_cache(get("cache"));
+        memory.set_rss(get("rss"));
+        memory.set_rss_huge(get("rss_huge"));
+        memory.set_mapped_file(get("mapped_file"));
+        memory.set_dirty(get("dirty"));
+        memory.set_writeback(get("writeback"));
+        memory.set_pg_pg_in(get("pgpgin"));
+        memory.set_pg_pg_out(get("pgpgout"));
+        memory.set_pg_fault(get("pgfault"));
+        memory.set_pg_maj_fault(get("pgmajfault"));
+        memory.set_inactive_anon(get("inactive_anon"));
+        memory.set_active_anon(get("active_anon"));
+        memory.set_inactive_file(get("inactive_file"));
+        memory.set_active_file(get("active_file"));
+        memory.set_unevictable(get("unevictable"));
+        memory.set_hierarchical_memory_limit(get("hierarchical_memory_limit"));
+        memory.set_hierarchical_swap_limit(get("hierarchical_memsw_limit"));
+        memory.set_total_cache(get("total_cache"));
+        memory.set_total_rss(get("total_rss"));
+        memory.set_total_rss_huge(get("total_rss_huge"));
+        memory.set_total_mapped_file(get("total_mapped_file"));
+        memory.set_total_dirty(get("total_dirty"));
+        memory.set_total_writeback(get("total_writeback"));
+        memory.set_total_pg_pg_in(get("total_pgpgin"));
+        memory.set_total_pg_pg_out(get("total_pgpgout"));
+        memory.set_total_pg_fault(get("total_pgfault"));
+        memory.set_total_pg_maj_fault(get("total_pgmajfault"));
+        memory.set_total_inactive_anon(get("total_inactive_anon"));
+        memory.set_total_active_anon(get("total_active_anon"));
+        memory.set_total_inactive_file(get("total_inactive_file"));
+        memory.set_total_active_file(get("total_active_file"));
+        memory.set_total_unevictable(get("total_unevictable"));
+        if let Some(usage) = memory_entry(dir, "memory") {
+            memory.set_usage(usage);
+        }
+        if let Some(swap) = memory_entry(dir, "memory.memsw") {
+            memory.set_swap(swap);
+        }
+        if let Some(kernel) = memory_entry(dir, "memory.kmem") {
+            memory.set_kernel(kernel);
+        }
+        if let Some(kernel_tcp) = memory_entry(dir, "memory.kmem.tcp") {
+            memory.set_kernel_tcp(kernel_tcp);
+        }
+        metrics.set_memory(memory);
+
+        let oom = read_flat_keyed(&dir.join("memory.oom_control"));
+        let get = |k: &str| oom.get(k).copied().unwrap_or_default();
+        let mut oom_control = MemoryOomControl::new();
+        oom_control.set_oom_kill_disable(get("oom_kill_disable"));
+        oom_control.set_under_oom(get("under_oom"));
+        oom_control.set_oom_kill(get("oom_kill"));
+        metrics.set_memory_oom_control(oom_control);
+    }
+
+    if let Some(dir) = dirs.get("blkio") {
+        let entries = |name: &str| blkio_entries(&dir.join(format!("blkio.{}", name)));
+        let mut blkio = BlkIOStat::new();
+        blkio.set_io_service_bytes_recursive(entries("io_service_bytes_recursive"));
+        blkio.set_io_serviced_recursive(entries("io_serviced_recursive"));
+        blkio.set_io_queued_recursive(entries("io_queued_recursive"));
+        blkio.set_io_service_time_recursive(entries("io_service_time_recursive"));
+        blkio.set_io_wait_time_recursive(entries("io_wait_time_recursive"));
+        blkio.set_io_merged_recursive(entries("io_merged_recursive"));
+        blkio.set_io_time_recursive(entries("time_recursive"));
+        blkio.set_sectors_recursive(entries("sectors_recursive"));
+        // Without the CFQ scheduler only the throttle stats are accounted.
+        if blkio.io_service_bytes_recursive.is_empty() {
+            blkio.set_io_service_bytes_recursive(entries("throttle.io_service_bytes"));
+            blkio.set_io_serviced_recursive(entries("throttle.io_serviced"));
+        }
+        metrics.set_blkio(blkio);
+    }
+
+    if let Some(dir) = dirs.get("hugetlb") {
+        let hugetlb = hugetlb_sizes(dir, ".usage_in_bytes")
+            .into_iter()
+            .map(|size| {
+                let file = |name: &str| dir.join(format!("hugetlb.{}.{}", size, name));
+                let mut stat = HugetlbStat::new();
+                stat.set_usage(read_u64(&file("usage_in_bytes")).unwrap_or_default());
+                stat.set_max(read_u64(&file("max_usage_in_bytes")).unwrap_or_default());
+                stat.set_failcnt(read_u64(&file("failcnt")).unwrap_or_default());
+                stat.set_pagesize(size);
+                stat
+            })
+            .collect();
+        metrics.set_hugetlb(hugetlb);
+    }
+
+    if let Some(dir) = dirs.get("rdma") {
+        let rdma_entries = |name: &str| {
+            read_rdma(&dir.join(name))
+                .into_iter()
+                .map(|(device, handles, objects)| {
+                    let mut entry = RdmaEntry::new();
+                    entry.set_device(device);
+                    entry.set_hca_handles(handles);
+                    entry.set_hca_objects(objects);
+                    entry
+                })
+                .collect()
+        };
+        let mut rdma = RdmaStat::new();
+        rdma.set_current(rdma_entries("rdma.current"));
+        rdma.set_limit(rdma_entries("rdma.max"));
+        metrics.set_rdma(rdma);
+    }
+
+    metrics
+}
+
+/// Convert cgroup v2 metrics to the v1 message, for the fields both have.
+fn v2_to_v1(m: &v2::Metrics) -> Metrics {
+    let mut metrics = Metrics::new();
+
+    let mut pids = PidsStat::new();
+    pids.set_current(m.pids.current);
+    pids.set_limit(m.pids.limit);
+    metrics.set_pids(pids);
+
+    let mut usage = CPUUsage::new();
+    usage.set_total(m.cpu.usage_usec * 1000);
+    usage.set_user(m.cpu.user_usec * 1000);
+    usage.set_kernel(m.cpu.system_usec * 1000);
+    let mut throttle = Throttle::new();
+    throttle.set_periods(m.cpu.nr_periods);
+    throttle.set_throttled_periods(m.cpu.nr_throttled);
+    throttle.set_throttled_time(m.cpu.throttled_usec * 1000);
+    let mut cpu = CPUStat::new();
+    cpu.set_usage(usage);
+    cpu.set_throttling(throttle);
+    metrics.set_cpu(cpu);
+
+    let mem = &m.memory;
+    let mut memory = MemoryStat::new();
+    memory.set_cache(mem.file);
+    memory.set_rss(mem.anon);
+    memory.set_rss_huge(mem.anon_thp);
+    memory.set_mapped_file(mem.file_mapped);
+    memory.set_dirty(mem.file_dirty);
+    memory.set_writeback(mem.file_writeback);
+    memory.set_pg_fault(mem.pgfault);
+    memory.set_pg_maj_fault(mem.pgmajfault);
+    memory.set_inactive_anon(mem.inactive_anon);
+    memory.set_active_anon(mem.active_anon);
+    memory.set_inactive_file(mem.inactive_file);
+    memory.set_active_file(mem.active_file);
+    memory.set_unevictable(mem.unevictable);
+    // cgroup v2 stats are hierarchical already.
+    memory.set_total_inactive_file(mem.inactive_file);
+    let mut entry = MemoryEntry::new();
+    entry.set_usage(mem.usage);
+    entry.set_limit(mem.usage_limit);
+    memory.set_usage(entry);
+    let mut swap = MemoryEntry::new();
+    swap.set_usage(mem.swap_usage);
+    swap.set_limit(mem.swap_limit);
+    memory.set_swap(swap);
+    metrics.set_memory(memory);
+
+    let mut service_bytes = Vec::new();
+    let mut serviced = Vec::new();
+    for e in &m.io.usage {
+        let entry = |op: &str, value: u64| {
+            let mut entry = BlkIOEntry::new();
+            entry.set_major(e.major);
+            entry.set_minor(e.minor);
+            entry.set_op(op.to_string());
+            entry.set_value(value);
+            entry
+        };
+        service_bytes.push(entry("Read", e.rbytes));
+        service_bytes.push(entry("Write", e.wbytes));
+        serviced.push(entry("Read", e.rios));
+        serviced.push(entry("Write", e.wios));
+    }
+    let mut blkio = BlkIOStat::new();
+    blkio.set_io_service_bytes_recursive(service_bytes);
+    blkio.set_io_serviced_recursive(serviced);
+    metrics.set_blkio(blkio);
+
+    let hugetlb = m
+        .hugetlb
+        .iter()
+        .map(|h| {
+            let mut stat = HugetlbStat::new();
+            stat.set_usage(h.current);
+            stat.set_max(h.max);
+            stat.set_pagesize(h.pagesize.clone());
+            stat
+        })
+        .collect();
+    metrics.set_hugetlb(hugetlb);
+
+    metrics
+}
+
 /// Update process cgroup limits
+///
+/// Every field of `resources` is written to the cgroup of the process, converting the cgroup v1
+/// ones on a unified cgroup v2 hierarchy. The `unified` keys are written as they are on cgroup v2.
+/// If a write fails, the files written before it are restored to their previous values.
 pub fn update_resources(pid: u32, resources: &LinuxResources) -> Result<()> {
-    // get container main process cgroup
-    let path =
-        get_cgroups_relative_paths_by_pid(pid).map_err(other_error!(e, "get process cgroup"))?;
-    let cgroup = Cgroup::load_with_relative_paths(hierarchies::auto(), Path::new("."), path);
-
-    for sub_system in Cgroup::subsystems(&cgroup) {
-        match sub_system {
-            Subsystem::Pid(pid_ctr) => {
-                // set maximum number of PIDs
-                if let Some(pids) = resources.pids() {
-                    pid_ctr
-                        .set_pid_max(MaxValue::Value(pids.limit()))
-                        .map_err(other_error!(e, "set pid max"))?;
+    let paths = read_process_cgroups(pid)?;
+    let writes = if hierarchies::is_cgroup2_unified_mode() {
+        let path = paths
+            .get("")
+            .ok_or_else(|| other!("no cgroup v2 for process {}", pid))?;
+        let dir = Path::new(CGROUP_V2_MOUNTPOINT).join(path.trim_start_matches('/'));
+        resource_writes_v2(&dir, resources)?
+    } else {
+        let mountinfo =
+            fs::read_to_string("/proc/self/mountinfo").map_err(io_error!(e, "read mountinfo"))?;
+        resource_writes_v1(&cgroup_v1_dirs(&mountinfo, &paths), resources)?
+    };
+    apply_writes(&writes)
+}
+
+/// How the previous content of a cgroup file is restored.
+#[derive(Debug, Clone, PartialEq)]
+enum Restore {
+    /// Write back the first line of the file.
+    Value,
+    /// Write back the value of a field of a flat keyed file, like `oom_kill_disable` of
+    /// `memory.oom_control`.
+    Field(&'static str),
+    /// The file has a line per key, the first word of the written value, like a device of
+    /// `io.max`. Write back the line of the key, or the key and the given reset value when the
+    /// key has no line.
+    Keyed(&'static str),
+    /// Write back the rules of `devices.list` after denying all devices.
+    Devices,
+}
+
+/// A value to write to a cgroup file.
+#[derive(Debug, Clone, PartialEq)]
+struct CgroupWrite {
+    path: PathBuf,
+    value: String,
+    restore: Restore,
+}
+
+impl CgroupWrite {
+    fn value(path: PathBuf, value: impl ToString) -> Self {
+        Self {
+            path,
+            value: value.to_string(),
+            restore: Restore::Value,
+        }
+    }
+
+    fn keyed(path: PathBuf, value: impl ToString, reset: &'static str) -> Self {
+        Self {
+            path,
+            value: value.to_string(),
+            restore: Restore::Keyed(reset),
+        }
+    }
+
+    /// The writes that restore the current content of the file.
+    fn snapshot(&self) -> Vec<(PathBuf, String)> {
+        let content = read_string(&self.path).unwrap_or_default();
+        match self.restore {
+            Restore::Value => content
+                .lines()
+                .next()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| vec![(self.path.clone(), l.trim().to_string())])
+                .unwrap_or_default(),
+            Restore::Field(field) => content
+                .lines()
+                .filter_map(|l| l.split_once(' '))
+                .find(|(k, _)| *k == field)
+                .map(|(_, v)| vec![(self.path.clone(), v.trim().to_string())])
+                .unwrap_or_default(),
+            Restore::Keyed(reset) => {
+                let key = self.value.split_whitespace().next().unwrap_or_default();
+                let line = content
+                    .lines()
+                    .find(|l| l.split_whitespace().next() == Some(key))
+                    .map(|l| l.trim().to_string())
+                    .unwrap_or_else(|| format!("{} {}", key, reset));
+                vec![(self.path.clone(), line)]
+            }
+            Restore::Devices => {
+                let dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+                let mut writes = vec![(dir.join("devices.deny"), "a".to_string())];
+                writes.extend(
+                    read_string(&dir.join("devices.list"))
+                        .unwrap_or_default()
+                        .lines()
+                        .map(|l| (dir.join("devices.allow"), l.trim().to_string())),
+                );
+                writes
+            }
+        }
+    }
+}
+
+fn write_cgroup_file(path: &Path, value: &str) -> Result<()> {
+    fs::write(path, value).map_err(io_error!(e, "write {} to {}", value, path.display()))
+}
+
+/// Apply the writes in order. When one fails, the files already written are restored in reverse
+/// order and the error of the failed write is returned.
+fn apply_writes(writes: &[CgroupWrite]) -> Result<()> {
+    let mut undo: Vec<Vec<(PathBuf, String)>> = Vec::new();
+    let mut devices_saved = false;
+    for w in writes {
+        // devices.list is saved once, restoring it undoes all the device rules
+        let snapshot = if w.restore == Restore::Devices && devices_saved {
+            Vec::new()
+        } else {
+            devices_saved |= w.restore == Restore::Devices;
+            w.snapshot()
+        };
+        if let Err(e) = write_cgroup_file(&w.path, &w.value) {
+            for (path, value) in undo.iter().rev().flatten() {
+                if let Err(re) = write_cgroup_file(path, value) {
+                    warn!("failed to restore cgroup file: {}", re);
+                }
+            }
+            return Err(e);
+        }
+        undo.push(snapshot);
+    }
+    Ok(())
+}
+
+/// A limit where a negative value is unlimited.
+fn max_or(value: i64) -> String {
+    if value < 0 {
+        "max".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Convert cgroup v1 CPU shares [2, 262144] to a cgroup v2 CPU weight [1, 10000].
+fn cpu_shares_to_weight(shares: u64) -> u64 {
+    let shares = shares.clamp(2, 262144);
+    1 + ((shares - 2) * 9999) / 262142
+}
+
+/// Convert a cgroup v1 blkio weight [10, 1000] to a cgroup v2 io weight [1, 10000].
+fn blkio_weight_to_io_weight(weight: u16) -> u64 {
+    let weight = (weight as u64).clamp(10, 1000);
+    1 + (weight - 10) * 9999 / 990
+}
+
+/// Convert the cgroup v1 memory+swap limit to the cgroup v2 swap limit, which excludes memory.
+///
+/// `None` leaves `memory.swap.max` unchanged.
+fn swap_to_v2(swap: i64, memory: i64) -> Result<Option<String>> {
+    if memory == -1 && swap == 0 {
+        return Ok(Some("max".to_string()));
+    }
+    match swap {
+        -1 => Ok(Some("max".to_string())),
+        0 => Ok(None),
+        _ if memory == 0 || memory == -1 => Err(other!(
+            "memory+swap limit {} needs a memory limit on cgroup v2",
+            swap
+        )),
+        _ if swap < memory => Err(other!(
+            "memory+swap limit {} is less than the memory limit {}",
+            swap,
+            memory
+        )),
+        _ => Ok(Some((swap - memory).to_string())),
+    }
+}
+
+/// The writes that apply `resources` to the cgroup v2 directory `dir`.
+fn resource_writes_v2(dir: &Path, resources: &LinuxResources) -> Result<Vec<CgroupWrite>> {
+    let mut writes = Vec::new();
+
+    if let Some(memory) = resources.memory() {
+        let limit = memory.limit().unwrap_or_default();
+        if limit != 0 {
+            writes.push(CgroupWrite::value(dir.join("memory.max"), max_or(limit)));
+        }
+        if let Some(swap) = swap_to_v2(memory.swap().unwrap_or_default(), limit)? {
+            writes.push(CgroupWrite::value(dir.join("memory.swap.max"), swap));
+        }
+        if let Some(reservation) = memory.reservation().filter(|r| *r != 0) {
+            writes.push(CgroupWrite::value(
+                dir.join("memory.low"),
+                max_or(reservation),
+            ));
+        }
+        if memory.kernel().unwrap_or_default() != 0 || memory.kernel_tcp().unwrap_or_default() != 0
+        {
+            return Err(other!(
+                "kernel memory limits are not supported on cgroup v2"
+            ));
+        }
+        if memory.swappiness().is_some() {
+            return Err(other!("memory swappiness is not supported on cgroup v2"));
+        }
+        if memory.disable_oom_killer() == Some(true) {
+            return Err(other!(
+                "disabling the OOM killer is not supported on cgroup v2"
+            ));
+        }
+    }
+
+    if let Some(cpu) = resources.cpu() {
+        if cpu.realtime_runtime().unwrap_or_default() != 0
+            || cpu.realtime_period().unwrap_or_default() != 0
+        {
+            return Err(other!(
+                "realtime CPU scheduling is not supported on cgroup v2"
+            ));
+        }
+        if let Some(shares) = cpu.shares().filter(|s| *s != 0) {
+            writes.push(CgroupWrite::value(
+                dir.join("cpu.weight"),
+                cpu_shares_to_weight(shares),
+            ));
+        }
+        let period = cpu.period().filter(|p| *p != 0);
+        if cpu.quota().is_some() || period.is_some() {
+            let quota = match cpu.quota() {
+                Some(quota) => max_or(quota),
+                // cpu.max needs a quota to set the period
+                None => read_string(&dir.join("cpu.max"))
+                    .and_then(|c| c.split_whitespace().next().map(String::from))
+                    .unwrap_or_else(|| "max".to_string()),
+            };
+            let value = match period {
+                Some(period) => format!("{} {}", quota, period),
+                None => quota,
+            };
+            writes.push(CgroupWrite::value(dir.join("cpu.max"), value));
+        }
+        if let Some(burst) = cpu.burst() {
+            writes.push(CgroupWrite::value(dir.join("cpu.max.burst"), burst));
+        }
+        if let Some(idle) = cpu.idle() {
+            writes.push(CgroupWrite::value(dir.join("cpu.idle"), idle));
+        }
+        if let Some(cpus) = cpu.cpus().as_ref().filter(|c| !c.is_empty()) {
+            writes.push(CgroupWrite::value(dir.join("cpuset.cpus"), cpus));
+        }
+        if let Some(mems) = cpu.mems().as_ref().filter(|m| !m.is_empty()) {
+            writes.push(CgroupWrite::value(dir.join("cpuset.mems"), mems));
+        }
+    }
+
+    // a zero limit is left unchanged, like `runc update` does
+    if let Some(pids) = resources.pids().filter(|p| p.limit() != 0) {
+        writes.push(CgroupWrite::value(
+            dir.join("pids.max"),
+            max_or(pids.limit()),
+        ));
+    }
+
+    if let Some(blkio) = resources.block_io() {
+        // BFQ takes the cgroup v1 weights, the io controller its own range
+        let bfq = dir.join("io.bfq.weight");
+        let (weight_file, convert): (PathBuf, fn(u16) -> u64) = if bfq.exists() {
+            (bfq, u64::from)
+        } else {
+            (dir.join("io.weight"), blkio_weight_to_io_weight)
+        };
+        if let Some(weight) = blkio.weight().filter(|w| *w != 0) {
+            let value = format!("default {}", convert(weight));
+            writes.push(CgroupWrite::keyed(weight_file.clone(), value, "100"));
+        }
+        for d in blkio.weight_device().as_ref().into_iter().flatten() {
+            if let Some(weight) = d.weight() {
+                let value = format!("{}:{} {}", d.major(), d.minor(), convert(weight));
+                writes.push(CgroupWrite::keyed(weight_file.clone(), value, "0"));
+            }
+        }
+        let throttles = vec![
+            ("rbps", blkio.throttle_read_bps_device()),
+            ("wbps", blkio.throttle_write_bps_device()),
+            ("riops", blkio.throttle_read_iops_device()),
+            ("wiops", blkio.throttle_write_iops_device()),
+        ];
+        for (key, devices) in throttles {
+            for d in devices.as_ref().into_iter().flatten() {
+                let rate = match d.rate() {
+                    0 => "max".to_string(),
+                    rate => rate.to_string(),
+                };
+                let value = format!("{}:{} {}={}", d.major(), d.minor(), key, rate);
+                writes.push(CgroupWrite::keyed(
+                    dir.join("io.max"),
+                    value,
+                    "rbps=max wbps=max riops=max wiops=max",
+                ));
+            }
+        }
+    }
+
+    for limit in resources.hugepage_limits().as_ref().into_iter().flatten() {
+        writes.push(CgroupWrite::value(
+            dir.join(format!("hugetlb.{}.max", limit.page_size())),
+            max_or(limit.limit()),
+        ));
+    }
+
+    writes.extend(rdma_writes(&dir.join("rdma.max"), resources));
+
+    if resources
+        .devices()
+        .as_ref()
+        .map_or(false, |d| !d.is_empty())
+    {
+        return Err(other!(
+            "device rules are enforced with eBPF on cgroup v2 and can't be updated"
+        ));
+    }
+    if let Some(network) = resources.network() {
+        if network.class_id().is_some()
+            || network
+                .priorities()
+                .as_ref()
+                .map_or(false, |p| !p.is_empty())
+        {
+            return Err(other!(
+                "network class and priorities are not supported on cgroup v2"
+            ));
+        }
+    }
+
+    // unified keys are written last to override the converted values
+    let mut unified: Vec<_> = resources.unified().iter().flatten().collect();
+    unified.sort();
+    for (key, value) in unified {
+        if key.contains('/') || key.starts_with('.') {
+            return Err(other!("invalid cgroup v2 file {}", key));
+        }
+        writes.push(CgroupWrite::value(dir.join(key), value));
+    }
+    Ok(writes)
+}
+
+/// The `device hca_handle=N hca_object=N` writes of the rdma limits to `path`.
+fn rdma_writes(path: &Path, resources: &LinuxResources) -> Vec<CgroupWrite> {
+    let mut rdma: Vec<_> = resources.rdma().iter().flatten().collect();
+    rdma.sort_by(|a, b| a.0.cmp(b.0));
+    rdma.into_iter()
+        .filter_map(|(device, limit)| {
+            let mut value = device.to_string();
+            if let Some(handles) = limit.hca_handles() {
+                value.push_str(&format!(" hca_handle={}", handles));
+            }
+            if let Some(objects) = limit.hca_objects() {
+                value.push_str(&format!(" hca_object={}", objects));
+            }
+            if value.len() == device.len() {
+                return None;
+            }
+            Some(CgroupWrite::keyed(
+                path.to_path_buf(),
+                value,
+                "hca_handle=max hca_object=max",
+            ))
+        })
+        .collect()
+}
+
+/// The writes that apply `resources` to the cgroup v1 directories of each controller.
+fn resource_writes_v1(
+    dirs: &HashMap<String, PathBuf>,
+    resources: &LinuxResources,
+) -> Result<Vec<CgroupWrite>> {
+    if resources
+        .unified()
+        .as_ref()
+        .map_or(false, |u| !u.is_empty())
+    {
+        return Err(other!("unified resources are only supported on cgroup v2"));
+    }
+    let dir = |controller: &str| {
+        let dir = dirs.get(controller);
+        if dir.is_none() {
+            debug!("cgroup controller {} is not mounted, skip it", controller);
+        }
+        dir
+    };
+    let mut writes = Vec::new();
+
+    if let Some(memory) = resources.memory() {
+        if let Some(dir) = dir("memory") {
+            let limit = memory.limit().unwrap_or_default();
+            let mut swap = memory.swap().unwrap_or_default();
+            if limit == -1 && swap == 0 {
+                swap = -1;
+            }
+            let limit_write = CgroupWrite::value(dir.join("memory.limit_in_bytes"), limit);
+            let swap_write = CgroupWrite::value(dir.join("memory.memsw.limit_in_bytes"), swap);
+            if limit != 0 && swap != 0 {
+                // the memory limit can't exceed the memory+swap limit, so raising both writes
+                // memory+swap first
+                let current = read_u64(&dir.join("memory.limit_in_bytes")).unwrap_or_default();
+                if swap == -1 || current < swap as u64 {
+                    writes.extend(vec![swap_write, limit_write]);
+                } else {
+                    writes.extend(vec![limit_write, swap_write]);
+                }
+            } else if limit != 0 {
+                writes.push(limit_write);
+            } else if swap != 0 {
+                writes.push(swap_write);
+            }
+            if let Some(reservation) = memory.reservation().filter(|r| *r != 0) {
+                writes.push(CgroupWrite::value(
+                    dir.join("memory.soft_limit_in_bytes"),
+                    reservation,
+                ));
+            }
+            if let Some(kernel) = memory.kernel().filter(|k| *k != 0) {
+                writes.push(CgroupWrite::value(
+                    dir.join("memory.kmem.limit_in_bytes"),
+                    kernel,
+                ));
+            }
+            if let Some(kernel_tcp) = memory.kernel_tcp().filter(|k| *k != 0) {
+                writes.push(CgroupWrite::value(
+                    dir.join("memory.kmem.tcp.limit_in_bytes"),
+                    kernel_tcp,
+                ));
+            }
+            if let Some(swappiness) = memory.swappiness() {
+                if swappiness > 100 {
+                    return Err(other!("invalid memory swappiness {}", swappiness));
                 }
+                writes.push(CgroupWrite::value(
+                    dir.join("memory.swappiness"),
+                    swappiness,
+                ));
+            }
+            if let Some(disable) = memory.disable_oom_killer() {
+                writes.push(CgroupWrite {
+                    path: dir.join("memory.oom_control"),
+                    value: (disable as u8).to_string(),
+                    restore: Restore::Field("oom_kill_disable"),
+                });
+            }
+            if let Some(hierarchy) = memory.use_hierarchy() {
+                writes.push(CgroupWrite::value(
+                    dir.join("memory.use_hierarchy"),
+                    hierarchy as u8,
+                ));
+            }
+        }
+    }
+
+    if let Some(cpu) = resources.cpu() {
+        if let Some(dir) = dir("cpu") {
+            if let Some(shares) = cpu.shares().filter(|s| *s != 0) {
+                writes.push(CgroupWrite::value(dir.join("cpu.shares"), shares));
+            }
+            if let Some(period) = cpu.period().filter(|p| *p != 0) {
+                writes.push(CgroupWrite::value(dir.join("cpu.cfs_period_us"), period));
+            }
+            if let Some(quota) = cpu.quota().filter(|q| *q != 0) {
+                writes.push(CgroupWrite::value(dir.join("cpu.cfs_quota_us"), quota));
+            }
+            if let Some(burst) = cpu.burst() {
+                writes.push(CgroupWrite::value(dir.join("cpu.cfs_burst_us"), burst));
+            }
+            if let Some(idle) = cpu.idle() {
+                writes.push(CgroupWrite::value(dir.join("cpu.idle"), idle));
             }
-            Subsystem::Mem(mem_ctr) => {
-                if let Some(memory) = resources.memory() {
-                    // set memory limit in bytes
-                    if let Some(limit) = memory.limit() {
-                        mem_ctr
-                            .set_limit(limit)
-                            .map_err(other_error!(e, "set mem limit"))?;
-                    }
-
-                    // set memory swap limit in bytes
-                    if let Some(swap) = memory.swap() {
-                        mem_ctr
-                            .set_memswap_limit(swap)
-                            .map_err(other_error!(e, "set memsw limit"))?;
-                    }
+            // the period bounds the runtime, so it is written first
+            if let Some(period) = cpu.realtime_period().filter(|p| *p != 0) {
+                writes.push(CgroupWrite::value(dir.join("cpu.rt_period_us"), period));
+            }
+            if let Some(runtime) = cpu.realtime_runtime().filter(|r| *r != 0) {
+                writes.push(CgroupWrite::value(dir.join("cpu.rt_runtime_us"), runtime));
+            }
+        }
+        let cpus = cpu.cpus().as_ref().filter(|c| !c.is_empty());
+        let mems = cpu.mems().as_ref().filter(|m| !m.is_empty());
+        if cpus.is_some() || mems.is_some() {
+            if let Some(dir) = dir("cpuset") {
+                if let Some(cpus) = cpus {
+                    writes.push(CgroupWrite::value(dir.join("cpuset.cpus"), cpus));
+                }
+                if let Some(mems) = mems {
+                    writes.push(CgroupWrite::value(dir.join("cpuset.mems"), mems));
                 }
             }
-            Subsystem::CpuSet(cpuset_ctr) => {
-                if let Some(cpu) = resources.cpu() {
-                    // set CPUs to use within the cpuset
-                    if let Some(cpus) = cpu.cpus() {
-                        cpuset_ctr
-                            .set_cpus(cpus)
-                            .map_err(other_error!(e, "set CPU sets"))?;
-                    }
-
-                    // set list of memory nodes in the cpuset
-                    if let Some(mems) = cpu.mems() {
-                        cpuset_ctr
-                            .set_mems(mems)
-                            .map_err(other_error!(e, "set CPU memes"))?;
-                    }
+        }
+    }
+
+    // a zero limit is left unchanged, like `runc update` does
+    if let Some(pids) = resources.pids().filter(|p| p.limit() != 0) {
+        if let Some(dir) = dir("pids") {
+            writes.push(CgroupWrite::value(
+                dir.join("pids.max"),
+                max_or(pids.limit()),
+            ));
+        }
+    }
+
+    if let Some(blkio) = resources.block_io() {
+        if let Some(dir) = dir("blkio") {
+            if let Some(weight) = blkio.weight().filter(|w| *w != 0) {
+                let file = dir.join("blkio.weight");
+                let file = if file.exists() {
+                    file
+                } else {
+                    dir.join("blkio.bfq.weight")
+                };
+                writes.push(CgroupWrite::value(file, weight));
+            }
+            if let Some(leaf_weight) = blkio.leaf_weight().filter(|w| *w != 0) {
+                writes.push(CgroupWrite::value(
+                    dir.join("blkio.leaf_weight"),
+                    leaf_weight,
+                ));
+            }
+            for d in blkio.weight_device().as_ref().into_iter().flatten() {
+                if let Some(weight) = d.weight() {
+                    let value = format!("{}:{} {}", d.major(), d.minor(), weight);
+                    writes.push(CgroupWrite::keyed(
+                        dir.join("blkio.weight_device"),
+                        value,
+                        "0",
+                    ));
+                }
+                if let Some(leaf_weight) = d.leaf_weight() {
+                    let value = format!("{}:{} {}", d.major(), d.minor(), leaf_weight);
+                    writes.push(CgroupWrite::keyed(
+                        dir.join("blkio.leaf_weight_device"),
+                        value,
+                        "0",
+                    ));
                 }
             }
-            Subsystem::Cpu(cpu_ctr) => {
-                if let Some(cpu) = resources.cpu() {
-                    // set CPU shares
-                    if let Some(shares) = cpu.shares() {
-                        cpu_ctr
-                            .set_shares(shares)
-                            .map_err(other_error!(e, "set CPU share"))?;
-                    }
-
-                    // set CPU hardcap limit
-                    if let Some(quota) = cpu.quota() {
-                        cpu_ctr
-                            .set_cfs_quota(quota)
-                            .map_err(other_error!(e, "set CPU quota"))?;
-                    }
-
-                    // set CPU hardcap period
-                    if let Some(period) = cpu.period() {
-                        cpu_ctr
-                            .set_cfs_period(period)
-                            .map_err(other_error!(e, "set CPU period"))?;
-                    }
+            let throttles = vec![
+                ("read_bps", blkio.throttle_read_bps_device()),
+                ("write_bps", blkio.throttle_write_bps_device()),
+                ("read_iops", blkio.throttle_read_iops_device()),
+                ("write_iops", blkio.throttle_write_iops_device()),
+            ];
+            for (name, devices) in throttles {
+                let file = dir.join(format!("blkio.throttle.{}_device", name));
+                for d in devices.as_ref().into_iter().flatten() {
+                    let value = format!("{}:{} {}", d.major(), d.minor(), d.rate());
+                    writes.push(CgroupWrite::keyed(file.clone(), value, "0"));
                 }
             }
-            Subsystem::HugeTlb(ht_ctr) => {
-                // set the limit if "pagesize" hugetlb usage
-                if let Some(hp_limits) = resources.hugepage_limits() {
-                    for limit in hp_limits {
-                        ht_ctr
-                            .set_limit_in_bytes(limit.page_size().as_str(), limit.limit() as u64)
-                            .map_err(other_error!(e, "set huge page limit"))?;
-                    }
+        }
+    }
+
+    if let Some(limits) = resources
+        .hugepage_limits()
+        .as_ref()
+        .filter(|l| !l.is_empty())
+    {
+        if let Some(dir) = dir("hugetlb") {
+            for limit in limits {
+                writes.push(CgroupWrite::value(
+                    dir.join(format!("hugetlb.{}.limit_in_bytes", limit.page_size())),
+                    limit.limit(),
+                ));
+            }
+        }
+    }
+
+    if let Some(network) = resources.network() {
+        if let Some(class_id) = network.class_id() {
+            if let Some(dir) = dir("net_cls") {
+                writes.push(CgroupWrite::value(dir.join("net_cls.classid"), class_id));
+            }
+        }
+        if let Some(priorities) = network.priorities().as_ref().filter(|p| !p.is_empty()) {
+            if let Some(dir) = dir("net_prio") {
+                for p in priorities {
+                    writes.push(CgroupWrite::keyed(
+                        dir.join("net_prio.ifpriomap"),
+                        format!("{} {}", p.name(), p.priority()),
+                        "0",
+                    ));
                 }
             }
-            _ => {}
         }
     }
-    Ok(())
+
+    if resources.rdma().as_ref().map_or(false, |r| !r.is_empty()) {
+        if let Some(dir) = dir("rdma") {
+            writes.extend(rdma_writes(&dir.join("rdma.max"), resources));
+        }
+    }
+
+    if let Some(devices) = resources.devices().as_ref().filter(|d| !d.is_empty()) {
+        if let Some(dir) = dir("devices") {
+            for d in devices {
+                let id = |n: Option<i64>| n.map_or_else(|| "*".to_string(), |n| n.to_string());
+                let value = format!(
+                    "{} {}:{} {}",
+                    d.typ().unwrap_or_default().as_str(),
+                    id(d.major()),
+                    id(d.minor()),
+                    d.access().as_deref().unwrap_or("rwm"),
+                );
+                let file = if d.allow() {
+                    "devices.allow"
+                } else {
+                    "devices.deny"
+                };
+                writes.push(CgroupWrite {
+                    path: dir.join(file),
+                    value,
+                    restore: Restore::Devices,
+                });
+            }
+        }
+    }
+    Ok(writes)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::HashMap, fs, path::Path};
+
     use cgroups_rs::{hierarchies, Cgroup, CgroupPid};
+    use oci_spec::runtime::{
+        LinuxBlockIoBuilder, LinuxCpuBuilder, LinuxDeviceCgroupBuilder, LinuxDeviceType,
+        LinuxMemoryBuilder, LinuxNetworkBuilder, LinuxPidsBuilder, LinuxResourcesBuilder,
+        LinuxThrottleDeviceBuilder,
+    };
 
     use crate::cgroup::{
-        add_task_to_cgroup, adjust_oom_score, read_process_oom_score, OOM_SCORE_ADJ_MAX,
+        add_task_to_cgroup, adjust_oom_score, apply_writes, cgroup_v1_dirs, check_cgroup_procs,
+        collect_metrics_v1, collect_metrics_v2, parse_process_cgroups, read_process_oom_score,
+        resource_writes_v1, resource_writes_v2, v2_to_v1, CgroupWrite, Restore, OOM_SCORE_ADJ_MAX,
     };
 
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(dir).unwrap();
+        for (name, content) in files {
+            fs::write(dir.join(name), content).unwrap();
+        }
+    }
+
     #[test]
     fn test_add_cgroup() {
         let path = "runc_shim_test_cgroup";
@@ -256,4 +1467,471 @@ mod tests {
             assert_eq!(new, OOM_SCORE_ADJ_MAX)
         }
     }
+
+    #[test]
+    fn test_cgroup_v1_dirs() {
+        let paths = parse_process_cgroups(
+            "12:pids:/kubepods/pod1\n5:memory:/kubepods-burstable/pod1\n4:cpu,cpuacct:/kubepods/pod1\n1:name=systemd:/init.scope\n0::/\n",
+        );
+        assert_eq!(paths["cpu"], "/kubepods/pod1");
+        assert_eq!(paths["cpuacct"], "/kubepods/pod1");
+        assert_eq!(paths[""], "/");
+
+        let mountinfo = "\
+30 25 0:26 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid shared:10 - cgroup cgroup rw,cpu,cpuacct
+31 25 0:27 /kubepods /sys/fs/cgroup/pids rw,nosuid shared:11 - cgroup cgroup rw,pids
+33 25 0:29 /kubepods /sys/fs/cgroup/memory rw,nosuid shared:13 - cgroup cgroup rw,memory
+32 25 0:28 / /sys/fs/cgroup/unified rw,nosuid shared:12 - cgroup2 cgroup2 rw
+";
+        let dirs = cgroup_v1_dirs(mountinfo, &paths);
+        assert_eq!(
+            dirs["cpuacct"],
+            Path::new("/sys/fs/cgroup/cpu,cpuacct/kubepods/pod1")
+        );
+        assert_eq!(dirs["pids"], Path::new("/sys/fs/cgroup/pids/pod1"));
+        // a sibling of the mount root that shares its name as a prefix is not under it
+        assert_eq!(
+            dirs["memory"],
+            Path::new("/sys/fs/cgroup/memory/kubepods-burstable/pod1")
+        );
+        assert!(!dirs.contains_key("blkio"));
+    }
+
+    #[test]
+    fn test_collect_metrics_v2() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(
+            dir.path(),
+            &[
+                ("pids.current", "3\n"),
+                ("pids.max", "max\n"),
+                (
+                    "cpu.stat",
+                    "usage_usec 100\nuser_usec 60\nsystem_usec 40\nnr_periods 5\nnr_throttled 2\nthrottled_usec 7\n",
+                ),
+                (
+                    "memory.stat",
+                    "anon 4096\nfile 8192\ninactive_file 1024\npgfault 9\nworkingset_refault 2\n",
+                ),
+                ("memory.current", "12288\n"),
+                ("memory.max", "max\n"),
+                ("memory.events", "low 0\nhigh 1\nmax 2\noom 3\noom_kill 4\n"),
+                (
+                    "io.stat",
+                    "8:0 rbytes=10 wbytes=20 rios=1 wios=2 dbytes=0 dios=0\n",
+                ),
+                ("hugetlb.2MB.current", "0\n"),
+                ("hugetlb.2MB.max", "max\n"),
+                ("hugetlb.2MB.events", "max 0\n"),
+                ("rdma.current", "mlx4_0 hca_handle=2 hca_object=20\n"),
+                ("rdma.max", "mlx4_0 hca_handle=max hca_object=100\n"),
+            ],
+        );
+        let metrics = collect_metrics_v2(dir.path()).unwrap();
+        assert_eq!(metrics.pids.current, 3);
+        assert_eq!(metrics.pids.limit, u64::MAX);
+        assert_eq!(metrics.cpu.usage_usec, 100);
+        assert_eq!(metrics.cpu.throttled_usec, 7);
+        assert_eq!(metrics.memory.anon, 4096);
+        assert_eq!(metrics.memory.workingset_refault, 2);
+        assert_eq!(metrics.memory.usage, 12288);
+        assert_eq!(metrics.memory.usage_limit, u64::MAX);
+        assert_eq!(metrics.memory_events.oom_kill, 4);
+        assert_eq!(metrics.io.usage.len(), 1);
+        assert_eq!(metrics.io.usage[0].wbytes, 20);
+        assert_eq!(metrics.hugetlb.len(), 1);
+        assert_eq!(metrics.hugetlb[0].pagesize, "2MB");
+        assert_eq!(metrics.hugetlb[0].max, u64::MAX);
+        assert_eq!(metrics.rdma.current[0].hca_objects, 20);
+        assert_eq!(metrics.rdma.limit[0].hca_handles, u32::MAX);
+
+        let v1 = v2_to_v1(&metrics);
+        assert_eq!(v1.cpu.usage.total, 100_000);
+        assert_eq!(v1.memory.rss, 4096);
+        assert_eq!(v1.memory.total_inactive_file, 1024);
+        assert_eq!(v1.blkio.io_service_bytes_recursive.len(), 2);
+
+        assert!(collect_metrics_v2(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_check_cgroup_procs() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_cgroup_procs(dir.path(), 42).is_err());
+        write_files(dir.path(), &[("cgroup.procs", "1\n420\n42\n")]);
+        check_cgroup_procs(dir.path(), 42).unwrap();
+        assert!(check_cgroup_procs(dir.path(), 4).is_err());
+    }
+
+    #[test]
+    fn test_collect_metrics_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = dir.path().join("cpu,cpuacct");
+        write_files(
+            &cpu,
+            &[
+                ("cpuacct.usage", "1000\n"),
+                ("cpuacct.usage_percpu", "600 400 \n"),
+                ("cpuacct.stat", "user 1\nsystem 2\n"),
+                (
+                    "cpu.stat",
+                    "nr_periods 5\nnr_throttled 2\nthrottled_time 7\n",
+                ),
+            ],
+        );
+        let memory = dir.path().join("memory");
+        write_files(
+            &memory,
+            &[
+                (
+                    "memory.stat",
+                    "cache 10\nrss 20\ntotal_inactive_file 30\npgpgin 4\n",
+                ),
+                ("memory.usage_in_bytes", "100\n"),
+                ("memory.limit_in_bytes", "9223372036854771712\n"),
+                ("memory.failcnt", "1\n"),
+                (
+                    "memory.oom_control",
+                    "oom_kill_disable 0\nunder_oom 0\noom_kill 3\n",
+                ),
+            ],
+        );
+        let blkio = dir.path().join("blkio");
+        write_files(
+            &blkio,
+            &[
+                ("blkio.io_service_bytes_recursive", "Total 0\n"),
+                (
+                    "blkio.throttle.io_service_bytes",
+                    "8:0 Read 10\n8:0 Write 20\nTotal 30\n",
+                ),
+                ("blkio.time_recursive", "8:0 5\n"),
+            ],
+        );
+        let hugetlb = dir.path().join("hugetlb");
+        write_files(
+            &hugetlb,
+            &[
+                ("hugetlb.1GB.usage_in_bytes", "0\n"),
+                ("hugetlb.2MB.usage_in_bytes", "2097152\n"),
+                ("hugetlb.2MB.failcnt", "1\n"),
+            ],
+        );
+        let dirs: HashMap<_, _> = vec![
+            ("cpu", cpu.clone()),
+            ("cpuacct", cpu),
+            ("memory", memory),
+            ("blkio", blkio),
+            ("hugetlb", hugetlb),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+
+        let metrics = collect_metrics_v1(&dirs);
+        assert_eq!(metrics.cpu.usage.total, 1000);
+        assert_eq!(metrics.cpu.usage.per_cpu, vec![600, 400]);
+        assert!(metrics.cpu.usage.user > 0);
+        assert_eq!(metrics.cpu.throttling.throttled_time, 7);
+        assert_eq!(metrics.memory.rss, 20);
+        assert_eq!(metrics.memory.pg_pg_in, 4);
+        assert_eq!(metrics.memory.total_inactive_file, 30);
+        assert_eq!(metrics.memory.usage.usage, 100);
+        assert_eq!(metrics.memory.usage.failcnt, 1);
+        assert!(metrics.memory.swap.is_none());
+        assert_eq!(metrics.memory_oom_control.oom_kill, 3);
+        let bytes = &metrics.blkio.io_service_bytes_recursive;
+        assert_eq!(bytes.len(), 2);
+        assert_eq!((bytes[1].op.as_str(), bytes[1].value), ("Write", 20));
+        assert_eq!(metrics.blkio.io_time_recursive[0].value, 5);
+        let pagesizes: Vec<_> = metrics
+            .hugetlb
+            .iter()
+            .map(|h| h.pagesize.as_str())
+            .collect();
+        assert_eq!(pagesizes, vec!["1GB", "2MB"]);
+        assert_eq!(metrics.hugetlb[1].failcnt, 1);
+        assert!(metrics.pids.is_none());
+    }
+
+    #[test]
+    fn test_resource_writes_v2() {
+        let dir = tempfile::tempdir().unwrap();
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1000)
+                    .swap(3000)
+                    .build()
+                    .unwrap(),
+            )
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .shares(1024u64)
+                    .quota(50000)
+                    .period(100000u64)
+                    .build()
+                    .unwrap(),
+            )
+            .pids(LinuxPidsBuilder::default().limit(-1).build().unwrap())
+            .block_io(
+                LinuxBlockIoBuilder::default()
+                    .weight(500u16)
+                    .throttle_read_bps_device(vec![LinuxThrottleDeviceBuilder::default()
+                        .major(8)
+                        .minor(0)
+                        .rate(1024u64)
+                        .build()
+                        .unwrap()])
+                    .build()
+                    .unwrap(),
+            )
+            .unified(
+                vec![("memory.high".to_string(), "900".to_string())]
+                    .into_iter()
+                    .collect::<HashMap<_, _>>(),
+            )
+            .build()
+            .unwrap();
+
+        let writes: Vec<_> = resource_writes_v2(dir.path(), &resources)
+            .unwrap()
+            .into_iter()
+            .map(|w| {
+                let name = w.path.strip_prefix(dir.path()).unwrap();
+                (name.to_str().unwrap().to_string(), w.value)
+            })
+            .collect();
+        let expected = vec![
+            ("memory.max", "1000"),
+            ("memory.swap.max", "2000"),
+            ("cpu.weight", "39"),
+            ("cpu.max", "50000 100000"),
+            ("pids.max", "max"),
+            ("io.weight", "default 4950"),
+            ("io.max", "8:0 rbps=1024"),
+            ("memory.high", "900"),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(writes, expected);
+
+        // a zero pids limit is left unchanged
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(0).build().unwrap())
+            .build()
+            .unwrap();
+        assert!(resource_writes_v2(dir.path(), &resources)
+            .unwrap()
+            .is_empty());
+
+        // the memory+swap limit can't be less than the memory limit
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1000)
+                    .swap(500)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(resource_writes_v2(dir.path(), &resources).is_err());
+
+        // realtime scheduling has no cgroup v2 equivalent
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .realtime_runtime(1000)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(resource_writes_v2(dir.path(), &resources).is_err());
+
+        // and neither have kernel memory, swappiness, the oom killer, network and devices
+        let unsupported = vec![
+            LinuxResourcesBuilder::default()
+                .memory(LinuxMemoryBuilder::default().kernel(1000).build().unwrap()),
+            LinuxResourcesBuilder::default().memory(
+                LinuxMemoryBuilder::default()
+                    .swappiness(10u64)
+                    .build()
+                    .unwrap(),
+            ),
+            LinuxResourcesBuilder::default().memory(
+                LinuxMemoryBuilder::default()
+                    .disable_oom_killer(true)
+                    .build()
+                    .unwrap(),
+            ),
+            LinuxResourcesBuilder::default().network(
+                LinuxNetworkBuilder::default()
+                    .class_id(1u32)
+                    .build()
+                    .unwrap(),
+            ),
+            LinuxResourcesBuilder::default().devices(vec![LinuxDeviceCgroupBuilder::default()
+                .allow(false)
+                .build()
+                .unwrap()]),
+        ];
+        for resources in unsupported {
+            let resources = resources.build().unwrap();
+            assert!(resource_writes_v2(dir.path(), &resources).is_err());
+        }
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .disable_oom_killer(false)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(resource_writes_v2(dir.path(), &resources)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_resource_writes_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = dir.path().join("memory");
+        write_files(&memory, &[("memory.limit_in_bytes", "1000\n")]);
+        let dirs: HashMap<_, _> = vec![("memory".to_string(), memory.clone())]
+            .into_iter()
+            .collect();
+
+        // raising the limits writes memory+swap first
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(2000)
+                    .swap(4000)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let writes = resource_writes_v1(&dirs, &resources).unwrap();
+        assert_eq!(
+            writes,
+            vec![
+                CgroupWrite::value(memory.join("memory.memsw.limit_in_bytes"), 4000),
+                CgroupWrite::value(memory.join("memory.limit_in_bytes"), 2000),
+            ]
+        );
+
+        // lowering them writes memory first
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(500)
+                    .swap(800)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let writes = resource_writes_v1(&dirs, &resources).unwrap();
+        assert_eq!(writes[0].path, memory.join("memory.limit_in_bytes"));
+
+        // controllers that are not mounted are skipped
+        let devices = vec![
+            LinuxDeviceCgroupBuilder::default()
+                .allow(false)
+                .access("rwm")
+                .build()
+                .unwrap(),
+            LinuxDeviceCgroupBuilder::default()
+                .allow(true)
+                .typ(LinuxDeviceType::C)
+                .major(1)
+                .minor(3)
+                .access("rwm")
+                .build()
+                .unwrap(),
+        ];
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(10).build().unwrap())
+            .devices(devices)
+            .build()
+            .unwrap();
+        assert!(resource_writes_v1(&dirs, &resources).unwrap().is_empty());
+
+        // device rules are written in order
+        let devices = dir.path().join("devices");
+        let dirs: HashMap<_, _> = vec![("devices".to_string(), devices.clone())]
+            .into_iter()
+            .collect();
+        let writes = resource_writes_v1(&dirs, &resources).unwrap();
+        let device_write = |file: &str, value: &str| CgroupWrite {
+            path: devices.join(file),
+            value: value.to_string(),
+            restore: Restore::Devices,
+        };
+        assert_eq!(
+            writes,
+            vec![
+                device_write("devices.deny", "a *:* rwm"),
+                device_write("devices.allow", "c 1:3 rwm"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_writes_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(
+            dir.path(),
+            &[
+                ("memory.max", "100\n"),
+                ("io.max", "8:0 rbps=1 wbps=max riops=max wiops=max\n"),
+            ],
+        );
+        let writes = vec![
+            CgroupWrite::value(dir.path().join("memory.max"), 200),
+            CgroupWrite::keyed(
+                dir.path().join("io.max"),
+                "8:16 rbps=5",
+                "rbps=max wbps=max riops=max wiops=max",
+            ),
+            CgroupWrite::value(dir.path().join("missing").join("pids.max"), 10),
+        ];
+        assert!(apply_writes(&writes).is_err());
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("memory.max"), "100");
+        assert_eq!(read("io.max"), "8:16 rbps=max wbps=max riops=max wiops=max");
+
+        apply_writes(&writes[..2]).unwrap();
+        assert_eq!(read("memory.max"), "200");
+        assert_eq!(read("io.max"), "8:16 rbps=5");
+    }
+
+    #[test]
+    fn test_apply_writes_rollback_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("devices.list", "c 1:3 rwm\nc 1:5 rwm\n")]);
+        let device_write = |file: &str, value: &str| CgroupWrite {
+            path: dir.path().join(file),
+            value: value.to_string(),
+            restore: Restore::Devices,
+        };
+        let writes = vec![
+            device_write("devices.deny", "a"),
+            device_write("devices.allow", "c 1:3 rwm"),
+            CgroupWrite::value(dir.path().join("missing").join("pids.max"), 10),
+        ];
+        assert!(apply_writes(&writes).is_err());
+
+        // the rules are restored once, after denying all devices
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("devices.deny"), "a");
+        assert_eq!(read("devices.allow"), "c 1:5 rwm");
+    }
 }
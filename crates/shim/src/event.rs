@@ -1,4 +1,13 @@
-use containerd_shim_protos::{events::task::*, protobuf::MessageDyn};
+use std::{collections::VecDeque, fmt::Display, time::Duration};
+
+use containerd_shim_protos::{
+    events::task::*,
+    protobuf::{Message, MessageDyn},
+    shim::events::ForwardRequest,
+    topics::TASK_EXIT_EVENT_TOPIC,
+    ttrpc::context::Context,
+};
+use log::{error, warn};
 
 pub trait Event: MessageDyn {
     fn topic(&self) -> String;
@@ -63,3 +72,166 @@ impl Event for TaskCheckpointed {
         "/tasks/checkpointed".to_string()
     }
 }
+
+/// Capacity of the queue of events pending delivery to containerd.
+pub const EVENT_QUEUE_CAPACITY: usize = 1024;
+
+/// Timeout of a delivery attempt when the context of the event has none.
+pub(crate) const FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// First delay before reconnecting to containerd, doubled on each failure.
+pub(crate) const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+
+/// Longest delay before reconnecting to containerd.
+pub(crate) const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+/// How long the shim waits for the pending events to be delivered when it exits.
+pub(crate) const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Log an event dropped before it was delivered, as an error for a `TaskExit`.
+pub(crate) fn log_undelivered(req: &ForwardRequest, reason: &dyn Display) {
+    let envelope = req.envelope();
+    if envelope.topic() != TASK_EXIT_EVENT_TOPIC {
+        warn!("drop {} event: {}", envelope.topic(), reason);
+        return;
+    }
+    match TaskExit::parse_from_bytes(&envelope.event().value) {
+        Ok(exit) => error!(
+            "drop exit event of container {} (pid {}, exit status {}): {}",
+            exit.container_id, exit.pid, exit.exit_status, reason
+        ),
+        Err(_) => error!("drop {} event: {}", envelope.topic(), reason),
+    }
+}
+
+/// Events pending delivery to containerd, in the order they are published.
+///
+/// When the queue is full, the oldest event that is not a `TaskExit` is dropped with a warning,
+/// so exit events are kept even above the capacity.
+pub(crate) struct EventQueue {
+    events: VecDeque<(Context, ForwardRequest)>,
+    capacity: usize,
+    in_flight: Option<ForwardRequest>,
+}
+
+impl EventQueue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity,
+            in_flight: None,
+        }
+    }
+
+    pub(crate) fn push(&mut self, mut ctx: Context, req: ForwardRequest) {
+        if ctx.timeout_nano == 0 {
+            ctx.timeout_nano = FORWARD_TIMEOUT.as_nanos() as i64;
+        }
+        self.events.push_back((ctx, req));
+        if self.events.len() > self.capacity {
+            let oldest = self
+                .events
+                .iter()
+                .position(|(_, r)| r.envelope().topic() != TASK_EXIT_EVENT_TOPIC);
+            if let Some((_, dropped)) = oldest.and_then(|i| self.events.remove(i)) {
+                warn!(
+                    "event queue is full, drop {} event",
+                    dropped.envelope().topic()
+                );
+            }
+        }
+    }
+
+    /// Take the next event to deliver, which stays in flight until [EventQueue::delivered].
+    pub(crate) fn pop(&mut self) -> Option<(Context, ForwardRequest)> {
+        let event = self.events.pop_front();
+        self.in_flight = event.as_ref().map(|(_, req)| req.clone());
+        event
+    }
+
+    /// The event in flight is delivered or dropped.
+    pub(crate) fn delivered(&mut self) {
+        self.in_flight = None;
+    }
+
+    /// Drop the event in flight and the queued ones, logging each.
+    pub(crate) fn drop_pending(&mut self, reason: &dyn Display) {
+        if let Some(req) = self.in_flight.take() {
+            log_undelivered(&req, reason);
+        }
+        while let Some((_, req)) = self.events.pop_front() {
+            log_undelivered(&req, reason);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.events.len()
+    }
+}
+
+/// The delay before the next reconnection after `backoff`.
+pub(crate) fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(RECONNECT_BACKOFF_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_shim_protos::shim::events::Envelope;
+
+    use super::*;
+
+    fn request(topic: &str) -> ForwardRequest {
+        let mut envelope = Envelope::new();
+        envelope.set_topic(topic.to_string());
+        let mut req = ForwardRequest::new();
+        req.set_envelope(envelope);
+        req
+    }
+
+    #[test]
+    fn test_event_queue_keeps_exits() {
+        let mut queue = EventQueue::new(2);
+        queue.push(Context::default(), request("/tasks/exit"));
+        queue.push(Context::default(), request("/tasks/oom"));
+        queue.push(Context::default(), request("/tasks/exit"));
+        queue.push(Context::default(), request("/tasks/exit"));
+        assert_eq!(queue.len(), 3);
+
+        let (ctx, _) = queue.pop().unwrap();
+        assert_eq!(ctx.timeout_nano, FORWARD_TIMEOUT.as_nanos() as i64);
+        let topics: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(_, r)| r.envelope().topic().to_string())
+            .collect();
+        assert_eq!(topics, vec!["/tasks/exit", "/tasks/exit"]);
+    }
+
+    #[test]
+    fn test_event_queue_drops_oldest() {
+        let mut queue = EventQueue::new(2);
+        queue.push(Context::default(), request("/tasks/create"));
+        queue.push(Context::default(), request("/tasks/start"));
+        queue.push(Context::default(), request("/tasks/oom"));
+        let topics: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(_, r)| r.envelope().topic().to_string())
+            .collect();
+        assert_eq!(topics, vec!["/tasks/start", "/tasks/oom"]);
+        assert_eq!(next_backoff(RECONNECT_BACKOFF_MAX), RECONNECT_BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_event_queue_drop_pending() {
+        let mut queue = EventQueue::new(2);
+        queue.push(Context::default(), request("/tasks/exit"));
+        queue.push(Context::default(), request("/tasks/oom"));
+        queue.pop().unwrap();
+        assert!(queue.in_flight.is_some());
+        queue.drop_pending(&"shim exits");
+        assert!(queue.in_flight.is_none());
+        assert_eq!(queue.len(), 0);
+
+        queue.push(Context::default(), request("/tasks/exit"));
+        queue.pop().unwrap();
+        queue.delivered();
+        assert!(queue.in_flight.is_none());
+    }
+}
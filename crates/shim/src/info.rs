@@ -0,0 +1,98 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Runtime info of the shim, printed by the `-info` and `-v` flags.
+//!
+//! containerd writes the runtime options as a protobuf `Any` to the stdin of `-info`, and reads
+//! the [RuntimeInfo] of the shim as protobuf from its stdout.
+
+use std::path::Path;
+
+use crate::{
+    error::Result,
+    protos::{
+        protobuf::{well_known_types::any::Any, Message},
+        types::introspection::{RuntimeInfo, RuntimeVersion},
+    },
+};
+
+/// Parse the runtime options passed to `-info`, `None` when containerd sent none.
+pub fn read_options(data: &[u8]) -> Result<Option<Any>> {
+    if data.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Any::parse_from_bytes(data)?))
+}
+
+/// The default info of a shim: the runtime id, the version of this crate and the options.
+pub fn default_info(runtime_id: &str, options: Option<Any>) -> RuntimeInfo {
+    let mut version = RuntimeVersion::new();
+    version.set_version(env!("CARGO_PKG_VERSION").to_string());
+
+    let mut info = RuntimeInfo::new();
+    info.set_name(runtime_id.to_string());
+    info.set_version(version);
+    if let Some(options) = options {
+        info.set_options(options);
+    }
+    info
+}
+
+/// The output of `-v` for the shim binary at `argv0`.
+pub(crate) fn version_output(argv0: &str, info: &RuntimeInfo) -> String {
+    let binary = Path::new(argv0)
+        .file_name()
+        .map_or(argv0.into(), |name| name.to_string_lossy());
+    format!(
+        "{}:\n  Runtime:  {}\n  Version:  {}\n  Revision: {}\n",
+        binary,
+        info.name(),
+        info.version().version(),
+        info.version().revision(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_info() {
+        assert!(read_options(&[]).unwrap().is_none());
+
+        let mut any = Any::new();
+        any.type_url = "containerd.runc.v1.Options".to_string();
+        any.value = vec![0x0a, 0x01, 0x61];
+        let options = read_options(&any.write_to_bytes().unwrap()).unwrap();
+        assert_eq!(options.as_ref(), Some(&any));
+
+        let info = default_info("io.containerd.runc.v2", options);
+        let info = RuntimeInfo::parse_from_bytes(&info.write_to_bytes().unwrap()).unwrap();
+        assert_eq!(info.name(), "io.containerd.runc.v2");
+        assert_eq!(info.version().version(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.options(), &any);
+        assert!(!info.has_features());
+    }
+
+    #[test]
+    fn test_version_output() {
+        let info = default_info("io.containerd.runc.v2", None);
+        let output = version_output("/usr/bin/containerd-shim-runc-v2", &info);
+        assert!(output.starts_with("containerd-shim-runc-v2:\n"));
+        assert!(output.contains("  Runtime:  io.containerd.runc.v2\n"));
+        assert!(output.contains(&format!("  Version:  {}\n", env!("CARGO_PKG_VERSION"))));
+    }
+}
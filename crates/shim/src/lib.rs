@@ -33,11 +33,8 @@
 //!
 
 use std::{
-    collections::hash_map::DefaultHasher,
-    fs::File,
-    hash::Hasher,
-    os::unix::{io::RawFd, net::UnixListener},
-    path::{Path, PathBuf},
+    collections::hash_map::DefaultHasher, fs::File, hash::Hasher, os::unix::io::RawFd,
+    path::PathBuf,
 };
 
 pub use containerd_shim_protos as protos;
@@ -59,12 +56,16 @@ pub mod error;
 mod args;
 #[cfg(feature = "async")]
 pub mod asynchronous;
+pub mod bootstrap;
 pub mod cgroup;
 pub mod event;
+pub mod info;
 mod logger;
 pub mod monitor;
 pub mod mount;
+pub mod oom;
 mod reap;
+pub mod socket;
 #[cfg(not(feature = "async"))]
 pub mod synchronous;
 pub mod util;
@@ -144,6 +145,10 @@ pub struct StartOpts {
     pub namespace: String,
 
     pub debug: bool,
+    /// Bootstrap params containerd 2.0 sends on stdin, `None` for older containerd.
+    ///
+    /// The output of `start` is built from them with [bootstrap::start_response].
+    pub bootstrap: Option<bootstrap::BootstrapParams>,
 }
 
 /// The shim process communicates with the containerd server through a communication channel
@@ -174,25 +179,8 @@ pub fn socket_address(socket_path: &str, namespace: &str, id: &str) -> String {
     format!("unix://{}/{:x}.sock", SOCKET_ROOT, hash)
 }
 
-fn parse_sockaddr(addr: &str) -> &str {
-    if let Some(addr) = addr.strip_prefix("unix://") {
-        return addr;
-    }
-
-    if let Some(addr) = addr.strip_prefix("vsock://") {
-        return addr;
-    }
-
-    addr
-}
-
-fn start_listener(address: &str) -> std::io::Result<UnixListener> {
-    let path = parse_sockaddr(address);
-    // Try to create the needed directory hierarchy.
-    if let Some(parent) = Path::new(path).parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    UnixListener::bind(path)
+fn start_listener(address: &str) -> std::io::Result<socket::Listener> {
+    socket::listen(address)
 }
 
 pub struct Console {
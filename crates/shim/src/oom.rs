@@ -0,0 +1,408 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+#![cfg(target_os = "linux")]
+
+//! Watch the memory cgroups of containers for OOM kills.
+//!
+//! On cgroup v1 an eventfd is registered for `memory.oom_control` in `cgroup.event_control`.
+//! On cgroup v2 `memory.events` is watched with inotify, and an OOM is reported when its
+//! `oom_kill` counter grows. The IDs of the OOM killed containers are received from the
+//! [OomReceiver] returned with the watcher, to be published as `TaskOOM` events.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use log::{debug, error, warn};
+use nix::{
+    errno::Errno,
+    sys::{
+        epoll::{
+            epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+        },
+        eventfd::{eventfd, EfdFlags},
+        inotify::{AddWatchFlags, InitFlags, Inotify},
+    },
+    unistd::close,
+};
+
+use crate::{
+    cgroup::memory_cgroup_dir,
+    error::{Error, Result},
+};
+
+/// Receiver of the IDs of the OOM killed containers.
+#[cfg(not(feature = "async"))]
+pub type OomReceiver = std::sync::mpsc::Receiver<String>;
+/// Receiver of the IDs of the OOM killed containers.
+#[cfg(feature = "async")]
+pub type OomReceiver = tokio::sync::mpsc::UnboundedReceiver<String>;
+
+#[cfg(not(feature = "async"))]
+fn oom_channel() -> (impl Fn(String) -> bool + Send + 'static, OomReceiver) {
+    let (tx, rx) = std::sync::mpsc::channel();
+    (move |id| tx.send(id).is_ok(), rx)
+}
+
+#[cfg(feature = "async")]
+fn oom_channel() -> (impl Fn(String) -> bool + Send + 'static, OomReceiver) {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    (move |id| tx.send(id).is_ok(), rx)
+}
+
+/// How the OOM kills of a cgroup are notified.
+enum Source {
+    /// An eventfd registered for `memory.oom_control` of a cgroup v1 directory.
+    EventFd { file: File, dir: PathBuf },
+    /// An inotify watch of `memory.events` of a cgroup v2 directory.
+    Inotify {
+        inotify: Inotify,
+        events: PathBuf,
+        oom_kill: u64,
+    },
+}
+
+struct Target {
+    id: String,
+    source: Source,
+}
+
+impl Target {
+    fn fd(&self) -> RawFd {
+        match &self.source {
+            Source::EventFd { file, .. } => file.as_raw_fd(),
+            Source::Inotify { inotify, .. } => inotify.as_raw_fd(),
+        }
+    }
+
+    /// Handle a notification, returning whether the container was OOM killed and whether its
+    /// cgroup was removed.
+    fn check(&mut self) -> (bool, bool) {
+        match &mut self.source {
+            Source::EventFd { file, dir } => {
+                let mut buf = [0u8; 8];
+                if let Err(e) = file.read_exact(&mut buf) {
+                    warn!("failed to read oom eventfd of {}: {}", self.id, e);
+                }
+                // the eventfd is also notified when the cgroup is removed
+                if !dir.join("cgroup.event_control").exists() {
+                    return (false, true);
+                }
+                (true, false)
+            }
+            Source::Inotify {
+                inotify,
+                events,
+                oom_kill,
+            } => {
+                let gone = match inotify.read_events() {
+                    Ok(events) => events.iter().any(|e| {
+                        e.mask
+                            .intersects(AddWatchFlags::IN_IGNORED | AddWatchFlags::IN_DELETE_SELF)
+                    }),
+                    Err(Errno::EAGAIN) => false,
+                    Err(e) => {
+                        warn!("failed to read inotify events of {}: {}", self.id, e);
+                        false
+                    }
+                };
+                let count = read_oom_kill(events);
+                let oom = count > *oom_kill;
+                *oom_kill = count;
+                (oom, gone)
+            }
+        }
+    }
+}
+
+impl Drop for Target {
+    fn drop(&mut self) {
+        // the eventfd is closed with its file
+        if let Source::Inotify { inotify, .. } = &self.source {
+            close(inotify.as_raw_fd()).unwrap_or_else(|e| warn!("failed to close inotify: {}", e));
+        }
+    }
+}
+
+/// The `oom_kill` counter of a cgroup v2 `memory.events`.
+fn read_oom_kill(path: &Path) -> u64 {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .find(|(k, _)| *k == "oom_kill")
+        .and_then(|(_, v)| v.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+type Targets = Arc<Mutex<HashMap<RawFd, Target>>>;
+
+/// Watches the memory cgroups of containers for OOM kills.
+///
+/// A thread waits for the notifications of all the watched cgroups, and stops when the watcher
+/// is dropped or the [OomReceiver] is closed. A cgroup stops being watched when it is removed.
+pub struct OomWatcher {
+    epoll: RawFd,
+    stop: File,
+    targets: Targets,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OomWatcher {
+    /// Start a watcher, returning it with the receiver of the OOM killed container IDs.
+    pub fn new() -> Result<(Self, OomReceiver)> {
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let stop = match eventfd(0, EfdFlags::EFD_CLOEXEC) {
+            Ok(fd) => unsafe { File::from_raw_fd(fd) },
+            Err(e) => {
+                let _ = close(epoll);
+                return Err(e.into());
+            }
+        };
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, stop.as_raw_fd() as u64);
+        if let Err(e) = epoll_ctl(epoll, EpollOp::EpollCtlAdd, stop.as_raw_fd(), &mut event) {
+            let _ = close(epoll);
+            return Err(e.into());
+        }
+
+        let targets = Targets::default();
+        let (send, rx) = oom_channel();
+        let thread = {
+            let targets = targets.clone();
+            let stop = stop.as_raw_fd();
+            thread::Builder::new()
+                .name("shim-oom-watcher".to_string())
+                .spawn(move || run(epoll, stop, targets, send))
+                .map_err(io_error!(e, "spawn oom watcher"))?
+        };
+        Ok((
+            Self {
+                epoll,
+                stop,
+                targets,
+                thread: Some(thread),
+            },
+            rx,
+        ))
+    }
+
+    /// Watch the memory cgroup of the process `pid`, reporting its OOM kills as container `id`.
+    pub fn add(&self, id: &str, pid: u32) -> Result<()> {
+        match memory_cgroup_dir(pid)? {
+            (dir, true) => self.add_cgroup_v2(id, &dir),
+            (dir, false) => self.add_cgroup_v1(id, &dir),
+        }
+    }
+
+    /// Watch the cgroup v1 memory directory `dir` of container `id`.
+    pub fn add_cgroup_v1(&self, id: &str, dir: &Path) -> Result<()> {
+        let fd = eventfd(0, EfdFlags::EFD_CLOEXEC)?;
+        let file = unsafe { File::from_raw_fd(fd) };
+        let oom_control = File::open(dir.join("memory.oom_control")).map_err(io_error!(
+            e,
+            "open memory.oom_control of {}",
+            id
+        ))?;
+        OpenOptions::new()
+            .write(true)
+            .open(dir.join("cgroup.event_control"))
+            .and_then(|mut f| write!(f, "{} {}", fd, oom_control.as_raw_fd()))
+            .map_err(io_error!(e, "register oom eventfd of {}", id))?;
+        self.register(Target {
+            id: id.to_string(),
+            source: Source::EventFd {
+                file,
+                dir: dir.to_path_buf(),
+            },
+        })
+    }
+
+    /// Watch the cgroup v2 directory `dir` of container `id`.
+    pub fn add_cgroup_v2(&self, id: &str, dir: &Path) -> Result<()> {
+        let events = dir.join("memory.events");
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let target = Target {
+            id: id.to_string(),
+            source: Source::Inotify {
+                inotify,
+                oom_kill: read_oom_kill(&events),
+                events: events.clone(),
+            },
+        };
+        inotify.add_watch(&events, AddWatchFlags::IN_MODIFY)?;
+        self.register(target)
+    }
+
+    /// Stop watching the cgroups of container `id`.
+    pub fn remove(&self, id: &str) {
+        let mut targets = self.targets.lock().unwrap();
+        let fds: Vec<_> = targets
+            .iter()
+            .filter(|(_, t)| t.id == id)
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in fds {
+            unregister(self.epoll, &mut targets, fd);
+        }
+    }
+
+    fn register(&self, target: Target) -> Result<()> {
+        let fd = target.fd();
+        let mut targets = self.targets.lock().unwrap();
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, fd as u64);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlAdd, fd, &mut event)?;
+        targets.insert(fd, target);
+        Ok(())
+    }
+}
+
+impl Drop for OomWatcher {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop.write_all(&1u64.to_ne_bytes()) {
+            error!("failed to stop oom watcher: {}", e);
+        } else if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.targets.lock().unwrap().clear();
+        let _ = close(self.epoll);
+    }
+}
+
+fn unregister(epoll: RawFd, targets: &mut HashMap<RawFd, Target>, fd: RawFd) {
+    if let Err(e) = epoll_ctl(epoll, EpollOp::EpollCtlDel, fd, None) {
+        warn!("failed to remove fd {} from epoll: {}", fd, e);
+    }
+    targets.remove(&fd);
+}
+
+fn run(epoll: RawFd, stop: RawFd, targets: Targets, send: impl Fn(String) -> bool) {
+    let mut events = vec![EpollEvent::empty(); 16];
+    loop {
+        let n = match epoll_wait(epoll, &mut events, -1) {
+            Ok(n) => n,
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                error!("failed to wait for oom events: {}", e);
+                return;
+            }
+        };
+        for event in &events[..n] {
+            let fd = event.data() as RawFd;
+            if fd == stop {
+                return;
+            }
+            let mut targets = targets.lock().unwrap();
+            let (id, (oom, gone)) = match targets.get_mut(&fd) {
+                Some(target) => (target.id.clone(), target.check()),
+                None => continue,
+            };
+            if gone {
+                debug!("cgroup of {} is removed, stop watching oom", id);
+                unregister(epoll, &mut targets, fd);
+            }
+            if oom && !send(id) {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    use super::OomWatcher;
+
+    /// A fake cgroup v2 directory, on tmpfs when it is available like cgroupfs files.
+    fn fake_cgroup() -> tempfile::TempDir {
+        let shm = Path::new("/dev/shm");
+        let dir = if shm.is_dir() {
+            tempfile::tempdir_in(shm).unwrap()
+        } else {
+            tempfile::tempdir().unwrap()
+        };
+        fs::write(
+            dir.path().join("memory.events"),
+            "low 0\nhigh 0\nmax 0\noom 0\noom_kill 1\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn recv(rx: &mut super::OomReceiver, timeout: Duration) -> Option<String> {
+        rx.recv_timeout(timeout).ok()
+    }
+
+    #[cfg(feature = "async")]
+    fn recv(rx: &mut super::OomReceiver, timeout: Duration) -> Option<String> {
+        use tokio::sync::mpsc::error::TryRecvError;
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match rx.try_recv() {
+                Ok(id) => return Some(id),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_oom_watcher_v2() {
+        let dir = fake_cgroup();
+        let events = dir.path().join("memory.events");
+        let (watcher, mut rx) = OomWatcher::new().unwrap();
+        watcher.add_cgroup_v2("c1", dir.path()).unwrap();
+
+        // changes of other counters are not an oom
+        fs::write(&events, "low 0\nhigh 1\nmax 1\noom 0\noom_kill 1\n").unwrap();
+        fs::write(&events, "low 0\nhigh 1\nmax 1\noom 1\noom_kill 2\n").unwrap();
+        assert_eq!(recv(&mut rx, Duration::from_secs(5)).as_deref(), Some("c1"));
+
+        watcher.remove("c1");
+        fs::write(&events, "low 0\nhigh 1\nmax 1\noom 2\noom_kill 3\n").unwrap();
+        assert_eq!(recv(&mut rx, Duration::from_millis(200)), None);
+    }
+
+    #[test]
+    fn test_oom_watcher_removed_cgroup() {
+        let dir = fake_cgroup();
+        let (watcher, mut rx) = OomWatcher::new().unwrap();
+        watcher.add_cgroup_v2("c1", dir.path()).unwrap();
+
+        fs::remove_file(dir.path().join("memory.events")).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !watcher.targets.lock().unwrap().is_empty() {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(watcher);
+        assert_eq!(recv(&mut rx, Duration::from_secs(5)), None);
+    }
+}
@@ -0,0 +1,305 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Socket addresses of the shim and containerd.
+//!
+//! An address is a unix socket path, optionally prefixed with `unix://`, an abstract unix
+//! socket as `unix://@name`, or a vsock as `vsock://cid:port`. Abstract sockets and vsock are
+//! only supported on Linux.
+
+use std::{
+    fmt,
+    os::unix::{
+        io::{AsRawFd, IntoRawFd, RawFd},
+        net::UnixListener,
+    },
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    sys::socket::{self, AddressFamily, SockFlag, SockType, SockaddrLike, UnixAddr},
+    unistd::close,
+};
+
+use crate::error::{Error, Result};
+
+/// Backlog of the listening sockets, the same as [UnixListener].
+const LISTEN_BACKLOG: usize = 128;
+
+/// A parsed socket address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketAddress {
+    /// Unix socket at a path.
+    Unix(PathBuf),
+    /// Abstract unix socket with a name.
+    Abstract(String),
+    /// vsock with a context ID and a port.
+    Vsock { cid: u32, port: u32 },
+}
+
+impl SocketAddress {
+    /// Parse `unix:///path`, `unix://@name`, `vsock://cid:port` or a bare path.
+    pub fn parse(address: &str) -> Result<Self> {
+        if let Some(addr) = address.strip_prefix("vsock://") {
+            let (cid, port) = addr
+                .split_once(':')
+                .ok_or_else(|| other!("invalid vsock address {}", address))?;
+            let cid = cid
+                .parse::<u32>()
+                .map_err(other_error!(e, "invalid vsock cid"))?;
+            let port = port
+                .parse::<u32>()
+                .map_err(other_error!(e, "invalid vsock port"))?;
+            return Ok(SocketAddress::Vsock { cid, port });
+        }
+        let addr = address.strip_prefix("unix://").unwrap_or(address);
+        match addr.strip_prefix('@') {
+            Some(name) => Ok(SocketAddress::Abstract(name.to_string())),
+            None => Ok(SocketAddress::Unix(PathBuf::from(addr))),
+        }
+    }
+
+    /// Path of a unix socket on the filesystem, `None` for the other addresses.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            SocketAddress::Unix(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketAddress::Unix(path) => write!(f, "unix://{}", path.display()),
+            SocketAddress::Abstract(name) => write!(f, "unix://@{}", name),
+            SocketAddress::Vsock { cid, port } => write!(f, "vsock://{}:{}", cid, port),
+        }
+    }
+}
+
+/// A listening socket, closed when dropped.
+#[derive(Debug)]
+pub struct Listener {
+    fd: RawFd,
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl IntoRawFd for Listener {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+/// Create a stream socket that is closed on exec.
+fn new_socket(family: AddressFamily) -> Result<RawFd> {
+    // SOCK_CLOEXEC flag is Linux specific
+    #[cfg(target_os = "linux")]
+    const SOCK_CLOEXEC: SockFlag = SockFlag::SOCK_CLOEXEC;
+
+    #[cfg(not(target_os = "linux"))]
+    const SOCK_CLOEXEC: SockFlag = SockFlag::empty();
+
+    let fd = socket::socket(family, SockType::Stream, SOCK_CLOEXEC, None)?;
+
+    // MacOS doesn't support atomic creation of a socket descriptor with `SOCK_CLOEXEC` flag,
+    // so there is a chance of leak if fork + exec happens in between of these calls.
+    #[cfg(not(target_os = "linux"))]
+    {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(|e| {
+            let _ = close(fd);
+            e
+        })?;
+    }
+
+    Ok(fd)
+}
+
+fn connect_to<A: SockaddrLike>(family: AddressFamily, addr: &A) -> Result<RawFd> {
+    let fd = new_socket(family)?;
+    socket::connect(fd, addr).map_err(|e| {
+        let _ = close(fd);
+        e
+    })?;
+    Ok(fd)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to<A: SockaddrLike>(family: AddressFamily, addr: &A) -> Result<Listener> {
+    let listener = Listener {
+        fd: new_socket(family)?,
+    };
+    socket::bind(listener.fd, addr)?;
+    socket::listen(listener.fd, LISTEN_BACKLOG)?;
+    Ok(listener)
+}
+
+/// Connect to `address`, returning the connected socket.
+pub fn connect(address: impl AsRef<str>) -> Result<RawFd> {
+    match SocketAddress::parse(address.as_ref())? {
+        SocketAddress::Unix(path) => connect_to(AddressFamily::Unix, &UnixAddr::new(&path)?),
+        #[cfg(target_os = "linux")]
+        SocketAddress::Abstract(name) => connect_to(
+            AddressFamily::Unix,
+            &UnixAddr::new_abstract(name.as_bytes())?,
+        ),
+        #[cfg(target_os = "linux")]
+        SocketAddress::Vsock { cid, port } => {
+            connect_to(AddressFamily::Vsock, &socket::VsockAddr::new(cid, port))
+        }
+        #[cfg(not(target_os = "linux"))]
+        addr => Err(other!("{} is only supported on Linux", addr)),
+    }
+}
+
+/// Convert the error of a socket call to an [std::io::Error], keeping its errno.
+fn to_io_error(e: Error) -> std::io::Error {
+    match e {
+        Error::Nix(errno) => errno.into(),
+        e => std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()),
+    }
+}
+
+/// Listen on `address`, creating the parent directories of a unix socket path.
+pub fn listen(address: &str) -> std::io::Result<Listener> {
+    match SocketAddress::parse(address).map_err(to_io_error)? {
+        SocketAddress::Unix(path) => {
+            // Try to create the needed directory hierarchy.
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let fd = UnixListener::bind(path)?.into_raw_fd();
+            Ok(Listener { fd })
+        }
+        #[cfg(target_os = "linux")]
+        SocketAddress::Abstract(name) => {
+            let addr = UnixAddr::new_abstract(name.as_bytes())?;
+            bind_to(AddressFamily::Unix, &addr).map_err(to_io_error)
+        }
+        #[cfg(target_os = "linux")]
+        SocketAddress::Vsock { cid, port } => {
+            bind_to(AddressFamily::Vsock, &socket::VsockAddr::new(cid, port)).map_err(to_io_error)
+        }
+        #[cfg(not(target_os = "linux"))]
+        addr => Err(to_io_error(other!("{} is only supported on Linux", addr))),
+    }
+}
+
+/// Whether `fd` is a vsock socket.
+#[cfg(target_os = "linux")]
+pub fn is_vsock(fd: RawFd) -> bool {
+    socket::getsockname::<socket::SockaddrStorage>(fd)
+        .ok()
+        .and_then(|addr| addr.family())
+        == Some(AddressFamily::Vsock)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    use nix::{
+        sys::socket::{accept, getsockname, VsockAddr},
+        unistd::{close, read, write},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        let cases = vec![
+            (
+                "unix:///run/containerd/s/1",
+                SocketAddress::Unix("/run/containerd/s/1".into()),
+            ),
+            (
+                "/run/shim.sock",
+                SocketAddress::Unix("/run/shim.sock".into()),
+            ),
+            ("unix://@shim", SocketAddress::Abstract("shim".to_string())),
+            (
+                "vsock://3:1024",
+                SocketAddress::Vsock { cid: 3, port: 1024 },
+            ),
+        ];
+        for (address, expected) in cases {
+            let parsed = SocketAddress::parse(address).unwrap();
+            assert_eq!(parsed, expected);
+            assert_eq!(SocketAddress::parse(&parsed.to_string()).unwrap(), expected);
+        }
+        assert!(SocketAddress::parse("vsock://3").is_err());
+        assert!(SocketAddress::parse("vsock://any:1024").is_err());
+        assert_eq!(SocketAddress::parse("unix://@shim").unwrap().path(), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn echo(listener: &Listener, address: &str) {
+        let client = connect(address).unwrap();
+        let server = accept(listener.as_raw_fd()).unwrap();
+        write(client, b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(read(server, &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"ping");
+        close(server).unwrap();
+        close(client).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_socket() {
+        let address = format!("unix://@containerd-shim-test-{}", std::process::id());
+        let listener = listen(&address).unwrap();
+        assert!(!is_vsock(listener.as_raw_fd()));
+        let err = listen(&address).expect_err("socket should already in use");
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+        echo(&listener, &address);
+    }
+
+    /// Needs the vsock_loopback module, run with `--ignored` where it is loaded.
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn test_vsock() {
+        // listen on any cid and connect to the local cid through vsock_loopback
+        let listener = listen(&format!(
+            "vsock://{}:{}",
+            libc::VMADDR_CID_ANY,
+            libc::VMADDR_PORT_ANY
+        ))
+        .unwrap();
+        assert!(is_vsock(listener.as_raw_fd()));
+        let port = getsockname::<VsockAddr>(listener.as_raw_fd())
+            .unwrap()
+            .port();
+        let address = format!("vsock://{}:{}", libc::VMADDR_CID_LOCAL, port);
+        close(connect(&address).unwrap()).unwrap();
+        accept(listener.as_raw_fd()).map(close).unwrap().unwrap();
+        echo(&listener, &address);
+    }
+}
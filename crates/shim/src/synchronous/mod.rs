@@ -37,7 +37,6 @@ use std::{
     env, fs,
     io::Write,
     os::unix::{fs::FileTypeExt, io::AsRawFd},
-    path::Path,
     process::{self, Command, Stdio},
     sync::{Arc, Condvar, Mutex},
 };
@@ -58,14 +57,21 @@ use util::{read_address, write_address};
 
 use crate::{
     api::DeleteResponse,
-    args, logger, parse_sockaddr,
+    args,
+    bootstrap::{start_response, BootstrapParams},
+    event::CLOSE_TIMEOUT,
+    info, logger,
     protos::{
-        protobuf::Message,
+        protobuf::{well_known_types::any::Any, Message},
         shim::shim_ttrpc::{create_task, Task},
-        ttrpc::{Client, Server},
+        ttrpc::Server,
+        types::introspection::RuntimeInfo,
     },
-    reap, socket_address, start_listener,
+    reap,
+    socket::SocketAddress,
+    socket_address, start_listener,
     synchronous::publisher::RemotePublisher,
+    util::{connect, read_stdin},
     Config, Error, Result, StartOpts, SOCKET_FD, TTRPC_ADDRESS,
 };
 
@@ -116,10 +122,19 @@ pub trait Shim {
     /// - `config`: for the shim to pass back configuration information
     fn new(runtime_id: &str, id: &str, namespace: &str, config: &mut Config) -> Self;
 
+    /// Runtime info of the shim, printed as protobuf by `-info` and as text by `-v`.
+    ///
+    /// `options` are the runtime options containerd passes to `-info`, if any. The default
+    /// info has the runtime id, the version of this crate and the options.
+    fn info(runtime_id: &str, options: Option<Any>) -> Result<RuntimeInfo> {
+        Ok(info::default_info(runtime_id, options))
+    }
+
     /// Start shim will be called by containerd when launching new shim instance.
     ///
     /// It expected to return TTRPC address containerd daemon can use to communicate with
-    /// the given shim instance.
+    /// the given shim instance. The address is written as is for older containerd, and in the
+    /// negotiated [BootstrapParams] for containerd 2.0.
     ///
     /// See <https://github.com/containerd/containerd/tree/master/runtime/v2#start>
     fn start_shim(&mut self, opts: StartOpts) -> Result<String>;
@@ -153,6 +168,24 @@ where
     let os_args: Vec<_> = env::args_os().collect();
     let flags = args::parse(&os_args[1..])?;
 
+    if flags.version {
+        let info = T::info(runtime_id, None)?;
+        let argv0 = os_args[0].to_string_lossy();
+        std::io::stdout()
+            .lock()
+            .write_all(info::version_output(&argv0, &info).as_bytes())
+            .map_err(io_error!(e, "write stdout"))?;
+        return Ok(());
+    }
+
+    if flags.info {
+        let info = T::info(runtime_id, info::read_options(&read_stdin()?)?)?;
+        let stdout = std::io::stdout();
+        let mut locked = stdout.lock();
+        info.write_to_writer(&mut locked)?;
+        return Ok(());
+    }
+
     let ttrpc_address = env::var(TTRPC_ADDRESS)?;
 
     // Create shim instance
@@ -176,13 +209,16 @@ where
                 ttrpc_address,
                 namespace: flags.namespace,
                 debug: flags.debug,
+                bootstrap: BootstrapParams::parse(&read_stdin()?),
             };
 
+            let params = args.bootstrap.clone();
             let address = shim.start_shim(args)?;
+            let response = start_response(params.as_ref(), &address)?;
 
             std::io::stdout()
                 .lock()
-                .write_fmt(format_args!("{}", address))
+                .write_fmt(format_args!("{}", response))
                 .map_err(io_error!(e, "write stdout"))?;
 
             Ok(())
@@ -202,6 +238,7 @@ where
             }
 
             let publisher = publisher::RemotePublisher::new(&ttrpc_address)?;
+            let closer = publisher.closer();
             let task = shim.create_task_service(publisher);
             let task_service = create_task(Arc::new(Box::new(task)));
             let mut server = Server::new().register_service(task_service);
@@ -214,6 +251,7 @@ where
 
             info!("Shutting down shim instance");
             server.shutdown();
+            closer.close(CLOSE_TIMEOUT);
 
             // NOTE: If the shim server is down(like oom killer), the address
             // socket might be leaking.
@@ -276,8 +314,9 @@ fn handle_signals(mut signals: Signals) {
 
 fn wait_socket_working(address: &str, interval_in_ms: u64, count: u32) -> Result<()> {
     for _i in 0..count {
-        match Client::connect(address) {
-            Ok(_) => {
+        match connect(address) {
+            Ok(fd) => {
+                let _ = nix::unistd::close(fd);
                 return Ok(());
             }
             Err(_) => {
@@ -293,8 +332,13 @@ fn remove_socket_silently(address: &str) {
 }
 
 fn remove_socket(address: &str) -> Result<()> {
-    let path = parse_sockaddr(address);
-    if let Ok(md) = Path::new(path).metadata() {
+    let addr = SocketAddress::parse(address)?;
+    let path = match addr.path() {
+        Some(path) => path,
+        // abstract sockets and vsock have no file to remove
+        None => return Ok(()),
+    };
+    if let Ok(md) = path.metadata() {
         if md.file_type().is_socket() {
             fs::remove_file(path).map_err(io_error!(e, "remove socket"))?;
         }
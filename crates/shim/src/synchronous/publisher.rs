@@ -16,6 +16,12 @@
 
 //! Implements a client to publish events from the shim back to containerd.
 
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
 use client::{
     protobuf::MessageDyn,
     shim::events,
@@ -24,15 +30,109 @@ use client::{
     Client, Events, EventsClient,
 };
 use containerd_shim_protos as client;
+use log::warn;
 
 use crate::{
-    error::Result,
+    error::{Error, Result},
+    event::{
+        log_undelivered, next_backoff, EventQueue, CLOSE_TIMEOUT, EVENT_QUEUE_CAPACITY,
+        RECONNECT_BACKOFF_MIN,
+    },
     util::{connect, convert_to_any, timestamp},
 };
 
 /// Remote publisher connects to containerd's TTRPC endpoint to publish events from shim.
+///
+/// Events are queued and forwarded in order by a background thread, which reconnects with
+/// backoff when the connection drops and retries the event that failed, so events are
+/// delivered at least once. See [EventQueue] for what is dropped when too many events are
+/// pending.
+///
+/// Dropping the publisher waits a few seconds for the pending events to be delivered.
 pub struct RemotePublisher {
-    client: EventsClient,
+    inner: Arc<Inner>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct State {
+    queue: EventQueue,
+    closed: bool,
+    done: bool,
+}
+
+struct Inner {
+    address: String,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl Inner {
+    /// Wait for the next event to deliver, `None` when the publisher is closed and all the
+    /// events are delivered.
+    fn next(&self) -> Option<(Context, events::ForwardRequest)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(event) = state.queue.pop() {
+                return Some(event);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    fn closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Sleep for `backoff`, returning early when the publisher is closed.
+    fn sleep(&self, backoff: Duration) {
+        let deadline = Instant::now() + backoff;
+        let mut state = self.state.lock().unwrap();
+        while !state.closed {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.cond.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    fn delivered(&self) {
+        self.state.lock().unwrap().queue.delivered();
+    }
+
+    /// Close the publisher and wait up to `timeout` for the pending events to be delivered,
+    /// dropping the rest. Return whether all the events were handled.
+    fn close(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.cond.notify_all();
+        while !state.done {
+            let now = Instant::now();
+            if now >= deadline {
+                let reason = format!("not delivered in {:?} before the shim exits", timeout);
+                state.queue.drop_pending(&reason);
+                return false;
+            }
+            state = self.cond.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+/// Closes a [RemotePublisher] that was handed over to the task service.
+pub(crate) struct Closer {
+    inner: Arc<Inner>,
+}
+
+impl Closer {
+    /// Wait up to `timeout` for the pending events to be delivered, dropping the rest.
+    pub(crate) fn close(&self, timeout: Duration) {
+        self.inner.close(timeout);
+    }
 }
 
 impl RemotePublisher {
@@ -40,22 +140,95 @@ impl RemotePublisher {
     ///
     /// containerd uses `/run/containerd/containerd.sock.ttrpc` by default
     pub fn new(address: impl AsRef<str>) -> Result<RemotePublisher> {
-        let client = Self::connect(address)?;
+        let client = Self::connect(address.as_ref())?;
+        let inner = Arc::new(Inner {
+            address: address.as_ref().to_string(),
+            state: Mutex::new(State {
+                queue: EventQueue::new(EVENT_QUEUE_CAPACITY),
+                closed: false,
+                done: false,
+            }),
+            cond: Condvar::new(),
+        });
+
+        let thread = {
+            let inner = inner.clone();
+            thread::Builder::new()
+                .name("shim-publisher".to_string())
+                .spawn(move || Self::run(&inner, EventsClient::new(client)))
+                .map_err(io_error!(e, "spawn publisher"))?
+        };
 
         Ok(RemotePublisher {
-            client: EventsClient::new(client),
+            inner,
+            thread: Some(thread),
         })
     }
 
+    pub(crate) fn closer(&self) -> Closer {
+        Closer {
+            inner: self.inner.clone(),
+        }
+    }
+
     fn connect(address: impl AsRef<str>) -> Result<Client> {
         let fd = connect(address)?;
         // Client::new() takes ownership of the RawFd.
         Ok(Client::new(fd))
     }
 
+    /// Forward the queued events until the publisher is closed, reconnecting when a delivery
+    /// fails. Once closed, each remaining event is tried once.
+    fn run(inner: &Inner, client: EventsClient) {
+        let mut client = Some(client);
+        let mut backoff = RECONNECT_BACKOFF_MIN;
+        let mut retry = None;
+        while let Some((ctx, req)) = retry.take().or_else(|| inner.next()) {
+            let result = match client.take() {
+                Some(c) => Ok(c),
+                None => Self::connect(&inner.address).map(EventsClient::new),
+            }
+            .and_then(|c| {
+                c.forward(ctx.clone(), &req)?;
+                Ok(c)
+            });
+            match result {
+                Ok(c) => {
+                    inner.delivered();
+                    client = Some(c);
+                    backoff = RECONNECT_BACKOFF_MIN;
+                }
+                Err(e) if inner.closed() => {
+                    inner.delivered();
+                    log_undelivered(&req, &format!("publisher is closed: {}", e));
+                }
+                Err(e) => {
+                    warn!(
+                        "publish {} to containerd: {}, retry in {:?}",
+                        req.envelope().topic(),
+                        e,
+                        backoff
+                    );
+                    inner.sleep(backoff);
+                    backoff = next_backoff(backoff);
+                    retry = Some((ctx, req));
+                }
+            }
+        }
+        inner.state.lock().unwrap().done = true;
+        inner.cond.notify_all();
+    }
+
+    fn enqueue(&self, ctx: Context, req: events::ForwardRequest) {
+        self.inner.state.lock().unwrap().queue.push(ctx, req);
+        self.inner.cond.notify_all();
+    }
+
     /// Publish a new event.
     ///
     /// Event object can be anything that Protobuf able serialize (e.g. implement `Message` trait).
+    /// The event is queued and this returns before it is delivered. `ctx` applies to each
+    /// delivery attempt, with a default timeout when it has none.
     pub fn publish(
         &self,
         ctx: Context,
@@ -72,19 +245,31 @@ impl RemotePublisher {
         let mut req = events::ForwardRequest::new();
         req.set_envelope(envelope);
 
-        self.client.forward(ctx, &req)?;
+        self.enqueue(ctx, req);
 
         Ok(())
     }
 }
 
+impl Drop for RemotePublisher {
+    /// Deliver the pending events before returning, trying each once.
+    fn drop(&mut self) {
+        if self.inner.close(CLOSE_TIMEOUT) {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
 impl Events for RemotePublisher {
     fn forward(
         &self,
         _ctx: &ttrpc::TtrpcContext,
         req: events::ForwardRequest,
     ) -> ttrpc::Result<empty::Empty> {
-        self.client.forward(Context::default(), &req)
+        self.enqueue(Context::default(), req);
+        Ok(empty::Empty::new())
     }
 }
 
@@ -92,12 +277,16 @@ impl Events for RemotePublisher {
 mod tests {
     use std::{
         os::unix::{io::AsRawFd, net::UnixListener},
-        sync::{Arc, Barrier},
+        sync::{
+            mpsc::{channel, Sender},
+            Arc, Barrier,
+        },
+        time::Duration,
     };
 
     use client::{
         api::{Empty, ForwardRequest},
-        events::task::TaskOOM,
+        events::task::{TaskExit, TaskOOM},
     };
     use ttrpc::Server;
 
@@ -153,4 +342,125 @@ mod tests {
 
         thread.join().unwrap();
     }
+
+    struct RecordingServer {
+        tx: Mutex<Sender<String>>,
+    }
+
+    impl Events for RecordingServer {
+        fn forward(&self, _ctx: &ttrpc::TtrpcContext, req: ForwardRequest) -> ttrpc::Result<Empty> {
+            let env = req.envelope();
+            let event = format!("{} {}", env.namespace(), env.topic());
+            self.tx.lock().unwrap().send(event).unwrap();
+            Ok(Empty::default())
+        }
+    }
+
+    fn start_server(path: &str, tx: Sender<String>) -> Server {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let t = Arc::new(
+            Box::new(RecordingServer { tx: Mutex::new(tx) }) as Box<dyn Events + Send + Sync>
+        );
+        let service = client::create_events(t);
+        let mut server = Server::new()
+            .add_listener(listener.as_raw_fd())
+            .unwrap()
+            .register_service(service);
+        std::mem::forget(listener);
+        server.start().unwrap();
+        server
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = format!("{}/socket", tmpdir.as_ref().to_str().unwrap());
+        let (tx, rx) = channel();
+        let recv = || rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let server = start_server(&path, tx.clone());
+        let publisher = RemotePublisher::new(&path).unwrap();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/oom",
+                "ns1",
+                Box::new(TaskOOM::new()),
+            )
+            .unwrap();
+        assert_eq!(recv(), "ns1 /tasks/oom");
+
+        // events published while containerd is down are delivered in order once it is back
+        server.shutdown();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/oom",
+                "ns2",
+                Box::new(TaskOOM::new()),
+            )
+            .unwrap();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/exit",
+                "ns3",
+                Box::new(TaskExit::new()),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+
+        let server = start_server(&path, tx);
+        assert_eq!(recv(), "ns2 /tasks/oom");
+        assert_eq!(recv(), "ns3 /tasks/exit");
+
+        drop(publisher);
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_close() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = format!("{}/socket", tmpdir.as_ref().to_str().unwrap());
+        let (tx, rx) = channel();
+
+        // the pending events are delivered before close returns
+        let server = start_server(&path, tx);
+        let publisher = RemotePublisher::new(&path).unwrap();
+        let closer = publisher.closer();
+        for ns in ["ns1", "ns2"] {
+            publisher
+                .publish(
+                    Context::default(),
+                    "/tasks/exit",
+                    ns,
+                    Box::new(TaskExit::new()),
+                )
+                .unwrap();
+        }
+        closer.close(CLOSE_TIMEOUT);
+        assert_eq!(rx.try_recv().unwrap(), "ns1 /tasks/exit");
+        assert_eq!(rx.try_recv().unwrap(), "ns2 /tasks/exit");
+        drop(publisher);
+
+        // and dropped when containerd is gone
+        let publisher = RemotePublisher::new(&path).unwrap();
+        let closer = publisher.closer();
+        server.shutdown();
+        publisher
+            .publish(
+                Context::default(),
+                "/tasks/exit",
+                "ns3",
+                Box::new(TaskExit::new()),
+            )
+            .unwrap();
+        let start = Instant::now();
+        closer.close(Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(closer.inner.state.lock().unwrap().queue.len(), 0);
+        assert!(rx.try_recv().is_err());
+    }
 }
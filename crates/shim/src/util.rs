@@ -15,26 +15,50 @@
 */
 
 use std::{
-    os::unix::io::RawFd,
+    io::Read,
+    sync::Mutex,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
 #[cfg(feature = "async")]
 pub use crate::asynchronous::util::*;
+pub use crate::socket::connect;
 #[cfg(not(feature = "async"))]
 pub use crate::synchronous::util::*;
 use crate::{
     api::Options,
-    error::Result,
+    error::{Error, Result},
     protos::protobuf::{
         well_known_types::{any::Any, timestamp::Timestamp},
         MessageDyn,
     },
 };
 
+lazy_static! {
+    static ref STDIN: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+}
+
+/// Read all of stdin, returning the same data on later calls.
+///
+/// The stdin of `start` holds either the bootstrap params or the runtime options, which are
+/// read in different places.
+pub fn read_stdin() -> Result<Vec<u8>> {
+    let mut stdin = STDIN.lock().unwrap();
+    if let Some(data) = stdin.as_ref() {
+        return Ok(data.clone());
+    }
+    let mut data = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut data)
+        .map_err(io_error!(e, "read stdin"))?;
+    *stdin = Some(data.clone());
+    Ok(data)
+}
+
 pub const CONFIG_FILE_NAME: &str = "config.json";
 pub const OPTIONS_FILE_NAME: &str = "options.json";
 pub const RUNTIME_FILE_NAME: &str = "runtime";
@@ -100,39 +124,6 @@ impl From<JsonOptions> for Options {
     }
 }
 
-pub fn connect(address: impl AsRef<str>) -> Result<RawFd> {
-    use nix::{sys::socket::*, unistd::close};
-
-    let unix_addr = UnixAddr::new(address.as_ref())?;
-
-    // SOCK_CLOEXEC flag is Linux specific
-    #[cfg(target_os = "linux")]
-    const SOCK_CLOEXEC: SockFlag = SockFlag::SOCK_CLOEXEC;
-
-    #[cfg(not(target_os = "linux"))]
-    const SOCK_CLOEXEC: SockFlag = SockFlag::empty();
-
-    let fd = socket(AddressFamily::Unix, SockType::Stream, SOCK_CLOEXEC, None)?;
-
-    // MacOS doesn't support atomic creation of a socket descriptor with `SOCK_CLOEXEC` flag,
-    // so there is a chance of leak if fork + exec happens in between of these calls.
-    #[cfg(not(target_os = "linux"))]
-    {
-        use nix::fcntl::{fcntl, FcntlArg, FdFlag};
-        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(|e| {
-            let _ = close(fd);
-            e
-        })?;
-    }
-
-    connect(fd, &unix_addr).map_err(|e| {
-        let _ = close(fd);
-        e
-    })?;
-
-    Ok(fd)
-}
-
 pub fn timestamp() -> Result<Timestamp> {
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
 